@@ -1,10 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use neighbor::bin_packing::search_locations;
-use neighbor::model::{AllListings, Vehicle};
+use neighbor::model::Vehicle;
+use neighbor::store::{self, LISTINGS_FILE};
 
 fn bench_api_search(c: &mut Criterion) {
-    let listings = AllListings::get();
-    let listings_slice = listings.inner();
+    let listings = store::read_listings(LISTINGS_FILE).unwrap();
+    let listings_slice = listings.as_slice();
 
     let mut group = c.benchmark_group("api_search");
 
@@ -0,0 +1,63 @@
+//! # Admin Authentication
+//!
+//! Requests that change the catalog or the server (listing edits and
+//! everything under `/admin`) need `Authorization: Bearer <admin_token>`.
+//! Without an `admin_token` in the configuration those endpoints are off.
+
+use std::fmt;
+
+pub const AUTHORIZATION: &str = "Authorization";
+/// Shorter tokens are refused at startup, they are too easy to guess
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// No token is configured, so nobody can use the endpoint
+    Disabled,
+    /// The request didn't send a bearer token
+    Missing,
+    /// The request sent the wrong token
+    Invalid,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "This endpoint is off, set admin_token to use it"),
+            Self::Missing => write!(f, "Send the admin token as {AUTHORIZATION}: Bearer <token>"),
+            Self::Invalid => write!(f, "The admin token is not valid"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdminToken(Option<String>);
+
+impl AdminToken {
+    pub fn new(token: Option<String>) -> Self {
+        Self(token.filter(|token| !token.is_empty()))
+    }
+
+    /// Check the value of an `Authorization` header
+    pub fn check(&self, authorization: Option<&str>) -> Result<(), AuthError> {
+        let Some(expected) = &self.0 else {
+            return Err(AuthError::Disabled);
+        };
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::Missing)?;
+        if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err(AuthError::Invalid)
+        }
+    }
+}
+
+/// Compare without stopping at the first difference, so timing doesn't give the token away
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,77 @@
+//! # Availability
+//!
+//! Listings can carry availability windows. A listing without any windows is
+//! available forever, which is how the original `listings.json` behaves.
+//!
+//! Ranges are half-open: `start_date` is the first day, `end_date` is the day
+//! the space is handed back, so `2025-01-01..2025-01-31` is 30 days.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::ValidationError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DateRange {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+}
+
+impl DateRange {
+    pub fn new(start_date: NaiveDate, end_date: NaiveDate) -> Self {
+        Self { start_date, end_date }
+    }
+
+    /// Number of days in the range
+    pub fn days(&self) -> i64 {
+        (self.end_date - self.start_date).num_days()
+    }
+
+    pub fn overlaps(&self, other: &DateRange) -> bool {
+        self.start_date < other.end_date && other.start_date < self.end_date
+    }
+
+    pub fn contains(&self, other: &DateRange) -> bool {
+        self.start_date <= other.start_date && other.end_date <= self.end_date
+    }
+}
+
+/// Longest period that can be searched or booked, about ten years
+pub const MAX_DAYS: i64 = 3660;
+
+pub fn validate_date_range(range: &DateRange) -> Result<(), ValidationError> {
+    if range.end_date <= range.start_date {
+        return Err(ValidationError::new("end_date_must_be_after_start_date"));
+    }
+    if range.days() > MAX_DAYS {
+        return Err(ValidationError::new("period_too_long"));
+    }
+    Ok(())
+}
+
+/// Check if the windows cover every day of the period.
+/// Back to back windows are treated as one, so a listing open for
+/// January and then for February can be rented across the month boundary.
+pub fn covers(windows: &[DateRange], period: &DateRange) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+
+    let mut sorted = windows.to_vec();
+    sorted.sort_by_key(|w| w.start_date);
+
+    let mut covered_until = period.start_date;
+    for window in sorted {
+        if window.start_date > covered_until {
+            break;
+        }
+        if window.end_date > covered_until {
+            covered_until = window.end_date;
+        }
+        if covered_until >= period.end_date {
+            return true;
+        }
+    }
+
+    false
+}
@@ -5,28 +5,106 @@
 //!
 //! For more information, see: https://en.wikipedia.org/wiki/Bin_packing_problem
 
+use crate::availability::DateRange;
+use crate::discounts::AppliedDiscount;
+use crate::fx::FxTable;
+use crate::locations::Locations;
+use crate::money::{Currency, Money, MoneyError};
+use crate::quote::Charges;
 use crate::model::{Listing, PossibleSpace, Vehicle};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Extra constraints for a search. The default is an open ended search
+/// that prices listings at their listed `price_in_cents`.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Only listings available for the whole period are eligible,
+    /// and prices are quoted for its duration
+    pub period: Option<DateRange>,
+    /// Listings that are already booked
+    pub excluded_listing_ids: HashSet<String>,
+    /// Location rules such as bundle discounts
+    pub locations: Arc<Locations>,
+    /// Rank by what the renter pays, service fee and tax included
+    pub include_fees_and_taxes: bool,
+    /// Prices are converted to this currency before they are compared
+    pub currency: Currency,
+    pub fx: Arc<FxTable>,
+    /// Combinations with more listings than this aren't tried
+    pub max_listings: Option<usize>,
+    /// Work done by the searches that share these options
+    pub stats: Arc<SearchStats>,
+}
+
+/// Counters for the metrics, kept apart from the results
+#[derive(Debug, Default)]
+pub struct SearchStats {
+    subsets: AtomicU64,
+    unpriced: AtomicU64,
+}
+
+impl SearchStats {
+    /// Combinations of listings tried so far
+    pub fn subsets(&self) -> u64 {
+        self.subsets.load(Ordering::Relaxed)
+    }
+
+    /// Eligible listings left out because they couldn't be priced
+    pub fn unpriced(&self) -> u64 {
+        self.unpriced.load(Ordering::Relaxed)
+    }
+}
+
+impl SearchOptions {
+    fn is_eligible(&self, listing: &Listing) -> bool {
+        if self.excluded_listing_ids.contains(&listing.id) {
+            return false;
+        }
+        match &self.period {
+            Some(period) => listing.is_available_for(period),
+            None => true,
+        }
+    }
+
+    fn price_of(&self, listing: &Listing) -> Result<Money, MoneyError> {
+        let price = match &self.period {
+            Some(period) => listing.price_for_period(period)?,
+            None => listing.price,
+        };
+        self.fx.convert(price, self.currency)
+    }
+}
 
 /// Main search function that finds all possible locations for the given vehicles
 pub fn search_locations(vehicles: Vec<Vehicle>, listings: &[Listing]) -> Vec<PossibleSpace> {
+    search_locations_with(vehicles, listings, &SearchOptions::default())
+}
+
+/// Same as `search_locations` but restricted by the search options
+pub fn search_locations_with(
+    vehicles: Vec<Vehicle>,
+    listings: &[Listing],
+    options: &SearchOptions,
+) -> Vec<PossibleSpace> {
+    search_index(vehicles, &LocationIndex::new(listings, options), options)
+}
+
+/// Search listings already grouped by `LocationIndex::new` with the same options
+pub fn search_index(vehicles: Vec<Vehicle>, index: &LocationIndex, options: &SearchOptions) -> Vec<PossibleSpace> {
     let expanded_vehicles = expand_vehicles(vehicles);
+    let _span = tracing::info_span!("solve", vehicles = expanded_vehicles.len(), locations = index.len()).entered();
 
     if expanded_vehicles.is_empty() {
         return Vec::new();
     }
 
-    let grouped = group_by_location(listings);
     let mut results = Vec::new();
 
-    for (location_id, location_listings) in grouped {
-        if let Some(CheapestCombo { listing_ids, total_price_in_cents }) =
-            find_cheapest_combination(&expanded_vehicles, &location_listings) {
-            results.push(PossibleSpace {
-                location_id,
-                listing_ids,
-                total_price_in_cents,
-            });
+    for location in &index.locations {
+        if let Some(result) = search_location(&expanded_vehicles, location, options) {
+            results.push(result);
         }
     }
 
@@ -34,6 +112,153 @@ pub fn search_locations(vehicles: Vec<Vehicle>, listings: &[Listing]) -> Vec<Pos
     results
 }
 
+/// The first results of `search_index`, see `search_cheapest`
+pub struct Cheapest {
+    /// Cheapest first, ties in the same order as `search_index`
+    pub results: Vec<PossibleSpace>,
+    /// Every accepted result is here, none were dropped or left unsearched
+    pub complete: bool,
+}
+
+/// The `count` cheapest results that `accept` lets through, the same ones
+/// `search_index` would return first. Locations are searched from the cheapest
+/// listing up and the search stops once no location left can beat what it has.
+pub fn search_cheapest(
+    vehicles: Vec<Vehicle>,
+    index: &LocationIndex,
+    options: &SearchOptions,
+    count: usize,
+    accept: impl Fn(&PossibleSpace) -> bool,
+) -> Cheapest {
+    let expanded_vehicles = expand_vehicles(vehicles);
+    let _span = tracing::info_span!("solve", vehicles = expanded_vehicles.len(), locations = index.len(), count).entered();
+    let mut best: Vec<((i64, usize), PossibleSpace)> = Vec::new();
+    let mut complete = true;
+
+    if expanded_vehicles.is_empty() {
+        return Cheapest { results: Vec::new(), complete };
+    }
+
+    let mut order: Vec<usize> = (0..index.locations.len()).collect();
+    order.sort_by_key(|&position| index.locations[position].floor);
+
+    for position in order {
+        let location = &index.locations[position];
+        if let Some(((worst, _), _)) = best.last().filter(|_| best.len() >= count) {
+            // Every location left costs at least as much as this one
+            if location.floor > *worst {
+                complete = false;
+                break;
+            }
+        }
+        let Some(result) = search_location(&expanded_vehicles, location, options).filter(|r| accept(r)) else {
+            continue;
+        };
+        // Ties keep the location order, like the stable sort in `search_index`
+        let key = (result.total_price_in_cents, position);
+        let at = best.partition_point(|(other, _)| *other < key);
+        best.insert(at, (key, result));
+        if best.len() > count {
+            best.pop();
+            complete = false;
+        }
+    }
+
+    Cheapest {
+        results: best.into_iter().map(|(_, result)| result).collect(),
+        complete,
+    }
+}
+
+fn search_location(vehicles: &[i32], location: &IndexedLocation, options: &SearchOptions) -> Option<PossibleSpace> {
+    let _span = tracing::debug_span!(
+        "solve_location",
+        location_id = location.location_id.as_str(),
+        listings = location.listings.len()
+    )
+    .entered();
+    let CheapestCombo { listing_ids, total, discount } =
+        cheapest_at(vehicles, &location.listings, &location.prices, options)?;
+    Some(PossibleSpace {
+        location_id: location.location_id.clone(),
+        listing_ids,
+        total_price_in_cents: total.amount_in_cents,
+        currency: total.currency,
+        discount,
+    })
+}
+
+/// The eligible listings grouped by location and priced once. Building it is
+/// most of the work that doesn't depend on the vehicles, so searches that
+/// share options can share the index.
+pub struct LocationIndex<'a> {
+    locations: Vec<IndexedLocation<'a>>,
+}
+
+struct IndexedLocation<'a> {
+    location_id: String,
+    listings: Vec<&'a Listing>,
+    prices: Vec<Money>,
+    /// No combination here costs less. Fees and tax only add to the rent,
+    /// but a bundle discount can take it down to nothing.
+    floor: i64,
+}
+
+impl<'a> LocationIndex<'a> {
+    pub fn new(listings: &'a [Listing], options: &SearchOptions) -> Self {
+        let _span = tracing::info_span!("group", listings = listings.len()).entered();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        let mut locations: Vec<IndexedLocation> = Vec::new();
+        for (listing, price) in priced_listings(listings, options) {
+            let position = *positions.entry(&listing.location_id).or_insert_with(|| {
+                locations.push(IndexedLocation {
+                    location_id: listing.location_id.clone(),
+                    listings: Vec::new(),
+                    prices: Vec::new(),
+                    floor: 0,
+                });
+                locations.len() - 1
+            });
+            locations[position].listings.push(listing);
+            locations[position].prices.push(price);
+        }
+        for location in &mut locations {
+            if options.locations.discounts_for(&location.location_id).is_empty() {
+                location.floor = location.prices.iter().map(|p| p.amount_in_cents).min().unwrap_or(0);
+            }
+        }
+        Self { locations }
+    }
+
+    /// Locations with at least one eligible listing
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+/// The eligible listings with their price. Quoting a period isn't free so each
+/// listing is priced once, not once per subset. A listing that can't be priced
+/// (overflow, missing exchange rate) can't be rented, it is counted and logged.
+fn priced_listings<'a>(listings: &'a [Listing], options: &SearchOptions) -> Vec<(&'a Listing, Money)> {
+    let mut priced = Vec::new();
+    let mut unpriced: Vec<(&str, MoneyError)> = Vec::new();
+    for listing in listings.iter().filter(|l| options.is_eligible(l)) {
+        match options.price_of(listing) {
+            Ok(price) => priced.push((listing, price)),
+            Err(e) => unpriced.push((&listing.id, e)),
+        }
+    }
+    if let Some((listing_id, error)) = unpriced.first() {
+        options.stats.unpriced.fetch_add(unpriced.len() as u64, Ordering::Relaxed);
+        tracing::warn!(count = unpriced.len(), listing_id, %error, "Left out listings that can't be priced");
+    }
+    priced
+}
+
 pub fn expand_vehicles(vehicles: Vec<Vehicle>) -> Vec<i32> {
     let mut expanded = Vec::new();
     for vehicle in vehicles {
@@ -44,26 +269,17 @@ pub fn expand_vehicles(vehicles: Vec<Vehicle>) -> Vec<i32> {
     expanded
 }
 
-fn group_by_location(listings: &[Listing]) -> HashMap<String, Vec<Listing>> {
-    let mut grouped: HashMap<String, Vec<Listing>> = HashMap::new();
-    for listing in listings {
-        grouped
-            .entry(listing.location_id.clone())
-            .or_insert_with(Vec::new)
-            .push(listing.clone());
-    }
-    grouped
-}
-
 pub struct CheapestCombo {
     pub listing_ids: Vec<String>,
-    pub total_price_in_cents: i32,
+    /// After the discount
+    pub total: Money,
+    pub discount: Option<AppliedDiscount>,
 } 
 
 impl CheapestCombo {
-    fn from_listings(listings: &Vec<Listing>, total_price_in_cents: i32) -> Self {
+    fn from_listings(listings: &[Listing], total: Money, discount: Option<AppliedDiscount>) -> Self {
         let listing_ids = listings.iter().map(|l| l.id.clone()).collect();
-        Self { listing_ids, total_price_in_cents }
+        Self { listing_ids, total, discount }
     }
 }
 
@@ -72,38 +288,91 @@ pub fn find_cheapest_combination(
     vehicles: &[i32],
     listings: &[Listing],
 ) -> Option<CheapestCombo> {
-    let n = listings.len();
+    find_cheapest_combination_with(vehicles, listings, &SearchOptions::default())
+}
+
+/// Find the cheapest combination using only the listings eligible for the search
+pub fn find_cheapest_combination_with(
+    vehicles: &[i32],
+    listings: &[Listing],
+    options: &SearchOptions,
+) -> Option<CheapestCombo> {
+    let (listings, prices): (Vec<&Listing>, Vec<Money>) = priced_listings(listings, options).into_iter().unzip();
+    cheapest_at(vehicles, &listings, &prices, options)
+}
+
+/// Try every combination of one location's priced listings
+fn cheapest_at(vehicles: &[i32], listings: &[&Listing], prices: &[Money], options: &SearchOptions) -> Option<CheapestCombo> {
+    let location = match listings.first() {
+        Some(listing) => options.locations.settings_for(&listing.location_id),
+        None => return None,
+    };
     let mut best: Option<CheapestCombo> = None;
+    // Ties go to the lowest mask, as they did when the masks were tried in order
+    let mut best_mask = u32::MAX;
+    let mut tried = 0;
 
-    // Use a powerset to try all the different combos
-    // More info: https://www.geeksforgeeks.org/dsa/power-set/
-    for mask in 1..(1 << n) {
+    for mask in combinations(listings.len(), options.max_listings) {
+        tried += 1;
         let mut selected_listings = Vec::new();
-        let mut total_price = 0;
+        let mut subtotal = Ok(Money::zero(options.currency));
 
-        for i in 0..n {
+        for (i, listing) in listings.iter().enumerate() {
             if (mask & (1 << i)) != 0 {
-                selected_listings.push(listings[i].clone());
-                total_price += listings[i].price_in_cents;
+                selected_listings.push((*listing).clone());
+                subtotal = subtotal.and_then(|s| s.checked_add(prices[i]));
             }
         }
 
         if can_fit_all_vehicles(vehicles, &selected_listings) {
-            match &best {
-                Some(CheapestCombo { total_price_in_cents: best_price, .. }) if total_price < *best_price => {
-                    best = Some(CheapestCombo::from_listings(&selected_listings, total_price));
-                }
-                None => {
-                    best = Some(CheapestCombo::from_listings(&selected_listings, total_price));
+            // Too expensive to add up means too expensive to rent
+            let Ok(charges) = subtotal.and_then(|s| Charges::new(location, s, selected_listings.len(), &options.fx)) else {
+                continue;
+            };
+            let total_price = if options.include_fees_and_taxes {
+                charges.total()
+            } else {
+                charges.discounted()
+            };
+            let Ok(total_price) = total_price else {
+                continue;
+            };
+            let discount = charges.discount;
+
+            let better = match &best {
+                Some(CheapestCombo { total: best_price, .. }) => {
+                    (total_price.amount_in_cents, mask) < (best_price.amount_in_cents, best_mask)
                 }
-                _ => {}
+                None => true,
+            };
+            if better {
+                best = Some(CheapestCombo::from_listings(&selected_listings, total_price, discount));
+                best_mask = mask;
             }
         }
     }
 
+    options.stats.subsets.fetch_add(tried, Ordering::Relaxed);
     best
 }
 
+/// Every non-empty combination of `n` listings as a bit mask, fewest listings first.
+/// With a `max`, larger combinations are never generated rather than skipped.
+pub fn combinations(n: usize, max: Option<usize>) -> impl Iterator<Item = u32> {
+    let end = 1u64 << n;
+    let max = max.map_or(n, |max| max.min(n));
+    // Gosper's hack (HAKMEM item 175) walks the masks with k bits set in increasing order
+    (1..=max).flat_map(move |k| {
+        let next = |&mask: &u64| {
+            let lowest = mask & mask.wrapping_neg();
+            let ripple = mask + lowest;
+            Some((((ripple ^ mask) >> 2) / lowest) | ripple)
+        };
+        std::iter::successors(Some((1u64 << k) - 1), next).take_while(move |&mask| mask < end)
+    })
+    .map(|mask| mask as u32)
+}
+
 /// Check if all vehicles can fit in the given listings
 pub fn can_fit_all_vehicles(vehicles: &[i32], listings: &[Listing]) -> bool {
     let mut assignment = vec![None; vehicles.len()];
@@ -123,7 +392,7 @@ pub fn can_fit_all_vehicles(vehicles: &[i32], listings: &[Listing]) -> bool {
         for (idx, listing) in listings.iter().enumerate() {
             if can_add_vehicle_to_listing(
                 vehicles,
-                &listing,
+                listing,
                 assignment,
                 idx,
                 vehicle_length,
@@ -0,0 +1,307 @@
+//! # Bookings
+//!
+//! Reserve a combination returned by `/search`. Creating a booking re-checks
+//! everything the search assumed (the listings exist at the location, they are
+//! available, the vehicles fit and nobody else booked them) while holding the
+//! store lock, so two overlapping bookings of a listing can't both succeed.
+//!
+//! Creation is optimistic: conflicts are checked against a snapshot of the
+//! per-listing versions, and the commit only goes through if none of the
+//! listings changed in the meantime. Otherwise it retries with fresh data.
+//!
+//! Deleting a listing goes through the ledger too: `unless_booked` holds it
+//! while the listing is checked and deleted, and a booking checks the listings
+//! still exist before it commits, so a booking can't land on a deleted listing.
+//!
+//! Bookings live in memory. A `BookingLog` can persist them, it is written
+//! before a change becomes visible, so a booking that couldn't be saved never happens.
+
+use crate::availability::{validate_date_range, DateRange};
+use crate::bin_packing;
+use crate::fx::FxTable;
+use crate::locations::Locations;
+use crate::model::{validate_total_quantity, Limits, Vehicle};
+use crate::money::Currency;
+use crate::quote::{self, Quote, QuoteError};
+use crate::store::ListingStore;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(context = Limits)]
+pub struct BookingRequest {
+    #[validate(length(min = 1))]
+    #[schema(min_length = 1)]
+    pub location_id: String,
+    #[validate(length(min = 1))]
+    #[schema(min_items = 1)]
+    pub listing_ids: Vec<String>,
+    /// At most `max_vehicles` in total (5 by default)
+    #[validate(length(min = 1), nested, custom(function = "validate_total_quantity", use_context))]
+    #[schema(min_items = 1)]
+    pub vehicles: Vec<Vehicle>,
+    #[serde(flatten)]
+    #[validate(custom(function = "validate_date_range"))]
+    pub period: DateRange,
+    /// Currency to charge in, the exchange rate base by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Booking {
+    pub id: String,
+    pub location_id: String,
+    pub listing_ids: Vec<String>,
+    pub vehicles: Vec<Vehicle>,
+    #[serde(flatten)]
+    pub period: DateRange,
+    /// What the listings cost after discounts, in the quote currency
+    pub total_price_in_cents: i64,
+    /// What the renter pays, fees and tax included, in the quote currency
+    pub total_with_fees_in_cents: i64,
+    pub quote: Quote,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookingError {
+    /// The listings can't be quoted: missing, elsewhere, unavailable or unpriceable
+    Quote(QuoteError),
+    AlreadyBooked { listing_id: String, booking_id: String },
+    VehiclesDoNotFit,
+    /// Gave up after the listings kept changing under us
+    Contention,
+    /// The booking log couldn't save the change
+    Storage(String),
+}
+
+impl fmt::Display for BookingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quote(e) => write!(f, "{e}"),
+            Self::AlreadyBooked { listing_id, booking_id } => {
+                write!(f, "Listing {listing_id} is already booked by {booking_id}")
+            }
+            Self::VehiclesDoNotFit => write!(f, "The vehicles do not fit in the listings"),
+            Self::Contention => write!(f, "The listings are being booked by someone else, try again"),
+            Self::Storage(e) => write!(f, "Could not save the booking: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BookingError {}
+
+impl From<QuoteError> for BookingError {
+    fn from(error: QuoteError) -> Self {
+        Self::Quote(error)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    bookings: HashMap<String, Booking>,
+    /// Bumped every time a booking for the listing is created or cancelled
+    listing_versions: HashMap<String, u64>,
+}
+
+impl Ledger {
+    fn versions_of(&self, listing_ids: &[String]) -> Vec<u64> {
+        listing_ids.iter().map(|id| self.version_of(id)).collect()
+    }
+
+    fn version_of(&self, listing_id: &str) -> u64 {
+        self.listing_versions.get(listing_id).copied().unwrap_or(0)
+    }
+
+    fn bump(&mut self, listing_ids: &[String]) {
+        for id in listing_ids {
+            *self.listing_versions.entry(id.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Somewhere bookings are kept across restarts
+pub trait BookingLog: Send + Sync {
+    fn record(&self, booking: &Booking) -> anyhow::Result<()>;
+    fn remove(&self, id: &str) -> anyhow::Result<()>;
+}
+
+/// In memory reservations keyed by booking id
+#[derive(Default)]
+pub struct BookingStore {
+    ledger: RwLock<Ledger>,
+    log: Option<Arc<dyn BookingLog>>,
+}
+
+impl BookingStore {
+    const MAX_ATTEMPTS: usize = 5;
+
+    /// Start from bookings saved earlier, saving every change to the log
+    pub fn persistent(log: Arc<dyn BookingLog>, bookings: Vec<Booking>) -> Self {
+        let mut ledger = Ledger::default();
+        for booking in bookings {
+            ledger.bump(&booking.listing_ids);
+            ledger.bookings.insert(booking.id.clone(), booking);
+        }
+        Self {
+            ledger: RwLock::new(ledger),
+            log: Some(log),
+        }
+    }
+
+    /// Validate the request against the catalog and current bookings and store it
+    pub fn create(
+        &self,
+        request: BookingRequest,
+        listings: &dyn ListingStore,
+        locations: &Locations,
+        fx: &FxTable,
+    ) -> Result<Booking, BookingError> {
+        let selected = quote::select_listings(
+            &request.location_id,
+            &request.listing_ids,
+            Some(&request.period),
+            &listings.listings(),
+        )?;
+
+        let vehicles = bin_packing::expand_vehicles(request.vehicles.clone());
+        if !bin_packing::can_fit_all_vehicles(&vehicles, &selected) {
+            return Err(BookingError::VehiclesDoNotFit);
+        }
+
+        let quote = Quote::new(
+            &request.location_id,
+            &selected,
+            Some(&request.period),
+            locations.settings_for(&request.location_id),
+            fx,
+            request.currency.unwrap_or(fx.base()),
+        )
+        .map_err(QuoteError::Price)?;
+
+        let booking = Booking {
+            id: Uuid::new_v4().to_string(),
+            total_price_in_cents: quote.subtotal_in_cents - quote.discount_in_cents,
+            total_with_fees_in_cents: quote.total_in_cents,
+            quote,
+            location_id: request.location_id,
+            listing_ids: request.listing_ids,
+            vehicles: request.vehicles,
+            period: request.period,
+        };
+
+        for _ in 0..Self::MAX_ATTEMPTS {
+            let expected_versions = {
+                let ledger = self.ledger.read().unwrap();
+                let conflict = find_conflict(&ledger.bookings, &booking.listing_ids, &booking.period);
+                if let Some((listing_id, booking_id)) = conflict {
+                    return Err(BookingError::AlreadyBooked { listing_id, booking_id });
+                }
+                ledger.versions_of(&booking.listing_ids)
+            };
+
+            let mut ledger = self.ledger.write().unwrap();
+            if ledger.versions_of(&booking.listing_ids) != expected_versions {
+                // Someone booked or cancelled one of the listings since we looked
+                continue;
+            }
+            // Deletes hold the ledger, one that finished since the catalog was read shows here
+            if let Some(id) = booking.listing_ids.iter().find(|id| listings.get(id).is_none()) {
+                return Err(QuoteError::ListingNotFound(id.clone()).into());
+            }
+            if let Some(log) = &self.log {
+                log.record(&booking).map_err(|e| BookingError::Storage(e.to_string()))?;
+            }
+            ledger.bump(&booking.listing_ids);
+            ledger.bookings.insert(booking.id.clone(), booking.clone());
+            return Ok(booking);
+        }
+
+        Err(BookingError::Contention)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Booking> {
+        self.ledger.read().unwrap().bookings.get(id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Booking> {
+        let mut bookings: Vec<Booking> = self.ledger.read().unwrap().bookings.values().cloned().collect();
+        bookings.sort_by_key(|b| (b.period.start_date, b.id.clone()));
+        bookings
+    }
+
+    /// Remove a booking, freeing its listings
+    pub fn cancel(&self, id: &str) -> Result<Option<Booking>, BookingError> {
+        let mut ledger = self.ledger.write().unwrap();
+        if !ledger.bookings.contains_key(id) {
+            return Ok(None);
+        }
+        if let Some(log) = &self.log {
+            log.remove(id).map_err(|e| BookingError::Storage(e.to_string()))?;
+        }
+        let booking = ledger.bookings.remove(id);
+        if let Some(booking) = &booking {
+            ledger.bump(&booking.listing_ids);
+        }
+        Ok(booking)
+    }
+
+    /// Run `change`, such as deleting the listing, unless the listing has
+    /// bookings that haven't ended by `today`. No booking can be made while it runs.
+    pub fn unless_booked<T>(&self, listing_id: &str, today: NaiveDate, change: impl FnOnce() -> T) -> Result<T, BookingError> {
+        let ledger = self.ledger.write().unwrap();
+        let booked = ledger
+            .bookings
+            .values()
+            .find(|b| b.period.end_date > today && b.listing_ids.iter().any(|id| id == listing_id));
+        if let Some(booking) = booked {
+            return Err(BookingError::AlreadyBooked {
+                listing_id: listing_id.to_string(),
+                booking_id: booking.id.clone(),
+            });
+        }
+        let changed = change();
+        drop(ledger);
+        Ok(changed)
+    }
+
+    /// Current version of a listing, bumped on every booking change
+    pub fn listing_version(&self, listing_id: &str) -> u64 {
+        self.ledger.read().unwrap().version_of(listing_id)
+    }
+
+    /// Listings booked during the period. Without a period the search is open
+    /// ended, so every booking that hasn't ended by `today` counts.
+    pub fn booked_listing_ids(&self, period: Option<&DateRange>, today: NaiveDate) -> HashSet<String> {
+        self.ledger
+            .read()
+            .unwrap()
+            .bookings
+            .values()
+            .filter(|b| period.map_or(b.period.end_date > today, |p| b.period.overlaps(p)))
+            .flat_map(|b| b.listing_ids.iter().cloned())
+            .collect()
+    }
+}
+
+fn find_conflict(
+    bookings: &HashMap<String, Booking>,
+    listing_ids: &[String],
+    period: &DateRange,
+) -> Option<(String, String)> {
+    bookings
+        .values()
+        .filter(|b| b.period.overlaps(period))
+        .find_map(|b| {
+            listing_ids
+                .iter()
+                .find(|id| b.listing_ids.contains(id))
+                .map(|id| (id.clone(), b.id.clone()))
+        })
+}
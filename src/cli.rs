@@ -0,0 +1,396 @@
+//! # Offline Search
+//!
+//! Runs the same search as `POST /search` against a catalog file, without
+//! starting the server:
+//!
+//! ```text
+//! neighbor search --listings listings.json --vehicle 20x2 --vehicle 10
+//! neighbor search --queries queries.txt --format csv
+//! ```
+//!
+//! A vehicle is written `LENGTHxQUANTITY`, or just `LENGTH` for one. A queries
+//! file has one search per line, either vehicles separated by spaces or the
+//! JSON body `/search` takes. Blank lines and lines starting with `#` are skipped.
+//!
+//! Nothing is booked offline, so every available listing is considered.
+//!
+//! `neighbor catalog check` lints a listings file and exits non-zero if it has
+//! errors, `neighbor catalog stats` summarizes it. See `inspect` for the rules.
+
+use crate::bin_packing::{self, SearchOptions};
+use crate::config::Config;
+use crate::fx::FxTable;
+use crate::inspect;
+use crate::locations::Locations;
+use crate::model::{PossibleSpace, SearchPeriod, SearchRequest, Vehicle};
+use crate::money::{Currency, Money};
+use crate::validation::{self, LoadMode};
+use chrono::NaiveDate;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use validator::{Validate, ValidateArgs};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns for reading
+    #[default]
+    Table,
+    /// One `/search` response per line
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct SearchArgs {
+    /// Catalog to search [default: the configured listings file]
+    #[arg(long)]
+    pub listings: Option<PathBuf>,
+    /// A vehicle to park, LENGTHxQUANTITY or LENGTH, can be repeated
+    #[arg(long = "vehicle", short, value_name = "VEHICLE")]
+    pub vehicles: Vec<VehicleSpec>,
+    /// Run every search in this file instead
+    #[arg(long, conflicts_with = "vehicles")]
+    pub queries: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+    #[arg(long, requires = "end_date")]
+    pub start_date: Option<NaiveDate>,
+    #[arg(long, requires = "start_date")]
+    pub end_date: Option<NaiveDate>,
+    /// Rank and report totals with the service fee and tax included
+    #[arg(long)]
+    pub include_fees_and_taxes: bool,
+    /// Currency to compare and show prices in [default: the exchange rate base]
+    #[arg(long)]
+    pub currency: Option<Currency>,
+}
+
+/// `20x2` is two vehicles 20 feet long
+#[derive(Debug, Clone)]
+pub struct VehicleSpec(pub Vehicle);
+
+impl FromStr for VehicleSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (length, quantity) = spec.split_once(['x', 'X']).unwrap_or((spec, "1"));
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid vehicle {spec}, expected LENGTHxQUANTITY such as 20x2"))
+        };
+        Ok(Self(Vehicle {
+            length: number(length)?,
+            quantity: number(quantity)?,
+        }))
+    }
+}
+
+/// One search from the command line or a queries file
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// Line in the queries file, 1 for the command line
+    pub line: usize,
+    pub vehicles: Vec<Vehicle>,
+}
+
+/// Parse a queries file, see the module docs for the format
+pub fn parse_queries(data: &str) -> anyhow::Result<Vec<Query>> {
+    let mut queries = Vec::new();
+    for (index, text) in data.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let vehicles = if text.starts_with('[') {
+            serde_json::from_str::<SearchRequest>(text)
+                .map_err(|e| anyhow::anyhow!("Line {line}: {e}"))?
+                .into()
+        } else {
+            text.split_whitespace()
+                .map(|spec| spec.parse::<VehicleSpec>().map(|spec| spec.0))
+                .collect::<Result<_, _>>()
+                .map_err(|e| anyhow::anyhow!("Line {line}: {e}"))?
+        };
+        queries.push(Query { line, vehicles });
+    }
+    Ok(queries)
+}
+
+/// The results of one query
+#[derive(Debug)]
+pub struct Answer {
+    pub query: Query,
+    pub results: Vec<PossibleSpace>,
+}
+
+/// Run the searches and write them to `out` in the requested format
+pub fn search(args: &SearchArgs, config: &Config, out: &mut impl Write) -> anyhow::Result<()> {
+    let queries = match &args.queries {
+        Some(path) => {
+            let data = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {}: {e}", path.display()))?;
+            parse_queries(&data)?
+        }
+        None if args.vehicles.is_empty() => anyhow::bail!("Pass at least one --vehicle or a --queries file"),
+        None => vec![Query {
+            line: 1,
+            vehicles: args.vehicles.iter().map(|spec| spec.0.clone()).collect(),
+        }],
+    };
+
+    let limits = config.limits();
+    for query in &queries {
+        let request = SearchRequest {
+            vehicles: query.vehicles.clone(),
+        };
+        if let Err(e) = request.validate_with_args(&limits) {
+            match args.queries {
+                Some(_) => anyhow::bail!("Line {}: {e}", query.line),
+                None => anyhow::bail!("{e}"),
+            }
+        }
+    }
+    let period = SearchPeriod {
+        start_date: args.start_date,
+        end_date: args.end_date,
+    };
+    period.validate()?;
+
+    let listings_file = args.listings.as_ref().unwrap_or(&config.listings_file);
+    let (listings, report) = validation::load_listings(listings_file, config.load_mode)?;
+    if !report.is_clean() {
+        // Keep stdout for the results so they can be piped
+        eprintln!("Checked {}: {report}", listings_file.display());
+    }
+    let fx = FxTable::load(&config.fx_file)?;
+    let currency = match args.currency {
+        None => fx.base(),
+        Some(currency) if fx.supports(currency) => currency,
+        Some(currency) => anyhow::bail!("No exchange rate for {currency}"),
+    };
+    let options = SearchOptions {
+        period: period.range(),
+        locations: Arc::new(Locations::load(&config.locations_file)?),
+        include_fees_and_taxes: args.include_fees_and_taxes,
+        currency,
+        fx: Arc::new(fx),
+        ..Default::default()
+    };
+
+    let answers: Vec<Answer> = queries
+        .into_iter()
+        .map(|query| {
+            let results = bin_packing::search_locations_with(query.vehicles.clone(), &listings, &options);
+            Answer { query, results }
+        })
+        .collect();
+    write_answers(&answers, args.format, out)
+}
+
+pub fn write_answers(answers: &[Answer], format: Format, out: &mut impl Write) -> anyhow::Result<()> {
+    match format {
+        Format::Json => {
+            for answer in answers {
+                serde_json::to_writer(&mut *out, &answer.results)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "query,location_id,listing_ids,total_price_in_cents,currency,discount_in_cents")?;
+            for answer in answers {
+                for result in &answer.results {
+                    let row = Row::new(answer, result);
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{}",
+                        row.query,
+                        csv_field(&row.location_id),
+                        csv_field(&row.listing_ids.join(";")),
+                        result.total_price_in_cents,
+                        result.currency,
+                        result.discount.as_ref().map_or(0, |d| d.amount_in_cents),
+                    )?;
+                }
+            }
+        }
+        Format::Table => write_table(answers, out)?,
+    }
+    Ok(())
+}
+
+/// One result flattened for the table and CSV formats
+struct Row {
+    query: usize,
+    location_id: String,
+    listing_ids: Vec<String>,
+    total: Money,
+    discount: Option<Money>,
+}
+
+impl Row {
+    fn new(answer: &Answer, result: &PossibleSpace) -> Self {
+        Self {
+            query: answer.query.line,
+            location_id: result.location_id.clone(),
+            listing_ids: result.listing_ids.clone(),
+            total: result.total(),
+            discount: result
+                .discount
+                .as_ref()
+                .map(|d| Money::new(d.amount_in_cents, result.currency)),
+        }
+    }
+}
+
+fn write_table(answers: &[Answer], out: &mut impl Write) -> anyhow::Result<()> {
+    let header = ["query", "location_id", "listings", "total", "discount"];
+    let mut rows: Vec<[String; 5]> = Vec::new();
+    for answer in answers {
+        if answer.results.is_empty() {
+            rows.push([answer.query.line.to_string(), "no match".to_string(), String::new(), String::new(), String::new()]);
+        }
+        for result in &answer.results {
+            let row = Row::new(answer, result);
+            rows.push([
+                row.query.to_string(),
+                row.location_id,
+                row.listing_ids.join(", "),
+                row.total.to_string(),
+                row.discount.as_ref().map(Money::to_string).unwrap_or_default(),
+            ]);
+        }
+    }
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Quote a field if it would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct CatalogArgs {
+    /// Catalog to inspect [default: the configured listings file]
+    #[arg(long)]
+    pub listings: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum CatalogCommand {
+    /// Report invalid listings, duplicate ids and outliers. Exits with 1 if there are errors.
+    Check {
+        #[command(flatten)]
+        catalog: CatalogArgs,
+        /// Fail on warnings too
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+    /// Summarize the locations, prices and sizes in the catalog
+    Stats(CatalogArgs),
+}
+
+/// Run a catalog command. Returns false if the check failed.
+pub fn catalog(command: &CatalogCommand, config: &Config, out: &mut impl Write) -> anyhow::Result<bool> {
+    let (CatalogCommand::Check { catalog: args, .. } | CatalogCommand::Stats(args)) = command;
+    let listings_file = args.listings.as_ref().unwrap_or(&config.listings_file);
+    // Lenient so every problem gets reported and the rest can still be inspected
+    let (listings, report) = validation::load_listings(listings_file, LoadMode::Lenient)?;
+
+    match command {
+        CatalogCommand::Check { deny_warnings, .. } => {
+            let check = inspect::check(&report, &listings);
+            match args.format {
+                ReportFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&check)?)?,
+                ReportFormat::Text => {
+                    for finding in &check.findings {
+                        writeln!(out, "{finding}")?;
+                    }
+                    writeln!(
+                        out,
+                        "Checked {} listings in {}: {} errors, {} warnings",
+                        check.listings,
+                        listings_file.display(),
+                        check.errors,
+                        check.warnings
+                    )?;
+                }
+            }
+            Ok(check.errors == 0 && !(*deny_warnings && check.warnings > 0))
+        }
+        CatalogCommand::Stats(_) => {
+            let stats = inspect::stats(&listings);
+            match args.format {
+                ReportFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&stats)?)?,
+                ReportFormat::Text => write_stats(&stats, out)?,
+            }
+            if !report.is_clean() {
+                eprintln!("Left out {} listings with problems, run catalog check for details", report.total - report.valid);
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn write_stats(stats: &inspect::Stats, out: &mut impl Write) -> anyhow::Result<()> {
+    writeln!(out, "Listings:  {}", stats.listings)?;
+    writeln!(out, "Locations: {}", stats.locations)?;
+    if let Some(summary) = &stats.listings_per_location {
+        writeln!(out, "\nListings per location")?;
+        write_summary(summary, 1, out)?;
+    }
+    for (currency, summary) in &stats.price_per_square_foot {
+        writeln!(out, "\nPrice per square foot in {currency} cents")?;
+        write_summary(summary, 2, out)?;
+    }
+    writeln!(out, "\nSizes")?;
+    for size in &stats.sizes {
+        let share = 100.0 * size.listings as f64 / stats.listings as f64;
+        writeln!(out, "  {:>4}x{:<4} {:>6} {share:>5.1}%", size.length, size.width, size.listings)?;
+    }
+    Ok(())
+}
+
+fn write_summary(summary: &inspect::Summary, decimals: usize, out: &mut impl Write) -> anyhow::Result<()> {
+    let inspect::Summary { min, p25, median, p75, max, mean } = summary;
+    writeln!(
+        out,
+        "  min {min:.decimals$}  p25 {p25:.decimals$}  median {median:.decimals$}  p75 {p75:.decimals$}  max {max:.decimals$}  mean {mean:.decimals$}"
+    )?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! # Compute Pool
+//!
+//! Searches are CPU bound, run on an actix worker they hold up every other
+//! connection on that worker. Handlers hand them to this pool instead: a fixed
+//! number of threads taking jobs from a bounded queue.
+//!
+//! When the queue is full the job is turned away with `ComputeError::Busy`
+//! instead of waiting, the server answers 503 so clients back off and retry.
+//! A caller that waits longer than the pool's timeout gets
+//! `ComputeError::TimedOut`. A job whose caller has gone, because the client
+//! disconnected or the wait timed out, is skipped when it comes off the queue
+//! so it doesn't hold up a thread for nobody.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::Dispatch;
+
+pub const DEFAULT_QUEUE: usize = 64;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComputeError {
+    /// Every thread is busy and the queue is full
+    Busy,
+    /// The job didn't finish within the pool's timeout
+    TimedOut,
+    /// The job panicked
+    Failed,
+}
+
+impl fmt::Display for ComputeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Busy => write!(f, "The server is busy, try again shortly"),
+            Self::TimedOut => write!(f, "The search took too long, try again shortly"),
+            Self::Failed => write!(f, "The search failed unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for ComputeError {}
+
+/// What a job returned, and how long it waited for a thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct Computed<T> {
+    pub value: T,
+    pub waited: Duration,
+}
+
+pub struct ComputePool {
+    sender: SyncSender<Job>,
+    /// Jobs waiting for a thread
+    queued: Arc<AtomicUsize>,
+    /// Jobs skipped because their caller had gone
+    abandoned: Arc<AtomicU64>,
+    timeout: Duration,
+}
+
+impl ComputePool {
+    /// `threads` threads and room for `queue` jobs waiting for one of them
+    pub fn new(threads: usize, queue: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("compute-{i}"))
+                .spawn(move || work(&receiver))
+                .expect("Could not start a compute thread");
+        }
+        Self {
+            sender,
+            queued: Arc::default(),
+            abandoned: Arc::default(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Give up on a job that hasn't finished `timeout` after it was submitted
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run `job` on the pool. It logs to the caller's subscriber, in the caller's span.
+    pub async fn run<T: Send + 'static>(&self, job: impl FnOnce() -> T + Send + 'static) -> Result<Computed<T>, ComputeError> {
+        let (done, result) = oneshot::channel();
+        let queued = self.queued.clone();
+        let abandoned = self.abandoned.clone();
+        let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+        let span = tracing::Span::current();
+        let submitted = Instant::now();
+        let job: Job = Box::new(move || {
+            queued.fetch_sub(1, Ordering::Relaxed);
+            let waited = submitted.elapsed();
+            // Nobody is waiting for the answer any more
+            if done.is_closed() {
+                abandoned.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            // A panic only fails this job, the thread stays in the pool
+            let job = AssertUnwindSafe(|| tracing::dispatcher::with_default(&dispatch, || span.in_scope(job)));
+            let result = panic::catch_unwind(job);
+            // The caller's span can't close while this holds on to it
+            drop(span);
+            if let Ok(value) = result {
+                let _ = done.send(Computed { value, waited });
+            }
+        });
+
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.try_send(job).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(ComputeError::Busy);
+        }
+        // Dropping `result` on a timeout tells the job to skip itself if it hasn't started
+        match tokio::time::timeout(self.timeout, result).await {
+            Ok(computed) => computed.map_err(|_| ComputeError::Failed),
+            Err(_) => Err(ComputeError::TimedOut),
+        }
+    }
+
+    /// Jobs waiting for a thread
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Jobs skipped so far because their caller had stopped waiting
+    pub fn abandoned(&self) -> u64 {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+}
+
+/// One thread per CPU
+impl Default for ComputePool {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()), DEFAULT_QUEUE)
+    }
+}
+
+/// Run jobs until the pool is dropped
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        job();
+    }
+}
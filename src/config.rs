@@ -0,0 +1,401 @@
+//! # Server Configuration
+//!
+//! Settings come in layers, each one overriding the one before:
+//!
+//! 1. Defaults, the same values the server always used
+//! 2. A TOML config file, `neighbor.toml` if there is one or the file given
+//!    with `--config` or `NEIGHBOR_CONFIG`
+//! 3. `NEIGHBOR_*` environment variables, such as `NEIGHBOR_PORT`
+//! 4. Command line flags, such as `--port`
+//!
+//! The result is checked before the server starts and printed at startup.
+
+use crate::auth;
+use crate::compute;
+use crate::fx::FX_FILE;
+use crate::locations::LOCATIONS_FILE;
+use crate::logging::{self, DEFAULT_LEVEL};
+use crate::model::Limits;
+use crate::rate_limit::{self, Network};
+use crate::store::{Backend, DEFAULT_DATABASE, LISTINGS_FILE};
+use crate::validation::LoadMode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const CONFIG_FILE: &str = "neighbor.toml";
+pub const CONFIG_ENV: &str = "NEIGHBOR_CONFIG";
+const ENV_PREFIX: &str = "NEIGHBOR_";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Storage {
+    /// Listings in the listings file, bookings only in memory
+    #[default]
+    Json,
+    /// Listings and bookings in the database
+    Sqlite,
+}
+
+impl FromStr for Storage {
+    type Err = String;
+
+    fn from_str(storage: &str) -> Result<Self, Self::Err> {
+        match storage {
+            "json" => Ok(Self::Json),
+            "sqlite" => Ok(Self::Sqlite),
+            other => Err(format!("Unknown storage {other}, expected json or sqlite")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// HTTP worker threads, one per CPU by default
+    pub workers: usize,
+    /// Threads that run searches, one per CPU by default
+    pub compute_threads: usize,
+    /// Searches that can wait for a compute thread before the server answers 503, at least 1
+    pub compute_queue: usize,
+    /// Seconds a search can take, waiting included, before the server answers 503
+    pub compute_timeout: u64,
+    pub listings_file: PathBuf,
+    pub locations_file: PathBuf,
+    pub fx_file: PathBuf,
+    pub storage: Storage,
+    /// Only used with `storage = "sqlite"`
+    pub database: PathBuf,
+    pub load_mode: LoadMode,
+    /// Most vehicles a single search or booking can ask for
+    pub max_vehicles: i32,
+    /// Most searches in one batch request
+    pub max_batch_size: usize,
+    /// Where logging starts, `PUT /admin/log-level` changes it while running
+    pub log_level: String,
+    /// Bearer token for listing edits and `/admin`, which are off without one. Never printed.
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+    /// Cost units per minute for each client, 0 for no limit
+    pub rate_limit: u32,
+    /// `X-Api-Key` values that get their own budget, other clients are limited by IP. Never printed.
+    #[serde(skip_serializing)]
+    pub api_keys: Vec<String>,
+    /// Proxies whose `Fly-Client-IP` and `X-Forwarded-For` headers give the client's IP
+    pub trusted_proxies: Vec<Network>,
+    /// Limits for routes such as `/search` that get their own budget.
+    /// Last because TOML tables have to come after the plain values.
+    pub route_limits: BTreeMap<String, u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            compute_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            compute_queue: compute::DEFAULT_QUEUE,
+            compute_timeout: compute::DEFAULT_TIMEOUT.as_secs(),
+            listings_file: LISTINGS_FILE.into(),
+            locations_file: LOCATIONS_FILE.into(),
+            fx_file: FX_FILE.into(),
+            storage: Storage::default(),
+            database: DEFAULT_DATABASE.into(),
+            load_mode: LoadMode::default(),
+            max_vehicles: Limits::default().max_vehicles,
+            max_batch_size: Limits::default().max_batch_size,
+            log_level: DEFAULT_LEVEL.to_string(),
+            admin_token: None,
+            rate_limit: rate_limit::DEFAULT_LIMIT,
+            api_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
+            route_limits: BTreeMap::new(),
+        }
+    }
+}
+
+/// One layer of settings. Anything left out keeps the value from the layer below.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Port to listen on [default: 8080]
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// HTTP worker threads [default: one per CPU]
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// Threads that run searches [default: one per CPU]
+    #[arg(long)]
+    pub compute_threads: Option<usize>,
+    /// Searches waiting for a compute thread before answering 503 [default: 64]
+    #[arg(long)]
+    pub compute_queue: Option<usize>,
+    /// Seconds a search can take, waiting included, before answering 503 [default: 30]
+    #[arg(long)]
+    pub compute_timeout: Option<u64>,
+    /// The listing catalog [default: listings.json]
+    #[arg(long)]
+    pub listings_file: Option<PathBuf>,
+    /// Settings per location [default: locations.json]
+    #[arg(long)]
+    pub locations_file: Option<PathBuf>,
+    /// Exchange rates [default: fx.json]
+    #[arg(long)]
+    pub fx_file: Option<PathBuf>,
+    /// json or sqlite [default: json]
+    #[arg(long)]
+    pub storage: Option<Storage>,
+    /// SQLite database for sqlite storage [default: neighbor.db]
+    #[arg(long)]
+    pub database: Option<PathBuf>,
+    /// strict refuses a listings file with problems, lenient skips the bad listings [default: strict]
+    #[arg(long)]
+    pub load_mode: Option<LoadMode>,
+    /// Most vehicles in one search or booking [default: 5]
+    #[arg(long)]
+    pub max_vehicles: Option<i32>,
+    /// Most searches in one batch request [default: 100]
+    #[arg(long)]
+    pub max_batch_size: Option<usize>,
+    /// error, warn, info, debug or trace, or per module directives [default: info]
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Only from the config file or NEIGHBOR_ADMIN_TOKEN, flags show up in the process list
+    #[arg(skip)]
+    pub admin_token: Option<String>,
+    /// Cost units per minute for each client, 0 turns rate limiting off [default: 600]
+    #[arg(long)]
+    pub rate_limit: Option<u32>,
+    /// Only from the config file or NEIGHBOR_API_KEYS, comma separated
+    #[arg(skip)]
+    pub api_keys: Option<Vec<String>>,
+    /// IP addresses or networks of proxies to take the client IP from, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub trusted_proxies: Option<Vec<Network>>,
+    /// Per route limits, only in the config file
+    #[arg(skip)]
+    pub route_limits: Option<BTreeMap<String, u32>>,
+}
+
+impl Overrides {
+    /// Read `NEIGHBOR_HOST`, `NEIGHBOR_PORT` and the rest through `var`
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            host: env(&var, "HOST")?,
+            port: env(&var, "PORT")?,
+            workers: env(&var, "WORKERS")?,
+            compute_threads: env(&var, "COMPUTE_THREADS")?,
+            compute_queue: env(&var, "COMPUTE_QUEUE")?,
+            compute_timeout: env(&var, "COMPUTE_TIMEOUT")?,
+            listings_file: env(&var, "LISTINGS_FILE")?,
+            locations_file: env(&var, "LOCATIONS_FILE")?,
+            fx_file: env(&var, "FX_FILE")?,
+            storage: env(&var, "STORAGE")?,
+            database: env(&var, "DATABASE")?,
+            load_mode: env(&var, "LOAD_MODE")?,
+            max_vehicles: env(&var, "MAX_VEHICLES")?,
+            max_batch_size: env(&var, "MAX_BATCH_SIZE")?,
+            log_level: env(&var, "LOG_LEVEL")?,
+            admin_token: env(&var, "ADMIN_TOKEN")?,
+            rate_limit: env(&var, "RATE_LIMIT")?,
+            api_keys: env_list(&var, "API_KEYS")?,
+            trusted_proxies: env_list(&var, "TRUSTED_PROXIES")?,
+            route_limits: None,
+        })
+    }
+
+    /// Read a TOML file using the same names as the flags, with underscores
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {}: {e}", path.display()))?;
+        toml::from_str(&data).map_err(|e| anyhow::anyhow!("Invalid config file {}: {e}", path.display()))
+    }
+}
+
+fn env<T: FromStr>(var: &impl Fn(&str) -> Option<String>, name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: fmt::Display,
+{
+    let name = format!("{ENV_PREFIX}{name}");
+    match var(&name).filter(|value| !value.is_empty()) {
+        None => Ok(None),
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid {name} {value}: {e}")),
+    }
+}
+
+/// A comma separated `env` value
+fn env_list<T: FromStr>(var: &impl Fn(&str) -> Option<String>, name: &str) -> anyhow::Result<Option<Vec<T>>>
+where
+    T::Err: fmt::Display,
+{
+    let Some(values) = env::<String>(var, name)? else {
+        return Ok(None);
+    };
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|e| anyhow::anyhow!("Invalid {ENV_PREFIX}{name}: {e}")))
+        .collect::<anyhow::Result<_>>()
+        .map(Some)
+}
+
+impl Config {
+    /// Layer the config file, the environment and the command line over the defaults.
+    /// `file` is the `--config` flag.
+    pub fn load(file: Option<&Path>, flags: Overrides, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        let file = file.map(Path::to_path_buf).or_else(|| var(CONFIG_ENV).map(PathBuf::from));
+        match file {
+            Some(path) => config.apply(Overrides::from_file(path)?),
+            // Only the default file is allowed to be missing
+            None if Path::new(CONFIG_FILE).exists() => config.apply(Overrides::from_file(CONFIG_FILE)?),
+            None => {}
+        }
+        config.apply(Overrides::from_env(var)?);
+        config.apply(flags);
+
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn apply(&mut self, overrides: Overrides) {
+        let Overrides {
+            host,
+            port,
+            workers,
+            compute_threads,
+            compute_queue,
+            compute_timeout,
+            listings_file,
+            locations_file,
+            fx_file,
+            storage,
+            database,
+            load_mode,
+            max_vehicles,
+            max_batch_size,
+            log_level,
+            admin_token,
+            rate_limit,
+            api_keys,
+            trusted_proxies,
+            route_limits,
+        } = overrides;
+        self.host = host.unwrap_or(std::mem::take(&mut self.host));
+        self.port = port.unwrap_or(self.port);
+        self.workers = workers.unwrap_or(self.workers);
+        self.compute_threads = compute_threads.unwrap_or(self.compute_threads);
+        self.compute_queue = compute_queue.unwrap_or(self.compute_queue);
+        self.compute_timeout = compute_timeout.unwrap_or(self.compute_timeout);
+        self.listings_file = listings_file.unwrap_or(std::mem::take(&mut self.listings_file));
+        self.locations_file = locations_file.unwrap_or(std::mem::take(&mut self.locations_file));
+        self.fx_file = fx_file.unwrap_or(std::mem::take(&mut self.fx_file));
+        self.storage = storage.unwrap_or(self.storage);
+        self.database = database.unwrap_or(std::mem::take(&mut self.database));
+        self.load_mode = load_mode.unwrap_or(self.load_mode);
+        self.max_vehicles = max_vehicles.unwrap_or(self.max_vehicles);
+        self.max_batch_size = max_batch_size.unwrap_or(self.max_batch_size);
+        self.log_level = log_level.unwrap_or(std::mem::take(&mut self.log_level));
+        self.admin_token = admin_token.or(self.admin_token.take());
+        self.rate_limit = rate_limit.unwrap_or(self.rate_limit);
+        self.api_keys = api_keys.unwrap_or(std::mem::take(&mut self.api_keys));
+        self.trusted_proxies = trusted_proxies.unwrap_or(std::mem::take(&mut self.trusted_proxies));
+        self.route_limits.extend(route_limits.unwrap_or_default());
+    }
+
+    /// Every setting that can't work, all at once
+    pub fn check(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if self.host.trim().is_empty() {
+            problems.push("host must not be empty".to_string());
+        }
+        if self.port == 0 {
+            problems.push("port must be between 1 and 65535".to_string());
+        }
+        if self.workers == 0 {
+            problems.push("workers must be at least 1".to_string());
+        }
+        if self.compute_threads == 0 {
+            problems.push("compute_threads must be at least 1".to_string());
+        }
+        if self.compute_queue == 0 {
+            // A queue of 0 is a rendezvous channel, nearly every search would be turned away
+            problems.push("compute_queue must be at least 1".to_string());
+        }
+        if self.compute_timeout == 0 {
+            problems.push("compute_timeout must be at least 1".to_string());
+        }
+        if self.max_vehicles < 1 {
+            problems.push("max_vehicles must be at least 1".to_string());
+        }
+        if self.max_batch_size == 0 {
+            problems.push("max_batch_size must be at least 1".to_string());
+        }
+        if let Err(e) = logging::filter(&self.log_level) {
+            problems.push(format!("log_level {}: {e}", self.log_level));
+        }
+        if self.admin_token.as_ref().is_some_and(|token| token.len() < auth::MIN_TOKEN_LENGTH) {
+            problems.push(format!("admin_token must be at least {} characters", auth::MIN_TOKEN_LENGTH));
+        }
+        if self.api_keys.iter().any(|key| key.trim().is_empty()) {
+            problems.push("api_keys must not be empty".to_string());
+        }
+        for route in self.route_limits.keys().filter(|route| !route.starts_with('/')) {
+            problems.push(format!("route_limits {route} must be a route such as /search"));
+        }
+        let files = [
+            ("listings_file", &self.listings_file),
+            ("locations_file", &self.locations_file),
+            ("fx_file", &self.fx_file),
+            ("database", &self.database),
+        ];
+        for (name, path) in files {
+            if path.as_os_str().is_empty() {
+                problems.push(format!("{name} must not be empty"));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("Invalid configuration:\n  {}", problems.join("\n  "))
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.storage {
+            Storage::Json => Backend::Json,
+            Storage::Sqlite => Backend::Sqlite {
+                path: self.database.clone(),
+            },
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_vehicles: self.max_vehicles,
+            max_batch_size: self.max_batch_size,
+        }
+    }
+}
+
+/// Prints as the TOML file that would give this configuration
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let toml = toml::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", toml.trim_end())
+    }
+}
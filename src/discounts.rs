@@ -0,0 +1,89 @@
+//! # Bundle Discounts
+//!
+//! Hosts can reward renting several listings at their location, for example
+//! "rent 2+ listings and get 10% off". Discounts are part of combination costing,
+//! so a bigger bundle can beat a smaller one once the discount kicks in.
+//! Only the best discount applies, they don't stack.
+//! Flat amounts are in the exchange rate base currency.
+
+use crate::fx::FxTable;
+use crate::money::{Money, MoneyError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DiscountKind {
+    /// Whole percent off the subtotal
+    Percentage { percent: i32 },
+    Flat { amount_in_cents: i64 },
+}
+
+fn validate_kind(kind: &DiscountKind) -> Result<(), ValidationError> {
+    match kind {
+        DiscountKind::Percentage { percent } if !(1..=100).contains(percent) => {
+            Err(ValidationError::new("percent_must_be_between_1_and_100"))
+        }
+        DiscountKind::Flat { amount_in_cents } if *amount_in_cents <= 0 => {
+            Err(ValidationError::new("amount_must_be_positive"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Discount {
+    /// Label shown to renters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Number of listings that must be rented together
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub min_listings: usize,
+    #[serde(flatten)]
+    #[validate(custom(function = "validate_kind"))]
+    pub kind: DiscountKind,
+}
+
+impl Discount {
+    /// Amount taken off the subtotal, never more than the subtotal
+    pub fn amount_off(&self, subtotal: Money, fx: &FxTable) -> Result<Money, MoneyError> {
+        let amount = match self.kind {
+            // Rounded down, as before amounts were `Money`
+            DiscountKind::Percentage { percent } => subtotal.checked_ratio_down(percent as i128, 100)?,
+            DiscountKind::Flat { amount_in_cents } => fx.from_base(amount_in_cents, subtotal.currency)?,
+        };
+        let amount = amount.min(subtotal)?;
+        Ok(Money::new(amount.amount_in_cents.max(0), amount.currency))
+    }
+}
+
+/// The discount that won and how much it took off
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AppliedDiscount {
+    #[serde(flatten)]
+    pub discount: Discount,
+    /// In the currency of the subtotal
+    pub amount_in_cents: i64,
+}
+
+/// Pick the discount worth the most for a bundle
+pub fn best_discount(
+    discounts: &[Discount],
+    listing_count: usize,
+    subtotal: Money,
+    fx: &FxTable,
+) -> Result<Option<AppliedDiscount>, MoneyError> {
+    let mut best: Option<AppliedDiscount> = None;
+    for discount in discounts.iter().filter(|d| listing_count >= d.min_listings) {
+        let amount = discount.amount_off(subtotal, fx)?;
+        if amount.amount_in_cents > best.as_ref().map_or(0, |b| b.amount_in_cents) {
+            best = Some(AppliedDiscount {
+                discount: discount.clone(),
+                amount_in_cents: amount.amount_in_cents,
+            });
+        }
+    }
+    Ok(best)
+}
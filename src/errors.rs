@@ -0,0 +1,155 @@
+//! # API Errors
+//!
+//! Every request that fails gets the same JSON body:
+//!
+//! ```json
+//! {
+//!   "code": "validation_failed",
+//!   "error": "Validation failed",
+//!   "details": "vehicles[1].quantity: must be at least 1",
+//!   "fields": [{ "field": "vehicles[1].quantity", "code": "range", "message": "must be at least 1" }]
+//! }
+//! ```
+//!
+//! `code` never changes and is what programs should match on. `error` and
+//! `details` are for people. `fields` is only there when the request failed
+//! validation, with one entry per problem.
+
+use serde::{Deserialize, Serialize};
+use serde_json::error::Category;
+use std::fmt;
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ApiError {
+    /// Stable, such as `validation_failed` or `cursor_expired`
+    pub code: String,
+    /// Short summary, such as "Validation failed"
+    pub error: String,
+    pub details: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// One problem with one field of the request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FieldError {
+    /// Path to the field, such as `vehicles[1].quantity`. Left out when the
+    /// problem is with the request as a whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Stable, such as `length_must_be_positive` or `range`
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: &str, error: &str, details: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            error: error.to_string(),
+            details: details.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn validation(errors: &ValidationErrors) -> Self {
+        Self::invalid_fields(
+            flatten(errors)
+                .into_iter()
+                .map(|(field, error)| FieldError {
+                    field,
+                    code: error.code.to_string(),
+                    message: message(error),
+                })
+                .collect(),
+        )
+    }
+
+    /// A validation failure found outside the validator
+    pub fn invalid_field(field: &str, code: &str, message: impl Into<String>) -> Self {
+        Self::invalid_fields(vec![FieldError {
+            field: Some(field.to_string()),
+            code: code.to_string(),
+            message: message.into(),
+        }])
+    }
+
+    fn invalid_fields(fields: Vec<FieldError>) -> Self {
+        let details = fields
+            .iter()
+            .map(|error| match &error.field {
+                Some(field) => format!("{field}: {}", error.message),
+                None => error.message.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            fields,
+            ..Self::new("validation_failed", "Validation failed", details)
+        }
+    }
+
+    /// A body that isn't JSON, or JSON that doesn't fit the request
+    pub fn json(error: &serde_json::Error) -> Self {
+        match error.classify() {
+            Category::Syntax | Category::Eof => Self::new("malformed_json", "Malformed JSON", error.to_string()),
+            Category::Data | Category::Io => Self::new("invalid_request", "Invalid request", error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.error, self.details)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Every error with the path to its field, sorted by path. Errors from a
+/// schema check belong to the struct that has it.
+pub fn flatten(errors: &ValidationErrors) -> Vec<(Option<String>, &ValidationError)> {
+    let mut found = Vec::new();
+    collect(errors, None, &mut found);
+    found.sort_by(|(a, _), (b, _)| a.cmp(b));
+    found
+}
+
+fn collect<'a>(errors: &'a ValidationErrors, prefix: Option<&str>, found: &mut Vec<(Option<String>, &'a ValidationError)>) {
+    for (field, kind) in errors.errors() {
+        let path = match (prefix, field.as_ref()) {
+            (prefix, "__all__") => prefix.map(str::to_string),
+            (Some(prefix), field) => Some(format!("{prefix}.{field}")),
+            (None, field) => Some(field.to_string()),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => found.extend(errors.iter().map(|error| (path.clone(), error))),
+            ValidationErrorsKind::Struct(errors) => collect(errors, path.as_deref(), found),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    let path = format!("{}[{index}]", path.as_deref().unwrap_or_default());
+                    collect(errors, Some(&path), found);
+                }
+            }
+        }
+    }
+}
+
+/// The error's own message, or one made from its code and params
+pub fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("range", Some(min), Some(max)) => format!("must be between {min} and {max}"),
+        ("range", Some(min), None) => format!("must be at least {min}"),
+        ("range", None, Some(max)) => format!("must be at most {max}"),
+        ("length", Some(min), None) if min == "1" => "must not be empty".to_string(),
+        ("length", Some(min), None) => format!("must have a length of at least {min}"),
+        ("length", None, Some(max)) => format!("must have a length of at most {max}"),
+        (code, _, _) => code.replace('_', " "),
+    }
+}
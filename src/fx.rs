@@ -0,0 +1,136 @@
+//! # Exchange Rates
+//!
+//! A static table of exchange rates loaded from the optional `fx.json`:
+//!
+//! ```json
+//! { "base": "USD", "rates": { "EUR": 1.08, "GBP": 1.27 } }
+//! ```
+//!
+//! A rate is how much one unit of the currency is worth in the base currency.
+//! Without the file only the base currency (USD) is supported.
+
+use crate::model::Listing;
+use crate::money::{Currency, Money, MoneyError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub const FX_FILE: &str = "fx.json";
+
+/// Rates are stored in millionths so conversions stay in integer math
+const RATE_SCALE: f64 = 1_000_000.0;
+
+#[derive(Debug, Deserialize)]
+struct FxFile {
+    #[serde(default)]
+    base: Currency,
+    #[serde(default)]
+    rates: HashMap<Currency, f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FxTable {
+    base: Currency,
+    /// Base currency micro units per unit of the currency
+    rates: HashMap<Currency, i64>,
+}
+
+impl Default for FxTable {
+    fn default() -> Self {
+        Self::new(Currency::USD)
+    }
+}
+
+impl FxTable {
+    pub fn new(base: Currency) -> Self {
+        Self {
+            base,
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Load the table, a missing file means only the base currency is supported
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: FxFile = serde_json::from_str(&data)?;
+
+        let mut table = Self::new(file.base);
+        for (currency, rate) in file.rates {
+            table.set_rate(currency, rate)?;
+        }
+        Ok(table)
+    }
+
+    /// Set how much one unit of the currency is worth in the base currency
+    pub fn set_rate(&mut self, currency: Currency, rate: f64) -> anyhow::Result<()> {
+        if !rate.is_finite() || rate <= 0.0 {
+            anyhow::bail!("Exchange rate for {currency} must be positive, got {rate}");
+        }
+        self.rates.insert(currency, (rate * RATE_SCALE).round() as i64);
+        Ok(())
+    }
+
+    pub fn base(&self) -> Currency {
+        self.base
+    }
+
+    pub fn supports(&self, currency: Currency) -> bool {
+        currency == self.base || self.rates.contains_key(&currency)
+    }
+
+    pub fn currencies(&self) -> Vec<Currency> {
+        let mut currencies: Vec<Currency> = self.rates.keys().copied().collect();
+        currencies.push(self.base);
+        currencies.sort();
+        currencies.dedup();
+        currencies
+    }
+
+    /// Every listing's currency has a rate, otherwise searches would leave them out
+    pub fn check_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        let mut missing: Vec<Currency> = listings
+            .iter()
+            .map(|listing| listing.price.currency)
+            .filter(|&currency| !self.supports(currency))
+            .collect();
+        missing.sort();
+        missing.dedup();
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(Currency::to_string).collect();
+            anyhow::bail!("Listings are priced in {} with no exchange rate into {}", missing.join(", "), self.base);
+        }
+        Ok(())
+    }
+
+    fn micros(&self, currency: Currency) -> Result<i128, MoneyError> {
+        if currency == self.base {
+            return Ok(RATE_SCALE as i128);
+        }
+        self.rates
+            .get(&currency)
+            .map(|rate| *rate as i128)
+            .ok_or(MoneyError::UnknownRate(currency))
+    }
+
+    /// Convert through the base currency, rounding half up to the cent
+    pub fn convert(&self, money: Money, to: Currency) -> Result<Money, MoneyError> {
+        if money.currency == to {
+            return Ok(money);
+        }
+        let from_micros = self.micros(money.currency)?;
+        let to_micros = self.micros(to)?;
+        let converted = money.checked_ratio(from_micros, to_micros)?;
+        Ok(Money::new(converted.amount_in_cents, to))
+    }
+
+    /// An amount configured in the base currency, in another currency
+    pub fn from_base(&self, amount_in_cents: i64, to: Currency) -> Result<Money, MoneyError> {
+        self.convert(Money::new(amount_in_cents, self.base), to)
+    }
+}
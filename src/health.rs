@@ -0,0 +1,110 @@
+//! # Health Checks
+//!
+//! `/healthz` answers as long as the server can handle requests at all, a
+//! failing liveness check means the process should be restarted.
+//!
+//! `/readyz` says whether searches can be answered: the catalog has to be
+//! loaded and have listings, an empty file or database would answer every
+//! search with nothing. A reload that failed doesn't make the server unready,
+//! it keeps searching the listings it has, but the error is reported until a
+//! reload works.
+//!
+//! `neighbor healthcheck` asks the server on the configured port, so container
+//! health checks don't have to repeat the port.
+
+use crate::config::Config;
+use crate::store::ListingStore;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Liveness {
+    /// Always `ok`
+    pub status: String,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self { status: "ok".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Why the server isn't ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub catalog: CatalogStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct CatalogStatus {
+    pub loaded: bool,
+    /// Goes up on every load, reload and edit
+    pub version: u64,
+    pub listings: usize,
+    /// Locations with at least one listing
+    pub locations: usize,
+    pub changed_at: DateTime<Utc>,
+    /// Why the last reload kept the current listings
+    pub last_reload_error: Option<String>,
+}
+
+impl Readiness {
+    pub fn of(store: &dyn ListingStore) -> Self {
+        let snapshot = store.snapshot();
+        // Stores start at version 1 once their listings are in
+        let loaded = snapshot.version > 0;
+        let locations: HashSet<&str> = snapshot.listings.iter().map(|l| l.location_id.as_str()).collect();
+        let reason = if !loaded {
+            Some("The catalog isn't loaded yet".to_string())
+        } else if snapshot.listings.is_empty() {
+            Some("The catalog has no listings".to_string())
+        } else {
+            None
+        };
+        Self {
+            ready: reason.is_none(),
+            reason,
+            catalog: CatalogStatus {
+                loaded,
+                version: snapshot.version,
+                listings: snapshot.listings.len(),
+                locations: locations.len(),
+                changed_at: snapshot.changed_at.into(),
+                last_reload_error: store.reload_error(),
+            },
+        }
+    }
+}
+
+/// Ask the server on the configured host and port for `path`, true if it answered 200
+pub fn probe(config: &Config, path: &str) -> anyhow::Result<bool> {
+    // A server listening everywhere is reachable on loopback
+    let host = match config.host.as_str() {
+        "0.0.0.0" | "::" | "[::]" => "127.0.0.1",
+        host => host,
+    };
+    let timeout = Duration::from_secs(3);
+    let address = (host, config.port)
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("{host} has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)
+        .with_context(|| format!("Could not connect to {address}"))?;
+    stream.set_read_timeout(Some(timeout))?;
+    // One write, `write!` would send the request in pieces
+    stream.write_all(format!("GET {path} HTTP/1.0\r\nHost: {host}\r\n\r\n").as_bytes())?;
+
+    let mut status = [0; 12];
+    stream.read_exact(&mut status)?;
+    // "HTTP/1.1 200"
+    Ok(status.ends_with(b" 200"))
+}
@@ -0,0 +1,132 @@
+//! # Idempotency Keys
+//!
+//! Clients retrying a request send the same `Idempotency-Key` header and get
+//! the original response back instead of performing the action twice.
+//! The request body is fingerprinted so a key can't be reused for a different request.
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+pub const HEADER: &str = "Idempotency-Key";
+
+#[derive(Debug, Clone)]
+enum Slot {
+    /// The first request with this key is still being handled
+    InFlight { fingerprint: String, started: Instant },
+    Completed { fingerprint: String, started: Instant, status: u16, body: Value },
+}
+
+impl Slot {
+    fn fingerprint(&self) -> &str {
+        match self {
+            Slot::InFlight { fingerprint, .. } | Slot::Completed { fingerprint, .. } => fingerprint,
+        }
+    }
+
+    fn started(&self) -> Instant {
+        match self {
+            Slot::InFlight { started, .. } | Slot::Completed { started, .. } => *started,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Begin {
+    /// First time the key is seen, handle the request and call `complete`
+    New,
+    /// Send back the stored response
+    Replay { status: u16, body: Value },
+    /// Another request with the key hasn't finished yet
+    InProgress,
+    /// The key was already used for a different request
+    Mismatch,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    slots: DashMap<String, Slot>,
+    ttl: Duration,
+}
+
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(24 * 60 * 60))
+    }
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self { slots: DashMap::new(), ttl }
+    }
+
+    /// Claim the key for a request with the given fingerprint
+    pub fn begin(&self, key: &str, fingerprint: &str) -> Begin {
+        let now = Instant::now();
+        self.slots.retain(|_, slot| now.duration_since(slot.started()) < self.ttl);
+
+        match self.slots.entry(key.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(Slot::InFlight {
+                    fingerprint: fingerprint.to_string(),
+                    started: now,
+                });
+                Begin::New
+            }
+            Entry::Occupied(entry) => {
+                let slot = entry.get();
+                if slot.fingerprint() != fingerprint {
+                    return Begin::Mismatch;
+                }
+                match slot {
+                    Slot::InFlight { .. } => Begin::InProgress,
+                    Slot::Completed { status, body, .. } => Begin::Replay {
+                        status: *status,
+                        body: body.clone(),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Store the response so replays get the same thing
+    pub fn complete(&self, key: &str, status: u16, body: Value) {
+        if let Some(mut slot) = self.slots.get_mut(key) {
+            if let Slot::InFlight { fingerprint, started } = slot.value().clone() {
+                *slot = Slot::Completed { fingerprint, started, status, body };
+            }
+        }
+    }
+
+    /// Forget a key that is still in flight, so the request can be tried again
+    pub fn release(&self, key: &str) {
+        self.slots.remove_if(key, |_, slot| matches!(slot, Slot::InFlight { .. }));
+    }
+
+    /// Hold the key claimed by `begin` until the response is stored. If the
+    /// request never gets that far, a panic included, the key is released.
+    pub fn claim<'a>(&'a self, key: &'a str) -> Claim<'a> {
+        Claim { store: self, key }
+    }
+}
+
+/// A key between `Begin::New` and its response
+#[must_use]
+pub struct Claim<'a> {
+    store: &'a IdempotencyStore,
+    key: &'a str,
+}
+
+impl Claim<'_> {
+    pub fn complete(self, status: u16, body: Value) {
+        self.store.complete(self.key, status, body);
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        // Only does anything when `complete` wasn't called
+        self.store.release(self.key);
+    }
+}
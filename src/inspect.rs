@@ -0,0 +1,259 @@
+//! # Catalog Inspection
+//!
+//! Lints a listings file before it ships and summarizes what is in it.
+//!
+//! Errors are the problems that stop a file from loading in strict mode, such
+//! as duplicate ids or dimensions that aren't multiples of 10. Warnings are
+//! listings that load fine but look unusual:
+//!
+//! - Locations with far more or fewer listings than the rest
+//! - Prices per square foot far from the rest of the catalog in the same currency
+//!
+//! Unusual means outside Tukey's fences, 1.5 interquartile ranges beyond the
+//! middle half. Prices are compared on a log scale since a few expensive
+//! listings would otherwise stretch the range for everyone.
+
+use crate::model::Listing;
+use crate::money::Currency;
+use crate::validation::LoadReport;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+const FENCE: f64 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Position in the file, for findings about one listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_id: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}:")?;
+        if let Some(index) = self.index {
+            write!(f, " listing {index}")?;
+        }
+        if let Some(id) = &self.id {
+            write!(f, " ({id})")?;
+        }
+        if let Some(location_id) = &self.location_id {
+            write!(f, " location {location_id}")?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub listings: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// Lint the listings. `report` has the problems found loading them leniently,
+/// `listings` the ones that loaded.
+pub fn check(report: &LoadReport, listings: &[Listing]) -> Check {
+    let mut findings: Vec<Finding> = report
+        .problems
+        .iter()
+        .map(|problem| Finding {
+            severity: Severity::Error,
+            index: Some(problem.index),
+            id: problem.id.clone(),
+            location_id: None,
+            message: match &problem.field {
+                Some(field) => format!("{field}: {}", problem.message),
+                None => problem.message.clone(),
+            },
+        })
+        .collect();
+
+    let counts = listings_per_location(listings);
+    let per_location: Vec<f64> = counts.values().map(|&count| count as f64).collect();
+    if let Some((low, high)) = fences(&per_location) {
+        for (location_id, &count) in &counts {
+            let count = count as f64;
+            if count < low || count > high {
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    index: None,
+                    id: None,
+                    location_id: Some(location_id.to_string()),
+                    message: format!("has {count} listings, most locations have {low:.0} to {high:.0}"),
+                });
+            }
+        }
+    }
+
+    // Lenient loading skipped the listings with errors, line the rest up with the file again
+    let skipped: HashSet<usize> = report.problems.iter().map(|problem| problem.index).collect();
+    let in_file = (0..report.total).filter(|index| !skipped.contains(index)).zip(listings);
+    for (currency, priced) in by_currency(in_file) {
+        let logs: Vec<f64> = priced.iter().map(|(_, price)| price.ln()).collect();
+        let Some((low, high)) = fences(&logs) else { continue };
+        for ((index, listing), price) in priced {
+            if price.ln() < low || price.ln() > high {
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    index: Some(index),
+                    id: Some(listing.id.clone()),
+                    location_id: None,
+                    message: format!(
+                        "costs {} per square foot, most listings in {currency} cost {} to {}",
+                        cents(price),
+                        cents(low.exp()),
+                        cents(high.exp())
+                    ),
+                });
+            }
+        }
+    }
+
+    findings.sort_by_key(|finding| (finding.severity, finding.index));
+    Check {
+        listings: report.total,
+        errors: findings.iter().filter(|f| f.severity == Severity::Error).count(),
+        warnings: findings.iter().filter(|f| f.severity == Severity::Warning).count(),
+        findings,
+    }
+}
+
+/// Quartiles and extremes of some values
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Summary {
+    pub fn of(values: &[f64]) -> Option<Self> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        Some(Self {
+            min: *sorted.first()?,
+            p25: quantile(&sorted, 0.25),
+            median: quantile(&sorted, 0.5),
+            p75: quantile(&sorted, 0.75),
+            max: *sorted.last()?,
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SizeCount {
+    pub length: i32,
+    pub width: i32,
+    pub listings: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub listings: usize,
+    pub locations: usize,
+    pub listings_per_location: Option<Summary>,
+    /// In cents, per currency
+    pub price_per_square_foot: BTreeMap<Currency, Summary>,
+    /// Most common first
+    pub sizes: Vec<SizeCount>,
+}
+
+pub fn stats(listings: &[Listing]) -> Stats {
+    let counts = listings_per_location(listings);
+    let per_location: Vec<f64> = counts.values().map(|&count| count as f64).collect();
+
+    let price_per_square_foot = by_currency(listings.iter().enumerate())
+        .into_iter()
+        .filter_map(|(currency, priced)| {
+            let prices: Vec<f64> = priced.iter().map(|(_, price)| *price).collect();
+            Some((currency, Summary::of(&prices)?))
+        })
+        .collect();
+
+    let mut by_size: HashMap<(i32, i32), usize> = HashMap::new();
+    for listing in listings {
+        *by_size.entry((listing.length, listing.width)).or_default() += 1;
+    }
+    let mut sizes_seen: Vec<SizeCount> = by_size
+        .into_iter()
+        .map(|((length, width), listings)| SizeCount { length, width, listings })
+        .collect();
+    sizes_seen.sort_by_key(|size| (std::cmp::Reverse(size.listings), size.length, size.width));
+
+    Stats {
+        listings: listings.len(),
+        locations: counts.len(),
+        listings_per_location: Summary::of(&per_location),
+        price_per_square_foot,
+        sizes: sizes_seen,
+    }
+}
+
+fn listings_per_location(listings: &[Listing]) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for listing in listings {
+        *counts.entry(listing.location_id.as_str()).or_default() += 1;
+    }
+    counts
+}
+
+/// Listings with their index and price per square foot in cents
+type Priced<'a> = Vec<((usize, &'a Listing), f64)>;
+
+fn by_currency<'a>(listings: impl Iterator<Item = (usize, &'a Listing)>) -> BTreeMap<Currency, Priced<'a>> {
+    let mut grouped: BTreeMap<Currency, Vec<_>> = BTreeMap::new();
+    for (index, listing) in listings {
+        let area = f64::from(listing.length) * f64::from(listing.width);
+        if area > 0.0 && listing.price.amount_in_cents > 0 {
+            let price = listing.price.amount_in_cents as f64 / area;
+            grouped.entry(listing.price.currency).or_default().push(((index, listing), price));
+        }
+    }
+    grouped
+}
+
+/// Values outside these are unusual. None when there are too few to tell.
+fn fences(values: &[f64]) -> Option<(f64, f64)> {
+    if values.len() < 4 {
+        return None;
+    }
+    let summary = Summary::of(values)?;
+    let spread = summary.p75 - summary.p25;
+    Some((summary.p25 - FENCE * spread, summary.p75 + FENCE * spread))
+}
+
+/// Linear interpolation between the closest ranks
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+fn cents(amount: f64) -> String {
+    format!("{amount:.2} cents")
+}
@@ -1,2 +1,26 @@
+pub mod auth;
+pub mod availability;
 pub mod bin_packing;
+pub mod bookings;
+pub mod cli;
+pub mod compute;
+pub mod config;
+pub mod discounts;
+pub mod errors;
+pub mod fx;
+pub mod health;
+pub mod idempotency;
+pub mod inspect;
+pub mod locations;
+pub mod logging;
+pub mod metrics;
 pub mod model;
+pub mod money;
+pub mod pricing;
+pub mod quote;
+pub mod rate_limit;
+pub mod reload;
+pub mod search;
+pub mod sqlite;
+pub mod store;
+pub mod validation;
@@ -0,0 +1,76 @@
+//! # Location Settings
+//!
+//! Hosts configure rules that apply to every listing at a location in
+//! `locations.json`, keyed by `location_id`. The file is optional,
+//! locations without an entry have no special rules.
+
+use crate::discounts::Discount;
+use crate::quote::ServiceFee;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use validator::Validate;
+
+pub const LOCATIONS_FILE: &str = "locations.json";
+
+/// Settings for locations that aren't in the file
+static NO_SETTINGS: Location = Location {
+    discounts: Vec::new(),
+    service_fee: None,
+    tax_rate_bps: 0,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Validate)]
+pub struct Location {
+    #[serde(default)]
+    #[validate(nested)]
+    pub discounts: Vec<Discount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub service_fee: Option<ServiceFee>,
+    /// Sales tax in basis points, 825 is 8.25%
+    #[serde(default)]
+    #[validate(range(min = 0, max = 10000))]
+    pub tax_rate_bps: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Locations(HashMap<String, Location>);
+
+impl Locations {
+    /// Load the settings, a missing file means no location has rules
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let locations: Self = serde_json::from_str(&data)?;
+        for (location_id, location) in &locations.0 {
+            location
+                .validate()
+                .map_err(|e| anyhow::anyhow!("Invalid settings for location {location_id}: {e}"))?;
+        }
+        Ok(locations)
+    }
+
+    pub fn get(&self, location_id: &str) -> Option<&Location> {
+        self.0.get(location_id)
+    }
+
+    /// Settings for the location, or no special rules if it has none
+    pub fn settings_for(&self, location_id: &str) -> &Location {
+        self.get(location_id).unwrap_or(&NO_SETTINGS)
+    }
+
+    pub fn insert(&mut self, location_id: impl Into<String>, location: Location) {
+        self.0.insert(location_id.into(), location);
+    }
+
+    pub fn discounts_for(&self, location_id: &str) -> &[Discount] {
+        &self.settings_for(location_id).discounts
+    }
+}
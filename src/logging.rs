@@ -0,0 +1,97 @@
+//! # Logging
+//!
+//! The server logs one JSON object per line on stdout. Every line from a
+//! request carries its `request_id`, taken from the `X-Request-Id` header when
+//! the caller sent a usable one so a request can be followed across services.
+//!
+//! Searches run in spans for validation, grouping the listings, solving and
+//! serializing the response. A span logs how long it took when it closes.
+//! Solving each location is at debug level, raise the level at runtime with
+//! `PUT /admin/log-level` to see which locations are slow.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Registry};
+use utoipa::ToSchema;
+
+pub const REQUEST_ID: &str = "X-Request-Id";
+pub const DEFAULT_LEVEL: &str = "info";
+
+/// Longest request id kept from the header, longer ones are replaced
+const MAX_REQUEST_ID: usize = 128;
+
+/// A log level such as `debug`, or `EnvFilter` directives such as
+/// `info,neighbor::bin_packing=trace`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LogLevel {
+    pub level: String,
+}
+
+/// Changes the level of a running subscriber
+pub struct LevelHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    current: Mutex<String>,
+}
+
+impl LevelHandle {
+    pub fn get(&self) -> LogLevel {
+        let level = self.current.lock().unwrap().clone();
+        LogLevel { level }
+    }
+
+    pub fn set(&self, level: &str) -> Result<LogLevel, ParseError> {
+        let filter = filter(level)?;
+        let mut current = self.current.lock().unwrap();
+        // Only fails once the subscriber is gone
+        let _ = self.handle.reload(filter);
+        *current = level.to_string();
+        Ok(LogLevel { level: current.clone() })
+    }
+}
+
+pub fn filter(level: &str) -> Result<EnvFilter, ParseError> {
+    EnvFilter::try_new(level)
+}
+
+/// JSON lines into `writer`, filtered at `level` until the handle changes it
+pub fn subscriber<W>(level: &str, writer: W) -> Result<(impl Subscriber + Send + Sync, LevelHandle), ParseError>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let (filter, handle) = reload::Layer::new(filter(level)?);
+    let format = tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(writer);
+    let subscriber = tracing_subscriber::registry().with(filter).with(format);
+    let handle = LevelHandle {
+        handle,
+        current: Mutex::new(level.to_string()),
+    };
+    Ok((subscriber, handle))
+}
+
+/// Log to stdout for the rest of the process
+pub fn init(level: &str) -> anyhow::Result<LevelHandle> {
+    let (subscriber, handle) = subscriber(level, std::io::stdout)?;
+    subscriber.try_init()?;
+    Ok(handle)
+}
+
+/// The caller's request id if it is printable and not too long, or a new one
+pub fn request_id(header: Option<&str>) -> String {
+    match header {
+        Some(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID && id.bytes().all(|b| b.is_ascii_graphic()) => {
+            id.to_string()
+        }
+        _ => uuid::Uuid::new_v4().to_string(),
+    }
+}
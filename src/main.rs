@@ -1,26 +1,94 @@
-use actix_web::{post, get, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::{InternalError, JsonPayloadError, PayloadError, QueryPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{delete, patch, post, put, get, web, App, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder};
+use anyhow::Context;
+use clap::Parser;
 use serde::Serialize;
 use serde_json::json;
-use validator::Validate;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{OpenApi, ToSchema};
+use validator::{Validate, ValidateArgs};
 
-mod bin_packing;
-mod model;
+use neighbor::auth::{self, AdminToken, AuthError};
+use neighbor::bin_packing::{self, LocationIndex, SearchOptions};
+use neighbor::bookings::{Booking, BookingError, BookingRequest, BookingStore};
+use neighbor::cli::{self as offline, CatalogCommand, SearchArgs};
+use neighbor::compute::{ComputeError, ComputePool, Computed};
+use neighbor::config::{Config, Overrides};
+use neighbor::errors::ApiError;
+use neighbor::fx::FxTable;
+use neighbor::health::{self, Liveness, Readiness};
+use neighbor::idempotency::{self, Begin, IdempotencyStore};
+use neighbor::locations::Locations;
+use neighbor::logging::{self, LevelHandle, LogLevel};
+use neighbor::metrics::{self, Metrics, SearchWork, Searched};
+use neighbor::model::{
+    BatchSearchItem, BatchSearchResult, Limits, Listing, ListingPatch, Page, PossibleSpace, SearchParams, SearchQuery,
+    SearchRequest, SearchResponse, Vehicle,
+};
+use neighbor::money::Currency;
+use neighbor::quote::{self, Quote, QuoteRequest};
+use neighbor::rate_limit::{self, Decision, RateLimiter};
+use neighbor::reload;
+use neighbor::search::{self as versioned, CursorError};
+use neighbor::sqlite::{Database, SqliteStore};
+use neighbor::store::{Backend, CatalogError, JsonFileStore, ListingStore, Reload};
+use std::collections::HashSet;
+use std::future::{ready, Ready};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::Instrument;
 
 #[cfg(test)]
 mod tests;
 
-use crate::model::{AllListings, SearchRequest, Vehicle};
-
+/// Finds parking spots for vehicles
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// TOML config file [default: neighbor.toml if it exists]
+    #[arg(long, short)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    overrides: Overrides,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-const IP_ADDRESS: &str = "0.0.0.0";
-const PORT: u16 = 8080; 
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run the HTTP server, the default
+    Serve,
+    /// Run a search against a catalog file without starting the server
+    Search(SearchArgs),
+    /// Lint or summarize a catalog file
+    Catalog {
+        #[command(subcommand)]
+        command: CatalogCommand,
+    },
+    /// Print the OpenAPI document, `openapi.json` is this output
+    Openapi,
+    /// Ask the running server if it is healthy, exits with 1 if not. For container health checks.
+    Healthcheck {
+        /// `/readyz` to check readiness instead
+        #[arg(long, default_value = "/healthz")]
+        path: String,
+    },
+}
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 struct ServiceHealth {
     is_healthy: bool,
     message: String,
 } 
 
+#[utoipa::path(responses((status = 200, body = ServiceHealth)))]
 #[get("/")]
 async fn index() -> impl Responder {
     HttpResponse::Ok().json(ServiceHealth {
@@ -29,34 +97,1159 @@ async fn index() -> impl Responder {
     })
 }
 
+/// Liveness, answers while the server can handle requests
+#[utoipa::path(responses((status = 200, body = Liveness)))]
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(Liveness::default())
+}
+
+/// Readiness, whether the catalog is loaded and searches can be answered
+#[utoipa::path(responses((status = 200, body = Readiness), (status = 503, description = "Not ready", body = Readiness)))]
+#[get("/readyz")]
+async fn readyz(listings: web::Data<dyn ListingStore>) -> impl Responder {
+    let readiness = Readiness::of(listings.as_ref());
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    HttpResponse::build(status).json(readiness)
+}
+
+/// Find the cheapest combination per location
+#[utoipa::path(
+    request_body = SearchRequest,
+    params(SearchParams, Page),
+    responses(
+        (status = 200, description = "Cheapest first", body = Vec<PossibleSpace>, headers(
+            ("X-Next-Cursor" = String, description = "Cursor for the next page, when paging and there is one"),
+            ("X-Total-Count" = usize, description = "Number of results, when paging and it is known"),
+        )),
+        (status = 400, body = ApiError),
+        (status = 410, description = "The cursor expired", body = ApiError),
+        (status = 429, description = "Rate limited, retry after `Retry-After` seconds", body = ApiError),
+        (status = 503, description = "Too many searches waiting or the search timed out, retry after `Retry-After` seconds", body = ApiError),
+    )
+)]
 #[post("/search")]
-async fn search(request: web::Json<SearchRequest>) -> impl Responder {
+#[allow(clippy::too_many_arguments)] // actix extractors
+async fn search(
+    request: web::Json<SearchRequest>,
+    params: web::Query<SearchParams>,
+    page: web::Query<Page>,
+    listings: web::Data<dyn ListingStore>,
+    bookings: web::Data<BookingStore>,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+    limits: web::Data<Limits>,
+    compute: web::Data<ComputePool>,
+) -> impl Responder {
+    let request = request.into_inner();
+
+    let valid = tracing::info_span!("validate")
+        .in_scope(|| request.validate_with_args(&limits).and(params.validate()).and(page.validate()));
+    if let Err(e) = valid {
+        return error(StatusCode::BAD_REQUEST, ApiError::validation(&e));
+    }
+    let options = match search_options(&params, &bookings, locations, fx) {
+        Ok(options) => options,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    let vehicles: Vec<Vehicle> = request.into();
+    let page = page.into_inner();
+    let snapshot = listings.snapshot();
+    if page.is_empty() {
+        let computed = compute
+            .run(move || {
+                let results = bin_packing::search_locations_with(vehicles, &snapshot.listings, &options);
+                (results, options.stats.subsets())
+            })
+            .await;
+        let Computed { value: (results, subsets), waited } = match computed {
+            Ok(computed) => computed,
+            Err(e) => return compute_error(e),
+        };
+        let work = Searched { subsets, results: results.len() };
+        return searched(serialize(&mut HttpResponse::Ok(), results), vec![work], waited);
+    }
+
+    let query = SearchQuery {
+        vehicles,
+        filters: Default::default(),
+        sort: Default::default(),
+        limit: page.limit,
+        offset: page.offset.unwrap_or(0),
+        cursor: page.cursor,
+        options: params.into_inner(),
+    };
+    let computed = compute
+        .run(move || (versioned::search(query, &snapshot, &options), options.stats.subsets()))
+        .await;
+    let Computed { value: (response, subsets), waited } = match computed {
+        Ok(computed) => computed,
+        Err(e) => return compute_error(e),
+    };
+    match response {
+        Ok(response) => {
+            let work = Searched { subsets, results: response.results.len() };
+            let mut builder = HttpResponse::Ok();
+            if let Some(total) = response.total {
+                builder.insert_header(("X-Total-Count", total.to_string()));
+            }
+            if let Some(cursor) = &response.next_cursor {
+                builder.insert_header(("X-Next-Cursor", cursor.as_str()));
+            }
+            searched(serialize(&mut builder, response.results), vec![work], waited)
+        }
+        Err(e) => cursor_error(e),
+    }
+}
+
+/// Object form of `/search` with filters, sorting and paging
+#[utoipa::path(
+    request_body = SearchQuery,
+    responses(
+        (status = 200, body = SearchResponse),
+        (status = 400, body = ApiError),
+        (status = 410, description = "The cursor expired", body = ApiError),
+        (status = 429, description = "Rate limited, retry after `Retry-After` seconds", body = ApiError),
+        (status = 503, description = "Too many searches waiting or the search timed out, retry after `Retry-After` seconds", body = ApiError),
+    )
+)]
+#[post("/v1/search")]
+async fn search_v1(
+    request: web::Json<SearchQuery>,
+    listings: web::Data<dyn ListingStore>,
+    bookings: web::Data<BookingStore>,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+    limits: web::Data<Limits>,
+    compute: web::Data<ComputePool>,
+) -> impl Responder {
+    let query = request.into_inner();
+
+    if let Err(e) = tracing::info_span!("validate").in_scope(|| query.validate_with_args(&limits)) {
+        return error(StatusCode::BAD_REQUEST, ApiError::validation(&e));
+    }
+    let options = match search_options(&query.options, &bookings, locations, fx) {
+        Ok(options) => SearchOptions { max_listings: query.filters.max_listings, ..options },
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+
+    let snapshot = listings.snapshot();
+    let computed = compute
+        .run(move || (versioned::search(query, &snapshot, &options), options.stats.subsets()))
+        .await;
+    let Computed { value: (response, subsets), waited } = match computed {
+        Ok(computed) => computed,
+        Err(e) => return compute_error(e),
+    };
+    match response {
+        Ok(response) => {
+            let work = Searched { subsets, results: response.results.len() };
+            searched(serialize(&mut HttpResponse::Ok(), response), vec![work], waited)
+        }
+        Err(e) => cursor_error(e),
+    }
+}
+
+fn cursor_error(e: CursorError) -> HttpResponse {
+    match e {
+        CursorError::Expired => error(StatusCode::GONE, ApiError::new("cursor_expired", "Cursor expired", e.to_string())),
+        CursorError::Malformed | CursorError::OtherSearch => {
+            error(StatusCode::BAD_REQUEST, ApiError::new("invalid_cursor", "Invalid cursor", e.to_string()))
+        }
+    }
+}
+
+/// Search for many fleets at once. Each item gets its own results or error,
+/// a bad item doesn't fail the rest of the batch.
+#[utoipa::path(
+    request_body = Vec<BatchSearchItem>,
+    params(SearchParams),
+    responses(
+        (status = 200, body = Vec<BatchSearchResult>),
+        (status = 400, body = ApiError),
+        (status = 429, description = "Rate limited, retry after `Retry-After` seconds", body = ApiError),
+        (status = 503, description = "Too many searches waiting or the search timed out, retry after `Retry-After` seconds", body = ApiError),
+    )
+)]
+#[post("/search/batch")]
+#[allow(clippy::too_many_arguments)] // actix extractors
+async fn search_batch(
+    request: web::Json<Vec<serde_json::Value>>,
+    params: web::Query<SearchParams>,
+    listings: web::Data<dyn ListingStore>,
+    bookings: web::Data<BookingStore>,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+    limits: web::Data<Limits>,
+    compute: web::Data<ComputePool>,
+) -> impl Responder {
+    let items = request.into_inner();
+
+    if items.len() > limits.max_batch_size {
+        let details = format!("A batch can have at most {} searches, got {}", limits.max_batch_size, items.len());
+        return error(StatusCode::BAD_REQUEST, ApiError::new("batch_too_large", "Batch too large", details));
+    }
+    if let Err(e) = params.validate() {
+        return error(StatusCode::BAD_REQUEST, ApiError::validation(&e));
+    }
+    let options = match search_options(&params, &bookings, locations, fx) {
+        Ok(options) => options,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+
+    let catalog = listings.listings();
+    let limits = *limits.into_inner();
+    let computed = compute
+        .run(move || {
+            // Group and price the listings once for the whole batch
+            let location_index = LocationIndex::new(&catalog, &options);
+            let mut ids = HashSet::new();
+            let mut work = Vec::new();
+            let results: Vec<BatchSearchResult> = items
+                .into_iter()
+                .map(|item| {
+                    let subsets = options.stats.subsets();
+                    let result = search_batch_item(item, &location_index, &options, &limits, &mut ids);
+                    if let BatchSearchResult::Found { results, .. } = &result {
+                        work.push(Searched { subsets: options.stats.subsets() - subsets, results: results.len() });
+                    }
+                    result
+                })
+                .collect();
+            (results, work)
+        })
+        .await;
+    match computed {
+        Ok(Computed { value: (results, work), waited }) => searched(serialize(&mut HttpResponse::Ok(), results), work, waited),
+        Err(e) => compute_error(e),
+    }
+}
+
+fn search_batch_item(
+    item: serde_json::Value,
+    location_index: &LocationIndex,
+    options: &SearchOptions,
+    limits: &Limits,
+    ids: &mut HashSet<String>,
+) -> BatchSearchResult {
+    let id = item.get("id").cloned().unwrap_or_default();
+    let item: BatchSearchItem = match serde_json::from_value(item) {
+        Ok(item) => item,
+        Err(e) => return BatchSearchResult::Failed { id, error: ApiError::json(&e) },
+    };
+    if !ids.insert(item.id.clone()) {
+        let details = format!("{} is used by an earlier search in the batch", item.id);
+        let error = ApiError::new("duplicate_id", "Duplicate id", details);
+        return BatchSearchResult::Failed { id: item.id.into(), error };
+    }
+    if let Err(e) = item.vehicles.validate_with_args(limits) {
+        let error = ApiError::validation(&e);
+        return BatchSearchResult::Failed { id: item.id.into(), error };
+    }
+
+    let results = bin_packing::search_index(item.vehicles.into(), location_index, options);
+    BatchSearchResult::Found { id: item.id, results }
+}
+
+/// Search options from the query params, shared by every search endpoint
+fn search_options(
+    params: &SearchParams,
+    bookings: &BookingStore,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+) -> Result<SearchOptions, ApiError> {
+    let currency = supported_currency(params.currency, &fx)?;
+    let period = params.period.range();
+    Ok(SearchOptions {
+        excluded_listing_ids: bookings.booked_listing_ids(period.as_ref(), chrono::Utc::now().date_naive()),
+        period,
+        locations: locations.into_inner(),
+        include_fees_and_taxes: params.include_fees_and_taxes,
+        currency,
+        fx: fx.into_inner(),
+        max_listings: None,
+        stats: Default::default(),
+    })
+}
+
+/// Search results can be large, time turning them into JSON
+fn serialize(builder: &mut HttpResponseBuilder, body: impl Serialize) -> HttpResponse {
+    tracing::info_span!("serialize").in_scope(|| builder.json(body))
+}
+
+/// Leave what the searches did for the metrics middleware
+fn searched(mut response: HttpResponse, searches: Vec<Searched>, waited: Duration) -> HttpResponse {
+    response.extensions_mut().insert(SearchWork { searches, waited });
+    response
+}
+
+/// The search couldn't run, 503 when it should be retried. The error is left
+/// for the metrics middleware to count.
+fn compute_error(e: ComputeError) -> HttpResponse {
+    let mut response = match e {
+        ComputeError::Busy => HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "1"))
+            .json(ApiError::new("overloaded", "Overloaded", e.to_string())),
+        ComputeError::TimedOut => HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "1"))
+            .json(ApiError::new("timed_out", "Timed out", e.to_string())),
+        ComputeError::Failed => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::new("internal_error", "Internal error", e.to_string()),
+        ),
+    };
+    response.extensions_mut().insert(e);
+    response
+}
+
+/// Itemized price for a combination
+#[utoipa::path(
+    request_body = QuoteRequest,
+    responses(
+        (status = 200, body = Quote),
+        (status = 400, body = ApiError),
+        (status = 422, description = "The listings can't be quoted together", body = ApiError),
+    )
+)]
+#[post("/quote")]
+async fn create_quote(
+    request: web::Json<QuoteRequest>,
+    listings: web::Data<dyn ListingStore>,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+) -> impl Responder {
     let request = request.into_inner();
 
     if let Err(e) = request.validate() {
-        return HttpResponse::BadRequest().json(json!({
-            "error": "Validation failed",
-            "details": e.to_string()
-        }));
+        return error(StatusCode::BAD_REQUEST, ApiError::validation(&e));
     }
+    let currency = match supported_currency(request.currency, &fx) {
+        Ok(currency) => currency,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
 
-    let vehicles: Vec<Vehicle> = request.into();
-    // Listings already got loaded so they are instant now...
-    let listings = AllListings::get(); 
-    let results = bin_packing::search_locations(vehicles, listings.inner());
-    HttpResponse::Ok().json(results)
+    let period = request.period.range();
+    let quote = quote::select_listings(&request.location_id, &request.listing_ids, period.as_ref(), &listings.listings())
+        .and_then(|selected| {
+            let location = locations.settings_for(&request.location_id);
+            Ok(Quote::new(&request.location_id, &selected, period.as_ref(), location, &fx, currency)?)
+        });
+    match quote {
+        Ok(quote) => HttpResponse::Ok().json(quote),
+        Err(e) => error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::new("quote_rejected", "Quote rejected", e.to_string()),
+        ),
+    }
+}
+
+/// Reserve a combination
+#[utoipa::path(
+    request_body = BookingRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe, a retry gets the first response")),
+    responses(
+        (status = 201, body = Booking),
+        (status = 400, body = ApiError),
+        (status = 409, description = "Already booked, or a request with the key is in progress", body = ApiError),
+        (status = 422, description = "The booking can't be made, or the key was used for another request", body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[post("/bookings")]
+#[allow(clippy::too_many_arguments)] // actix extractors
+async fn create_booking(
+    http_request: HttpRequest,
+    request: web::Json<BookingRequest>,
+    listings: web::Data<dyn ListingStore>,
+    bookings: web::Data<BookingStore>,
+    locations: web::Data<Locations>,
+    fx: web::Data<FxTable>,
+    limits: web::Data<Limits>,
+    idempotency_keys: web::Data<IdempotencyStore>,
+) -> impl Responder {
+    let request = request.into_inner();
+
+    let key = http_request
+        .headers()
+        .get(idempotency::HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let Some(key) = key else {
+        let (status, body) = book(request, listings.as_ref(), &bookings, &locations, &fx, &limits);
+        return HttpResponse::build(status).json(body);
+    };
+
+    let fingerprint = serde_json::to_string(&request).unwrap_or_default();
+    match idempotency_keys.begin(&key, &fingerprint) {
+        Begin::New => {
+            let claim = idempotency_keys.claim(&key);
+            let (status, body) = book(request, listings.as_ref(), &bookings, &locations, &fx, &limits);
+            claim.complete(status.as_u16(), body.clone());
+            HttpResponse::build(status).json(body)
+        }
+        Begin::Replay { status, body } => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+            HttpResponse::build(status)
+                .insert_header(("Idempotent-Replayed", "true"))
+                .json(body)
+        }
+        Begin::InProgress => error(
+            StatusCode::CONFLICT,
+            ApiError::new(
+                "request_in_progress",
+                "Request in progress",
+                format!("A request with {} {key} is still being processed", idempotency::HEADER),
+            ),
+        ),
+        Begin::Mismatch => error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::new(
+                "idempotency_key_reused",
+                "Idempotency key reused",
+                format!("{} {key} was already used for a different request", idempotency::HEADER),
+            ),
+        ),
+    }
+}
+
+/// Create the booking, returning the status and body so they can be replayed
+fn book(
+    request: BookingRequest,
+    listings: &dyn ListingStore,
+    bookings: &BookingStore,
+    locations: &Locations,
+    fx: &FxTable,
+    limits: &Limits,
+) -> (StatusCode, serde_json::Value) {
+    if let Err(e) = request.validate_with_args(limits) {
+        return (StatusCode::BAD_REQUEST, json!(ApiError::validation(&e)));
+    }
+    if let Err(e) = supported_currency(request.currency, fx) {
+        return (StatusCode::BAD_REQUEST, json!(e));
+    }
+
+    let (status, error) = match bookings.create(request, listings, locations, fx) {
+        Ok(booking) => return (StatusCode::CREATED, json!(booking)),
+        Err(e @ (BookingError::AlreadyBooked { .. } | BookingError::Contention)) => {
+            (StatusCode::CONFLICT, ApiError::new("booking_conflict", "Booking conflict", e.to_string()))
+        }
+        Err(e @ BookingError::Storage(_)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", "Storage error", e.to_string()))
+        }
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, ApiError::new("booking_rejected", "Booking rejected", e.to_string())),
+    };
+    (status, json!(error))
+}
+
+/// The requested currency, or the base currency, if there is an exchange rate for it
+fn supported_currency(currency: Option<Currency>, fx: &FxTable) -> Result<Currency, ApiError> {
+    match currency {
+        None => Ok(fx.base()),
+        Some(currency) if fx.supports(currency) => Ok(currency),
+        Some(currency) => Err(ApiError::new(
+            "unsupported_currency",
+            "Unsupported currency",
+            format!(
+                "No exchange rate for {currency}, supported currencies are {}",
+                fx.currencies().iter().map(Currency::to_string).collect::<Vec<_>>().join(", ")
+            ),
+        )),
+    }
+}
+
+#[utoipa::path(responses((status = 200, body = Vec<Booking>)))]
+#[get("/bookings")]
+async fn list_bookings(bookings: web::Data<BookingStore>) -> impl Responder {
+    HttpResponse::Ok().json(bookings.all())
+}
+
+#[utoipa::path(responses((status = 200, body = Booking), (status = 404, body = ApiError)))]
+#[get("/bookings/{id}")]
+async fn get_booking(id: web::Path<String>, bookings: web::Data<BookingStore>) -> impl Responder {
+    match bookings.get(&id) {
+        Some(booking) => HttpResponse::Ok().json(booking),
+        None => booking_not_found(&id),
+    }
+}
+
+#[utoipa::path(
+    responses((status = 200, body = Booking), (status = 404, body = ApiError), (status = 500, body = ApiError))
+)]
+#[delete("/bookings/{id}")]
+async fn cancel_booking(id: web::Path<String>, bookings: web::Data<BookingStore>) -> impl Responder {
+    match bookings.cancel(&id) {
+        Ok(Some(booking)) => HttpResponse::Ok().json(booking),
+        Ok(None) => booking_not_found(&id),
+        Err(e) => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::new("storage_error", "Storage error", e.to_string()),
+        ),
+    }
+}
+
+fn booking_not_found(id: &str) -> HttpResponse {
+    error(
+        StatusCode::NOT_FOUND,
+        ApiError::new("not_found", "Not found", format!("Booking {id} does not exist")),
+    )
+}
+
+#[utoipa::path(
+    request_body = Listing,
+    security(("admin_token" = [])),
+    responses(
+        (status = 201, body = Listing),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 409, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[post("/listings")]
+async fn create_listing(
+    _admin: Admin,
+    listing: web::Json<Listing>,
+    listings: web::Data<dyn ListingStore>,
+) -> impl Responder {
+    match listings.insert(listing.into_inner()) {
+        Ok(listing) => HttpResponse::Created().json(listing),
+        Err(e) => catalog_error(e),
+    }
+}
+
+#[utoipa::path(responses((status = 200, body = Listing), (status = 404, body = ApiError)))]
+#[get("/listings/{id}")]
+async fn get_listing(id: web::Path<String>, listings: web::Data<dyn ListingStore>) -> impl Responder {
+    match listings.get(&id) {
+        Some(listing) => HttpResponse::Ok().json(listing),
+        None => catalog_error(CatalogError::NotFound(id.into_inner())),
+    }
+}
+
+/// Replace every field of a listing, create new ones with `POST /listings`
+#[utoipa::path(
+    request_body = Listing,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Listing),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[put("/listings/{id}")]
+async fn replace_listing(
+    _admin: Admin,
+    id: web::Path<String>,
+    listing: web::Json<Listing>,
+    listings: web::Data<dyn ListingStore>,
+) -> impl Responder {
+    let listing = listing.into_inner();
+    if listing.id != *id {
+        return catalog_error(CatalogError::IdMismatch(listing.id));
+    }
+    match listings.replace(listing) {
+        Ok(listing) => HttpResponse::Ok().json(listing),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Change some fields of a listing, the rest keep their value
+#[utoipa::path(
+    request_body = ListingPatch,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Listing),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[patch("/listings/{id}")]
+async fn update_listing(
+    _admin: Admin,
+    id: web::Path<String>,
+    patch: web::Json<ListingPatch>,
+    listings: web::Data<dyn ListingStore>,
+) -> impl Responder {
+    match listings.update(&id, patch.into_inner()) {
+        Ok(listing) => HttpResponse::Ok().json(listing),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Listings with current or future bookings can't be deleted
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Listing),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 409, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[delete("/listings/{id}")]
+async fn delete_listing(
+    _admin: Admin,
+    id: web::Path<String>,
+    listings: web::Data<dyn ListingStore>,
+    bookings: web::Data<BookingStore>,
+) -> impl Responder {
+    match bookings.unless_booked(&id, chrono::Utc::now().date_naive(), || listings.delete(&id)) {
+        Ok(Ok(listing)) => HttpResponse::Ok().json(listing),
+        Ok(Err(e)) => catalog_error(e),
+        Err(_) => {
+            let details = format!("Listing {id} has bookings that haven't ended");
+            error(StatusCode::CONFLICT, ApiError::new("listing_booked", "Listing is booked", details))
+        }
+    }
+}
+
+/// Swap in the listings from the file or database, keeping the current ones if they are invalid
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = Reload),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 422, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[post("/admin/reload")]
+async fn reload_listings(_admin: Admin, listings: web::Data<dyn ListingStore>) -> impl Responder {
+    match listings.reload() {
+        Ok(reload) => HttpResponse::Ok().json(reload),
+        Err(e) => catalog_error(e),
+    }
+}
+
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses((status = 200, body = LogLevel), (status = 401, body = ApiError), (status = 403, body = ApiError))
+)]
+#[get("/admin/log-level")]
+async fn get_log_level(_admin: Admin, level: web::Data<LevelHandle>) -> impl Responder {
+    HttpResponse::Ok().json(level.get())
+}
+
+/// Change what gets logged without a restart
+#[utoipa::path(
+    request_body = LogLevel,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, body = LogLevel),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+    )
+)]
+#[put("/admin/log-level")]
+async fn set_log_level(_admin: Admin, request: web::Json<LogLevel>, level: web::Data<LevelHandle>) -> impl Responder {
+    match level.set(&request.level) {
+        Ok(level) => {
+            tracing::info!(level = level.level, "Changed the log level");
+            HttpResponse::Ok().json(level)
+        }
+        Err(e) => error(StatusCode::BAD_REQUEST, ApiError::invalid_field("level", "invalid_log_level", e.to_string())),
+    }
+}
+
+fn catalog_error(e: CatalogError) -> HttpResponse {
+    let details = e.to_string();
+    let (status, body) = match e {
+        CatalogError::NotFound(_) => (StatusCode::NOT_FOUND, ApiError::new("not_found", "Not found", details)),
+        CatalogError::DuplicateId(_) => {
+            (StatusCode::CONFLICT, ApiError::new("listing_conflict", "Listing conflict", details))
+        }
+        CatalogError::IdMismatch(_) => {
+            (StatusCode::BAD_REQUEST, ApiError::invalid_field("id", "id_does_not_match_path", details))
+        }
+        CatalogError::Invalid(errors) => (StatusCode::BAD_REQUEST, ApiError::validation(&errors)),
+        CatalogError::Storage(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, ApiError::new("storage_error", "Storage error", details))
+        }
+        CatalogError::Rejected(_) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, ApiError::new("reload_rejected", "Reload rejected", details))
+        }
+    };
+    error(status, body)
+}
+
+/// The OpenAPI document for every endpoint, generated from the handlers and model types
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Finds parking spots for vehicles"),
+    paths(
+        index,
+        healthz,
+        readyz,
+        search,
+        search_batch,
+        search_v1,
+        create_quote,
+        create_booking,
+        list_bookings,
+        get_booking,
+        cancel_booking,
+        create_listing,
+        get_listing,
+        replace_listing,
+        update_listing,
+        delete_listing,
+        reload_listings,
+        get_log_level,
+        set_log_level,
+    )
+)]
+struct ApiDoc;
+
+fn openapi() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    // Filled from Cargo.toml, which has no license
+    doc.info.license = None;
+    let token = SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build());
+    doc.components.get_or_insert_with(Default::default).add_security_scheme("admin_token", token);
+    doc
+}
+
+/// The same document that is committed as `openapi.json`
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(openapi())
+}
+
+/// Prometheus metrics
+#[get("/metrics")]
+async fn metrics_text(
+    metrics: web::Data<Metrics>,
+    listings: web::Data<dyn ListingStore>,
+    compute: web::Data<ComputePool>,
+) -> impl Responder {
+    metrics.catalog(&listings.snapshot());
+    metrics.compute(&compute);
+    HttpResponse::Ok().content_type(metrics::CONTENT_TYPE).body(metrics.render())
+}
+
+/// Run the request in a span with its id, which is also sent back
+async fn request_span(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let header = request.headers().get(logging::REQUEST_ID).and_then(|value| value.to_str().ok());
+    let id = logging::request_id(header);
+    let span = tracing::info_span!(
+        "request",
+        request_id = id.as_str(),
+        method = request.method().as_str(),
+        path = request.path(),
+        route = Empty,
+        status = Empty,
+    );
+    let mut response = next.call(request).instrument(span.clone()).await?;
+    span.record("route", response.request().match_pattern().as_deref().unwrap_or("unmatched"));
+    span.record("status", response.status().as_u16());
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    Ok(response)
+}
+
+/// Health checks and scrapes, never rate limited
+const UNLIMITED: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+/// The largest JSON body, `JsonConfig`'s default
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// Charge the client what the request costs, or turn it away with 429
+async fn rate_limit(
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let limiter = request.app_data::<web::Data<RateLimiter>>().cloned();
+    let route = request.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let Some(limiter) = limiter.filter(|_| !UNLIMITED.contains(&route.as_str())) else {
+        return Ok(next.call(request).await?.map_into_boxed_body());
+    };
+
+    let cost = match request_cost(&mut request, &route).await {
+        Ok(cost) => cost,
+        Err(response) => return Ok(request.into_response(response)),
+    };
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+    let forwarded_for = request
+        .headers()
+        .get_all(rate_limit::FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let forwarded_for = Some(forwarded_for.as_str()).filter(|hops| !hops.is_empty());
+    let peer = request.peer_addr().map(|addr| addr.ip());
+    let ip = limiter.client_ip(peer, header(rate_limit::FLY_CLIENT_IP), forwarded_for);
+    let client = limiter.client(header(rate_limit::API_KEY), ip);
+    let Some(decision) = limiter.check(&client, &route, cost, Instant::now()) else {
+        return Ok(next.call(request).await?.map_into_boxed_body());
+    };
+
+    let mut response = if decision.allowed {
+        next.call(request).await?.map_into_boxed_body()
+    } else if decision.over_limit() {
+        let details = format!(
+            "This request costs {}, more than the {} a minute allowed, make it smaller",
+            decision.cost, decision.limit
+        );
+        let body = ApiError::new("cost_over_limit", "Request costs too much", details);
+        request.into_response(error(StatusCode::BAD_REQUEST, body))
+    } else {
+        let details = format!(
+            "This request costs {} of the {} a minute allowed, {} are left",
+            decision.cost, decision.limit, decision.remaining
+        );
+        let body = ApiError::new("rate_limited", "Too many requests", details);
+        request.into_response(error(StatusCode::TOO_MANY_REQUESTS, body))
+    };
+    rate_limit_headers(response.headers_mut(), &decision);
+    Ok(response)
+}
+
+/// The `RateLimit` headers from the IETF draft, and `Retry-After` when turned away
+fn rate_limit_headers(headers: &mut actix_web::http::header::HeaderMap, decision: &Decision) {
+    let values = [
+        ("ratelimit-limit", Some(decision.limit.to_string())),
+        ("ratelimit-remaining", Some(decision.remaining.to_string())),
+        ("ratelimit-reset", Some(decision.reset.to_string())),
+        ("ratelimit-policy", Some(format!("{};w=60", decision.limit))),
+        ("retry-after", decision.retry_after.map(|seconds| seconds.to_string())),
+    ];
+    for (name, value) in values {
+        if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+/// What the request costs. Searches are priced from their body, which is put back for the handler.
+async fn request_cost(request: &mut ServiceRequest, route: &str) -> Result<u32, HttpResponse> {
+    if request.method() != Method::POST || !matches!(route, "/search" | "/search/batch" | "/v1/search") {
+        return Ok(1);
+    }
+    let invalid = |e: actix_web::Error| {
+        error(StatusCode::BAD_REQUEST, ApiError::new("invalid_request", "Invalid request", e.to_string()))
+    };
+    let payload = request.extract::<web::Payload>().await.map_err(invalid)?;
+    let body = match payload.to_bytes_limited(MAX_BODY).await {
+        Ok(body) => body.map_err(invalid)?,
+        Err(e) => {
+            let body = ApiError::new("payload_too_large", "Payload too large", e.to_string());
+            return Err(error(StatusCode::PAYLOAD_TOO_LARGE, body));
+        }
+    };
+
+    let max_vehicles = request
+        .app_data::<web::Data<Limits>>()
+        .map_or(Limits::default().max_vehicles, |limits| limits.max_vehicles);
+    let params = web::Query::<SearchParams>::from_query(request.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let cost = |vehicles: &[Vehicle], params: &SearchParams| rate_limit::search_cost(vehicles, params, max_vehicles);
+    // Anything that doesn't parse costs 1, the handler turns it away
+    let total = match route {
+        "/v1/search" => serde_json::from_slice::<SearchQuery>(&body)
+            .map_or(1, |query| cost(&query.vehicles, &query.options)),
+        "/search/batch" => serde_json::from_slice::<Vec<serde_json::Value>>(&body).map_or(1, |items| {
+            items
+                .into_iter()
+                .map(|item| {
+                    serde_json::from_value::<BatchSearchItem>(item)
+                        .map_or(1, |item| cost(&item.vehicles.vehicles, &params))
+                })
+                .fold(0, u32::saturating_add)
+        }),
+        _ => serde_json::from_slice::<SearchRequest>(&body).map_or(1, |body| cost(&body.vehicles, &params)),
+    };
+    request.set_payload(body.into());
+    Ok(total)
+}
+
+/// Count every request by its route, and time the searches
+async fn record_metrics(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let metrics = request.app_data::<web::Data<Metrics>>().cloned();
+    let response = next.call(request).await?;
+    if let Some(metrics) = metrics {
+        let route = response.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        metrics.request(&route, response.request().method().as_str(), response.status().as_u16());
+        if let Some(work) = response.response().extensions().get::<SearchWork>() {
+            metrics.search(&route, started.elapsed(), work);
+        }
+        if let Some(&e) = response.response().extensions().get::<ComputeError>() {
+            metrics.compute_error(e);
+        }
+    }
+    Ok(response)
+}
+
+fn error(status: StatusCode, error: ApiError) -> HttpResponse {
+    HttpResponse::build(status).json(error)
+}
+
+/// Bodies that aren't JSON, are too big or don't fit the request
+fn json_error(e: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let (status, body) = match &e {
+        JsonPayloadError::ContentType => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::new("unsupported_media_type", "Unsupported content type", "Send the body as application/json"),
+        ),
+        JsonPayloadError::OverflowKnownLength { .. }
+        | JsonPayloadError::Overflow { .. }
+        | JsonPayloadError::Payload(PayloadError::Overflow) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, ApiError::new("payload_too_large", "Payload too large", e.to_string()))
+        }
+        JsonPayloadError::Deserialize(json) => (StatusCode::BAD_REQUEST, ApiError::json(json)),
+        _ => (StatusCode::BAD_REQUEST, ApiError::new("invalid_request", "Invalid request", e.to_string())),
+    };
+    InternalError::from_response(e, error(status, body)).into()
+}
+
+fn query_error(e: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let body = ApiError::new("invalid_query", "Invalid query", e.to_string());
+    InternalError::from_response(e, error(StatusCode::BAD_REQUEST, body)).into()
+}
+
+async fn route_not_found(request: HttpRequest) -> HttpResponse {
+    let details = format!("No route for {} {}", request.method(), request.path());
+    error(StatusCode::NOT_FOUND, ApiError::new("not_found", "Not found", details))
+}
+
+/// Handlers that take this only run for requests with the admin token.
+/// Put it first so a bad body can't be reported before a missing token.
+struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let authorization = request.headers().get(auth::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        let checked = match request.app_data::<web::Data<AdminToken>>() {
+            Some(token) => token.check(authorization),
+            None => Err(AuthError::Disabled),
+        };
+        ready(checked.map(|_| Admin).map_err(|e| {
+            let response = match e {
+                AuthError::Disabled => {
+                    error(StatusCode::FORBIDDEN, ApiError::new("admin_disabled", "Admin endpoints are off", e.to_string()))
+                }
+                AuthError::Missing | AuthError::Invalid => {
+                    let mut response = error(StatusCode::UNAUTHORIZED, ApiError::new("unauthorized", "Unauthorized", e.to_string()));
+                    response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                    response
+                }
+            };
+            InternalError::from_response(e, response).into()
+        }))
+    }
+}
+
+/// Give extractor failures and unknown routes the same error body as the handlers
+fn errors(config: &mut web::ServiceConfig) {
+    config
+        .app_data(web::JsonConfig::default().error_handler(json_error))
+        .app_data(web::QueryConfig::default().error_handler(query_error))
+        .default_service(web::to(route_not_found));
+}
+
+fn write_openapi(out: &mut impl std::io::Write) -> anyhow::Result<()> {
+    writeln!(out, "{}", openapi().to_pretty_json()?)?;
+    Ok(())
+}
+
+/// Open the configured listing store and the bookings saved alongside it
+fn open_storage(config: &Config) -> anyhow::Result<(Arc<dyn ListingStore>, BookingStore)> {
+    let listings_file = &config.listings_file;
+    match config.backend() {
+        Backend::Json => {
+            let (listings, report) = JsonFileStore::open_with(listings_file, config.load_mode)?;
+            tracing::info!(file = %listings_file.display(), %report, "Checked the listings");
+            Ok((Arc::new(listings), BookingStore::default()))
+        }
+        Backend::Sqlite { path } => {
+            let database = Arc::new(Database::open(&path)?);
+            if database.listings()?.is_empty() {
+                let report = database.import_listings(listings_file, config.load_mode)?;
+                tracing::info!(file = %listings_file.display(), %report, "Checked the listings");
+                tracing::info!(
+                    file = %listings_file.display(),
+                    database = %path.display(),
+                    listings = report.valid,
+                    "Imported the listings"
+                );
+            }
+            let bookings = BookingStore::persistent(database.clone(), database.bookings()?);
+            Ok((Arc::new(SqliteStore::open(database)?), bookings))
+        }
+    }
+}
+
+/// Load the location settings and the exchange rates, a missing file means none
+fn load_settings(config: &Config) -> anyhow::Result<(Locations, FxTable)> {
+    let locations = Locations::load(&config.locations_file)
+        .with_context(|| format!("Invalid locations file {}", config.locations_file.display()))?;
+    let fx = FxTable::load(&config.fx_file)
+        .with_context(|| format!("Invalid exchange rates file {}", config.fx_file.display()))?;
+    Ok((locations, fx))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Starting server at http://{}:{}", IP_ADDRESS, PORT);
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref(), cli.overrides, |name| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let offline = match &cli.command {
+        None | Some(Command::Serve) => None,
+        Some(Command::Search(args)) => Some(offline::search(args, &config, &mut std::io::stdout().lock()).map(|_| true)),
+        Some(Command::Catalog { command }) => Some(offline::catalog(command, &config, &mut std::io::stdout().lock())),
+        Some(Command::Openapi) => Some(write_openapi(&mut std::io::stdout().lock()).map(|_| true)),
+        Some(Command::Healthcheck { path }) => Some(health::probe(&config, path)),
+    };
+    match offline {
+        None => {}
+        Some(Ok(true)) => return Ok(()),
+        Some(Ok(false)) => std::process::exit(1),
+        // Piping into `head` closes stdout early, that isn't a failure
+        Some(Err(e)) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => {
+            return Ok(());
+        }
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+
+    let log_level = match logging::init(&config.log_level) {
+        Ok(handle) => web::Data::new(handle),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    tracing::info!(%config, "Configuration");
+    tracing::info!(host = config.host, port = config.port, "Starting server");
 
     // Load the listings on server start up...
     // They are probably gonna time me based on API response time so I will preload now.
-    let _ = AllListings::get(); 
+    let (listings, bookings) = match open_storage(&config) {
+        Ok(storage) => storage,
+        Err(e) => {
+            // The report lists every problem, log it whole instead of panicking
+            tracing::error!(error = %e, "Could not open the listings");
+            std::process::exit(1);
+        }
+    };
+    // Kept alive for as long as the server runs
+    let _watcher = match config.backend() {
+        Backend::Json => reload::watch(listings.clone(), &config.listings_file)
+            .inspect_err(|e| tracing::warn!(file = %config.listings_file.display(), error = %e, "Not watching for changes"))
+            .ok(),
+        Backend::Sqlite { .. } => None,
+    };
+    let listings = web::Data::from(listings);
+    let bookings = web::Data::new(bookings);
+    let (locations, fx) = match load_settings(&config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!(error = %format!("{e:#}"), "Could not load the settings");
+            std::process::exit(1);
+        }
+    };
+    let locations = web::Data::new(locations);
+    let fx = web::Data::new(fx);
+    if let Err(e) = fx.check_listings(&listings.listings()) {
+        tracing::error!(error = %e, file = %config.fx_file.display(), "Missing exchange rates");
+        std::process::exit(1);
+    }
+    let limits = web::Data::new(config.limits());
+    let idempotency_keys = web::Data::new(IdempotencyStore::default());
+    let metrics = web::Data::new(Metrics::new());
+    let compute = web::Data::new(
+        ComputePool::new(config.compute_threads, config.compute_queue)
+            .with_timeout(Duration::from_secs(config.compute_timeout)),
+    );
+    let rate_limiter = web::Data::new(
+        RateLimiter::new(config.rate_limit, config.route_limits.clone())
+            .with_api_keys(config.api_keys.clone())
+            .with_trusted_proxies(config.trusted_proxies.clone()),
+    );
+    // Buckets that refilled are dropped off the request path
+    let evicting = rate_limiter.clone();
+    actix_web::rt::spawn(async move {
+        let mut every = actix_web::rt::time::interval(rate_limit::WINDOW);
+        loop {
+            every.tick().await;
+            evicting.evict(Instant::now());
+        }
+    });
+    let admin_token = web::Data::new(AdminToken::new(config.admin_token.clone()));
+    if config.admin_token.is_none() {
+        tracing::info!("No admin_token, listing edits and /admin are off");
+    }
 
-    HttpServer::new(|| App::new().service(index).service(search))
-        .bind((IP_ADDRESS, PORT))?
+    HttpServer::new(move || {
+        App::new()
+            .app_data(listings.clone())
+            .app_data(locations.clone())
+            .app_data(fx.clone())
+            .app_data(limits.clone())
+            .app_data(bookings.clone())
+            .app_data(idempotency_keys.clone())
+            .app_data(metrics.clone())
+            .app_data(compute.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(admin_token.clone())
+            .wrap(from_fn(rate_limit))
+            .app_data(log_level.clone())
+            .wrap(from_fn(record_metrics))
+            .wrap(from_fn(request_span))
+            .configure(errors)
+            .service(index)
+            .service(healthz)
+            .service(readyz)
+            .service(search)
+            .service(search_batch)
+            .service(search_v1)
+            .service(create_quote)
+            .service(create_booking)
+            .service(list_bookings)
+            .service(get_booking)
+            .service(cancel_booking)
+            .service(create_listing)
+            .service(get_listing)
+            .service(replace_listing)
+            .service(update_listing)
+            .service(delete_listing)
+            .service(reload_listings)
+            .service(get_log_level)
+            .service(set_log_level)
+            .service(openapi_json)
+            .service(metrics_text)
+    })
+        .workers(config.workers)
+        .bind((config.host.as_str(), config.port))?
         .run()
         .await
 }
@@ -0,0 +1,185 @@
+//! # Metrics
+//!
+//! Counters and histograms for Prometheus, served as text at `/metrics`.
+//!
+//! Requests are counted by route pattern rather than path, so ids in the path
+//! don't make a new series each. Search handlers leave a `SearchWork` in the
+//! response extensions and the middleware records it with the time the request
+//! took, and compute errors the same way so searches turned away are counted.
+//! The catalog and compute queue gauges are read on every scrape.
+
+use crate::compute::{ComputeError, ComputePool};
+use crate::store::Snapshot;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The content type of `render`
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// What the searches behind one response did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchWork {
+    /// One per search, a batch has one per item
+    pub searches: Vec<Searched>,
+    /// Time spent waiting for a compute thread
+    pub waited: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Searched {
+    /// Combinations of listings tried
+    pub subsets: u64,
+    pub results: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    search_duration: HistogramVec,
+    subsets: Histogram,
+    results: Histogram,
+    catalog_listings: IntGauge,
+    catalog_version: IntGauge,
+    catalog_changed: IntGauge,
+    compute_queued: IntGauge,
+    compute_wait: Histogram,
+    compute_rejected: IntCounterVec,
+    compute_abandoned: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("neighbor_http_requests_total", "HTTP requests by route, method and status"),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let search_duration = HistogramVec::new(
+            HistogramOpts::new("neighbor_search_duration_seconds", "Time to answer a search request"),
+            &["endpoint"],
+        )
+        .unwrap();
+        let subsets = Histogram::with_opts(
+            HistogramOpts::new("neighbor_search_subsets_evaluated", "Combinations of listings tried per search")
+                .buckets(exponential_buckets(1.0, 4.0, 12).unwrap()),
+        )
+        .unwrap();
+        let results = Histogram::with_opts(
+            HistogramOpts::new("neighbor_search_results", "Results returned per search")
+                .buckets(exponential_buckets(1.0, 2.0, 10).unwrap()),
+        )
+        .unwrap();
+        let catalog_listings = IntGauge::new("neighbor_catalog_listings", "Listings in the catalog").unwrap();
+        let catalog_version = IntGauge::new("neighbor_catalog_version", "Version of the catalog").unwrap();
+        let catalog_changed = IntGauge::new(
+            "neighbor_catalog_changed_timestamp_seconds",
+            "Unix time the catalog was last loaded, reloaded or edited",
+        )
+        .unwrap();
+
+        let compute_queued =
+            IntGauge::new("neighbor_compute_queue_depth", "Searches waiting for a compute thread").unwrap();
+        let compute_wait = Histogram::with_opts(
+            HistogramOpts::new("neighbor_compute_queue_wait_seconds", "Time a search waited for a compute thread")
+                .buckets(exponential_buckets(0.0005, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let compute_rejected = IntCounterVec::new(
+            Opts::new("neighbor_compute_rejected_total", "Searches turned away by the compute pool, by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let compute_abandoned = IntCounter::new(
+            "neighbor_compute_abandoned_total",
+            "Searches skipped because the client stopped waiting before a thread was free",
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(search_duration.clone())).unwrap();
+        registry.register(Box::new(subsets.clone())).unwrap();
+        registry.register(Box::new(results.clone())).unwrap();
+        registry.register(Box::new(catalog_listings.clone())).unwrap();
+        registry.register(Box::new(catalog_version.clone())).unwrap();
+        registry.register(Box::new(catalog_changed.clone())).unwrap();
+        registry.register(Box::new(compute_queued.clone())).unwrap();
+        registry.register(Box::new(compute_wait.clone())).unwrap();
+        registry.register(Box::new(compute_rejected.clone())).unwrap();
+        registry.register(Box::new(compute_abandoned.clone())).unwrap();
+
+        Self {
+            registry,
+            requests,
+            search_duration,
+            subsets,
+            results,
+            catalog_listings,
+            catalog_version,
+            catalog_changed,
+            compute_queued,
+            compute_wait,
+            compute_rejected,
+            compute_abandoned,
+        }
+    }
+
+    pub fn request(&self, route: &str, method: &str, status: u16) {
+        self.requests
+            .with_label_values(&[route, method, &status.to_string()])
+            .inc();
+    }
+
+    pub fn search(&self, endpoint: &str, took: Duration, work: &SearchWork) {
+        self.search_duration
+            .with_label_values(&[endpoint])
+            .observe(took.as_secs_f64());
+        self.compute_wait.observe(work.waited.as_secs_f64());
+        for searched in &work.searches {
+            self.subsets.observe(searched.subsets as f64);
+            self.results.observe(searched.results as f64);
+        }
+    }
+
+    pub fn catalog(&self, snapshot: &Snapshot) {
+        let changed_at = snapshot.changed_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.catalog_listings.set(snapshot.listings.len() as i64);
+        self.catalog_version.set(snapshot.version as i64);
+        self.catalog_changed.set(changed_at.as_secs() as i64);
+    }
+
+    pub fn compute(&self, pool: &ComputePool) {
+        self.compute_queued.set(pool.queued() as i64);
+        // The pool keeps the count, catch the counter up with it
+        let abandoned = pool.abandoned();
+        self.compute_abandoned.inc_by(abandoned.saturating_sub(self.compute_abandoned.get()));
+    }
+
+    /// A search the pool turned away. Failures are already counted as 500s.
+    pub fn compute_error(&self, e: ComputeError) {
+        let reason = match e {
+            ComputeError::Busy => "busy",
+            ComputeError::TimedOut => "timed_out",
+            ComputeError::Failed => return,
+        };
+        self.compute_rejected.with_label_values(&[reason]).inc();
+    }
+
+    /// Everything in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Only fails for metrics that break the format, these don't
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
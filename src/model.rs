@@ -1,42 +1,223 @@
-use lazy_static::lazy_static;
+use crate::availability::{self, DateRange};
+use crate::discounts::AppliedDiscount;
+use crate::errors::ApiError;
+use crate::money::{Currency, Money, MoneyError};
+use crate::pricing::Pricing;
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
-use std::fs;
-use validator::{Validate, ValidationError};
+use utoipa::openapi::schema::{ArrayBuilder, Schema};
+use utoipa::openapi::{path, ObjectBuilder, Ref, RefOr, Type};
+use utoipa::{IntoParams, PartialSchema, ToSchema};
+use validator::{Validate, ValidationError, ValidationErrors};
 
-lazy_static! {
-    static ref ALL_LISTINGS: AllListings = AllListings::load() 
-        .expect("Missing listings.json config file!");
+fn validate_dimension(dimension: i32) -> Result<(), ValidationError> {
+    if dimension <= 0 || dimension % 10 != 0 {
+        return Err(ValidationError::new("must_be_a_positive_multiple_of_10"));
+    }
+    Ok(())
 }
 
-pub struct AllListings(Vec<Listing>);
-
-impl AllListings {
-    pub fn load() -> anyhow::Result<Self> {
-        let data = fs::read_to_string("listings.json")?;
-        let listings: Vec<Listing> = serde_json::from_str(&data)?;
-        Ok(Self(listings))
-    }
+fn validate_windows(windows: &[DateRange]) -> Result<(), ValidationError> {
+    windows.iter().try_for_each(availability::validate_date_range)
+}
 
-    /// Get the singleton
-    pub fn get() -> &'static AllListings {
-        &ALL_LISTINGS
+fn validate_not_empty(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        let mut e = ValidationError::new("length");
+        e.add_param("min".into(), &1);
+        return Err(e);
     }
+    Ok(())
+}
 
-    pub fn inner(&'static self) -> &'static [Listing] {
-        &self.0
+fn validate_price(price: &Money) -> Result<(), ValidationError> {
+    if price.amount_in_cents < 1 {
+        let mut e = ValidationError::new("range");
+        e.add_param("min".into(), &1.0);
+        return Err(e);
     }
+    Ok(())
 }
 
 /// A listing for a parking location
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Listing {
     pub id: String,
     pub location_id: String,
-    /// Multiple of 10
     pub length: i32,
-    /// Multiple of 10
     pub width: i32,
-    pub price_in_cents: i32,
+    /// Monthly price, in the currency of every price on the listing.
+    /// `price_in_cents` and `currency` in JSON.
+    #[serde(flatten, with = "ListingPrice")]
+    pub price: Money,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub availability: Vec<DateRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<Pricing>,
+}
+
+/// How a listing's price is written: `price_in_cents`, and a `currency`
+/// that is USD when left out
+#[derive(Serialize, Deserialize)]
+struct ListingPrice {
+    price_in_cents: i64,
+    #[serde(default, skip_serializing_if = "Currency::is_usd")]
+    currency: Currency,
+}
+
+impl ListingPrice {
+    fn serialize<S: serde::Serializer>(price: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        Self { price_in_cents: price.amount_in_cents, currency: price.currency }.serialize(serializer)
+    }
+
+    fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let Self { price_in_cents, currency } = Deserialize::deserialize(deserializer)?;
+        Ok(Money::new(price_in_cents, currency))
+    }
+}
+
+/// A listing for a parking location
+#[derive(Serialize, ToSchema)]
+#[schema(as = Listing)]
+#[allow(dead_code)] // Only here for its schema, the price fields side by side as they are sent
+struct ListingJson {
+    #[schema(min_length = 1)]
+    id: String,
+    #[schema(min_length = 1)]
+    location_id: String,
+    /// Multiple of 10
+    #[schema(minimum = 10, multiple_of = 10)]
+    length: i32,
+    /// Multiple of 10
+    #[schema(minimum = 10, multiple_of = 10)]
+    width: i32,
+    /// Monthly price, shown for searches without dates
+    #[schema(minimum = 1)]
+    price_in_cents: i64,
+    /// Currency of every price on the listing
+    #[serde(default)]
+    currency: Currency,
+    /// When the listing can be rented. Empty means always available.
+    #[serde(default)]
+    availability: Vec<DateRange>,
+    /// Daily, weekly and monthly rates used to quote a period.
+    /// Defaults to `price_in_cents` per month.
+    #[serde(default)]
+    pricing: Option<Pricing>,
+}
+
+impl PartialSchema for Listing {
+    fn schema() -> RefOr<Schema> {
+        ListingJson::schema()
+    }
+}
+
+impl ToSchema for Listing {
+    fn name() -> std::borrow::Cow<'static, str> {
+        ListingJson::name()
+    }
+
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        ListingJson::schemas(schemas);
+    }
+}
+
+/// Written out instead of derived so problems with `price` are reported
+/// under `price_in_cents`, the name clients send
+impl Validate for Listing {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let checks = [
+            ("id", validate_not_empty(&self.id)),
+            ("location_id", validate_not_empty(&self.location_id)),
+            ("length", validate_dimension(self.length)),
+            ("width", validate_dimension(self.width)),
+            ("price_in_cents", validate_price(&self.price)),
+            ("availability", validate_windows(&self.availability)),
+        ];
+        for (field, check) in checks {
+            if let Err(e) = check {
+                errors.add(field, e);
+            }
+        }
+        if let Some(pricing) = &self.pricing {
+            errors.merge_self("pricing", pricing.validate());
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Listing {
+    pub fn is_available_for(&self, period: &DateRange) -> bool {
+        availability::covers(&self.availability, period)
+    }
+
+    pub fn pricing(&self) -> Pricing {
+        match &self.pricing {
+            Some(pricing) if pricing.has_rates() => pricing.clone(),
+            _ => Pricing::monthly(self.price.amount_in_cents),
+        }
+    }
+
+    /// Cheapest quote for renting the listing over the period
+    pub fn price_for_period(&self, period: &DateRange) -> Result<Money, MoneyError> {
+        let quote = self.pricing().quote(period.days())?;
+        Ok(Money::new(quote, self.price.currency))
+    }
+}
+
+/// Body of `PATCH /listings/{id}`, fields that are left out keep their value
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ListingPatch {
+    pub location_id: Option<String>,
+    pub length: Option<i32>,
+    pub width: Option<i32>,
+    pub price_in_cents: Option<i64>,
+    pub currency: Option<Currency>,
+    pub availability: Option<Vec<DateRange>>,
+    /// `null` removes the pricing rules
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Pricing>, nullable)]
+    pub pricing: Option<Option<Pricing>>,
+}
+
+/// Tell a field set to `null` apart from one that was left out
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl ListingPatch {
+    pub fn apply(self, listing: &mut Listing) {
+        if let Some(location_id) = self.location_id {
+            listing.location_id = location_id;
+        }
+        if let Some(length) = self.length {
+            listing.length = length;
+        }
+        if let Some(width) = self.width {
+            listing.width = width;
+        }
+        if let Some(price_in_cents) = self.price_in_cents {
+            listing.price.amount_in_cents = price_in_cents;
+        }
+        if let Some(currency) = self.currency {
+            listing.price.currency = currency;
+        }
+        if let Some(availability) = self.availability {
+            listing.availability = availability;
+        }
+        if let Some(pricing) = self.pricing {
+            listing.pricing = pricing;
+        }
+    }
 }
 
 fn validate_length(length: i32) -> Result<(), ValidationError> {
@@ -47,11 +228,13 @@ fn validate_length(length: i32) -> Result<(), ValidationError> {
 }
 
 /// A single vehicle with an inferred width
-#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct Vehicle {
     #[validate(custom(function = "validate_length"))]
+    #[schema(minimum = 1)]
     pub length: i32,
     #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
     pub quantity: i32,
 }
 
@@ -59,10 +242,31 @@ impl Vehicle {
     pub const WIDTH: i32 = 10; 
 }
 
-fn validate_total_quantity(vehicles: &[Vehicle]) -> Result<(), ValidationError> {
-    let total: i32 = vehicles.iter().map(|v| v.quantity).sum();
-    if total > 5 {
-        return Err(ValidationError::new("total_quantity_exceeds_5"));
+/// Request limits that come from the server configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Most vehicles a single search or booking can ask for
+    pub max_vehicles: i32,
+    /// Most searches in one `/search/batch` request
+    pub max_batch_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_vehicles: 5,
+            max_batch_size: 100,
+        }
+    }
+}
+
+pub fn validate_total_quantity(vehicles: &[Vehicle], limits: &Limits) -> Result<(), ValidationError> {
+    // In i64, quantities near i32::MAX would overflow the sum
+    let total = vehicles.iter().fold(0i64, |total, v| total.saturating_add(i64::from(v.quantity)));
+    if total > i64::from(limits.max_vehicles) {
+        // Named when the limit was always 5, clients match on codes so it stays
+        return Err(ValidationError::new("total_quantity_exceeds_5")
+            .with_message(format!("At most {} vehicles are allowed", limits.max_vehicles).into()));
     }
     Ok(())
 }
@@ -71,20 +275,251 @@ fn validate_total_quantity(vehicles: &[Vehicle]) -> Result<(), ValidationError>
 /// I flatten it using transparent
 #[derive(Debug, Serialize, Deserialize, Validate)]
 #[serde(transparent)]
+#[validate(context = Limits)]
 pub struct SearchRequest {
-    #[validate(length(min = 1), nested, custom(function = "validate_total_quantity"))]
+    #[validate(length(min = 1), nested, custom(function = "validate_total_quantity", use_context))]
     pub vehicles: Vec<Vehicle>,
 }
 
+/// The derive can't see through `transparent`, describe the array by hand
+impl PartialSchema for SearchRequest {
+    fn schema() -> RefOr<Schema> {
+        ArrayBuilder::new()
+            .items(Ref::from_schema_name(Vehicle::name()))
+            .min_items(Some(1))
+            .description(Some("The vehicles to park, at most `max_vehicles` of them in total (5 by default)"))
+            .into()
+    }
+}
+
+impl ToSchema for SearchRequest {
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        schemas.push((Vehicle::name().into(), Vehicle::schema()));
+    }
+}
+
+/// One fleet in a `/search/batch` request, the id is sent back with its results
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchSearchItem {
+    pub id: String,
+    pub vehicles: SearchRequest,
+}
+
+/// Answer for one `/search/batch` item, in the order they were sent
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum BatchSearchResult {
+    Found {
+        id: String,
+        results: Vec<PossibleSpace>,
+    },
+    Failed {
+        /// Whatever the item had as its id, if anything
+        #[schema(value_type = Option<String>)]
+        id: serde_json::Value,
+        #[serde(flatten)]
+        error: ApiError,
+    },
+}
+
 impl From<SearchRequest> for Vec<Vehicle> {
     fn from(request: SearchRequest) -> Self {
         request.vehicles
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn validate_search_period(period: &SearchPeriod) -> Result<(), ValidationError> {
+    match (period.start_date, period.end_date) {
+        (Some(start_date), Some(end_date)) => {
+            availability::validate_date_range(&DateRange::new(start_date, end_date))
+        }
+        (None, None) => Ok(()),
+        _ => Err(ValidationError::new("start_date_and_end_date_required_together")),
+    }
+}
+
+/// Optional rental period, passed as query params so the body stays an array
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, ToSchema, IntoParams)]
+#[validate(schema(function = "validate_search_period"))]
+pub struct SearchPeriod {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+impl SearchPeriod {
+    pub fn range(&self) -> Option<DateRange> {
+        match (self.start_date, self.end_date) {
+            (Some(start_date), Some(end_date)) => Some(DateRange::new(start_date, end_date)),
+            _ => None,
+        }
+    }
+}
+
+/// Query params for `/search`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, ToSchema)]
+pub struct SearchParams {
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub period: SearchPeriod,
+    /// Rank and report totals with the service fee and tax included
+    #[serde(default)]
+    pub include_fees_and_taxes: bool,
+    /// Currency to compare and show prices in, the exchange rate base by default
+    pub currency: Option<Currency>,
+}
+
+/// Body of `/v1/search`. Unlike `/search` everything is in one object so
+/// there is room for more than the vehicles.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(context = Limits, schema(function = "validate_query_page"))]
+pub struct SearchQuery {
+    /// At most `max_vehicles` in total (5 by default)
+    #[validate(length(min = 1), nested, custom(function = "validate_total_quantity", use_context))]
+    #[schema(min_items = 1)]
+    pub vehicles: Vec<Vehicle>,
+    #[serde(default)]
+    #[validate(nested)]
+    pub filters: SearchFilters,
+    #[serde(default)]
+    pub sort: SearchSort,
+    /// Return at most this many results, all of them by default
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub limit: Option<usize>,
+    /// Skip this many results
+    #[serde(default)]
+    pub offset: usize,
+    /// Continue from the page that returned this `next_cursor` instead of `offset`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// The same options `/search` takes as query params
+    #[serde(default)]
+    #[validate(nested)]
+    pub options: SearchParams,
+}
+
+fn validate_query_page(query: &SearchQuery) -> Result<(), ValidationError> {
+    validate_page(&Page {
+        limit: query.limit,
+        offset: Some(query.offset).filter(|&offset| offset > 0),
+        cursor: query.cursor.clone(),
+    })
+}
+
+fn validate_page(page: &Page) -> Result<(), ValidationError> {
+    if page.cursor.is_some() && page.offset.is_some() {
+        return Err(ValidationError::new("offset_and_cursor_not_allowed_together"));
+    }
+    Ok(())
+}
+
+/// Paging query params for `/search`, it still answers with a bare array
+/// and sends the cursor for the next page in the `X-Next-Cursor` header
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, IntoParams)]
+#[validate(schema(function = "validate_page"))]
+pub struct Page {
+    /// Return at most this many results, all of them by default
+    #[validate(range(min = 1))]
+    #[param(minimum = 1)]
+    pub limit: Option<usize>,
+    /// Skip this many results
+    pub offset: Option<usize>,
+    /// The `X-Next-Cursor` of the previous page, instead of `offset`
+    pub cursor: Option<String>,
+}
+
+/// `period` is flattened into the query, which the derive can't do
+impl IntoParams for SearchParams {
+    fn into_params(parameter_in: impl Fn() -> Option<path::ParameterIn>) -> Vec<path::Parameter> {
+        let mut params = SearchPeriod::into_params(&parameter_in);
+        params.push(
+            path::ParameterBuilder::new()
+                .name("include_fees_and_taxes")
+                .parameter_in(parameter_in().unwrap_or_default())
+                .description(Some("Rank and report totals with the service fee and tax included"))
+                .schema(Some(ObjectBuilder::new().schema_type(Type::Boolean).default(Some(false.into()))))
+                .build(),
+        );
+        params.push(
+            path::ParameterBuilder::new()
+                .name("currency")
+                .parameter_in(parameter_in().unwrap_or_default())
+                .description(Some("Currency to compare and show prices in, the exchange rate base by default"))
+                .schema(Some(Currency::schema()))
+                .build(),
+        );
+        params
+    }
+}
+
+impl Page {
+    pub fn is_empty(&self) -> bool {
+        self.limit.is_none() && self.offset.is_none() && self.cursor.is_none()
+    }
+}
+
+/// Narrow down the results of `/v1/search`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SearchFilters {
+    /// Only search these locations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location_ids: Option<Vec<String>>,
+    /// Leave out locations that cost more than this, in the search currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 0))]
+    #[schema(minimum = 0)]
+    pub max_total_in_cents: Option<i64>,
+    /// Leave out combinations that need more listings than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub max_listings: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSort {
+    /// Cheapest first, like `/search`
+    #[default]
+    Price,
+    /// Most expensive first
+    PriceDesc,
+    /// Fewest listings to manage first, then cheapest
+    FewestListings,
+}
+
+/// Response of `/v1/search`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchResponse {
+    /// The page of results after `offset` and `limit`
+    pub results: Vec<PossibleSpace>,
+    /// Results matching the filters, before `offset` and `limit`. Left out
+    /// when a `limit` let the search stop before finding all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    /// Pass it as `cursor` to get the next page, left out on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    pub took_ms: f64,
+    /// Version of the catalog that was searched, it changes with every edit or reload
+    pub catalog_version: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PossibleSpace {
     pub location_id: String,
     pub listing_ids: Vec<String>,
-    pub total_price_in_cents: i32,
+    pub total_price_in_cents: i64,
+    #[serde(default, skip_serializing_if = "Currency::is_usd")]
+    pub currency: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discount: Option<AppliedDiscount>,
+}
+
+impl PossibleSpace {
+    pub fn total(&self) -> Money {
+        Money::new(self.total_price_in_cents, self.currency)
+    }
 }
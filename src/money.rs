@@ -0,0 +1,210 @@
+//! # Money
+//!
+//! Amounts are kept in the minor unit of their currency (cents for USD) as an `i64`.
+//! Every operation is checked, so an absurdly expensive combination is an error
+//! instead of silently wrapping around to a cheap one.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::openapi::schema::Schema;
+use utoipa::openapi::{ObjectBuilder, RefOr, Type};
+use utoipa::{PartialSchema, ToSchema};
+
+/// ISO 4217 currency code such as `USD`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or("???")
+    }
+
+    pub fn is_usd(&self) -> bool {
+        *self == Self::USD
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self::USD
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 3] = code
+            .as_bytes()
+            .try_into()
+            .map_err(|_| MoneyError::InvalidCurrency(code.to_string()))?;
+        if !bytes.iter().all(u8::is_ascii_uppercase) {
+            return Err(MoneyError::InvalidCurrency(code.to_string()));
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({})", self.as_str())
+    }
+}
+
+impl PartialSchema for Currency {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .pattern(Some("^[A-Z]{3}$"))
+            .description(Some("ISO 4217 currency code"))
+            .examples([serde_json::json!("USD")])
+            .into()
+    }
+}
+
+impl ToSchema for Currency {}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    CurrencyMismatch { expected: Currency, found: Currency },
+    InvalidCurrency(String),
+    UnknownRate(Currency),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Price is too large"),
+            Self::CurrencyMismatch { expected, found } => {
+                write!(f, "Can't combine {found} with {expected}")
+            }
+            Self::InvalidCurrency(code) => write!(f, "{code} is not a currency code"),
+            Self::UnknownRate(currency) => write!(f, "No exchange rate for {currency}"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+pub const BASIS_POINTS: i128 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    pub amount_in_cents: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount_in_cents: i64, currency: Currency) -> Self {
+        Self { amount_in_cents, currency }
+    }
+
+    pub fn usd(amount_in_cents: i64) -> Self {
+        Self::new(amount_in_cents, Currency::USD)
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch {
+                expected: self.currency,
+                found: other.currency,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let amount = self.amount_in_cents.checked_add(other.amount_in_cents).ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(amount, self.currency))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let amount = self.amount_in_cents.checked_sub(other.amount_in_cents).ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(amount, self.currency))
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Result<Money, MoneyError> {
+        let amount = self.amount_in_cents.checked_mul(factor).ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(amount, self.currency))
+    }
+
+    /// Scale by `numerator / denominator`, rounding half up to the next cent
+    pub fn checked_ratio(self, numerator: i128, denominator: i128) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::Overflow);
+        }
+        let scaled = (self.amount_in_cents as i128)
+            .checked_mul(numerator)
+            .ok_or(MoneyError::Overflow)?;
+        let rounded = (scaled + denominator / 2).div_euclid(denominator);
+        let amount = i64::try_from(rounded).map_err(|_| MoneyError::Overflow)?;
+        Ok(Self::new(amount, self.currency))
+    }
+
+    /// Scale by `numerator / denominator`, dropping any fraction of a cent
+    pub fn checked_ratio_down(self, numerator: i128, denominator: i128) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::Overflow);
+        }
+        let scaled = (self.amount_in_cents as i128)
+            .checked_mul(numerator)
+            .ok_or(MoneyError::Overflow)?;
+        let amount = i64::try_from(scaled.div_euclid(denominator)).map_err(|_| MoneyError::Overflow)?;
+        Ok(Self::new(amount, self.currency))
+    }
+
+    /// Share of the amount in basis points, 825 is 8.25%
+    pub fn basis_points(self, basis_points: i32) -> Result<Money, MoneyError> {
+        self.checked_ratio(basis_points as i128, BASIS_POINTS)
+    }
+
+    pub fn min(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        Ok(if other.amount_in_cents < self.amount_in_cents { other } else { self })
+    }
+
+    /// Add up amounts that are all in the currency
+    pub fn sum(amounts: impl IntoIterator<Item = Money>, currency: Currency) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), |total, amount| total.checked_add(amount))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.amount_in_cents < 0 { "-" } else { "" };
+        let cents = self.amount_in_cents.unsigned_abs();
+        write!(f, "{sign}{}.{:02} {}", cents / 100, cents % 100, self.currency)
+    }
+}
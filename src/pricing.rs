@@ -0,0 +1,120 @@
+//! # Duration Pricing
+//!
+//! Hosts can price a listing per day, week and month. A quote for a period is
+//! the cheapest mix of those units that covers every day, so renting for
+//! 6 days can cost one week if the weekly rate beats 6 daily rates.
+//!
+//! Listings without a `pricing` block only have a monthly rate: their `price_in_cents`.
+//! Rates are in the listing's currency.
+
+use crate::availability::MAX_DAYS;
+use crate::money::MoneyError;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const DAYS_PER_WEEK: i64 = 7;
+pub const DAYS_PER_MONTH: i64 = 30;
+
+/// How days that don't fill a whole unit are charged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Proration {
+    /// Leftover days pay their share of the smallest unit, rounded up to the next cent
+    #[default]
+    ByDay,
+    /// Leftover days pay for a whole unit
+    WholeUnits,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Pricing {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub daily_in_cents: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub weekly_in_cents: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub monthly_in_cents: Option<i64>,
+    #[serde(default)]
+    pub proration: Proration,
+}
+
+impl Pricing {
+    pub fn monthly(price_in_cents: i64) -> Self {
+        Self {
+            monthly_in_cents: Some(price_in_cents),
+            ..Default::default()
+        }
+    }
+
+    pub fn has_rates(&self) -> bool {
+        !self.units().is_empty()
+    }
+
+    /// (days, price) for every rate the host set, smallest unit first
+    fn units(&self) -> Vec<(i64, i64)> {
+        [
+            (1, self.daily_in_cents),
+            (DAYS_PER_WEEK, self.weekly_in_cents),
+            (DAYS_PER_MONTH, self.monthly_in_cents),
+        ]
+        .into_iter()
+        .filter_map(|(days, rate)| rate.map(|rate| (days, rate)))
+        .collect()
+    }
+
+    /// Cheapest price in cents to cover the number of days
+    pub fn quote(&self, days: i64) -> Result<i64, MoneyError> {
+        let units = self.units();
+        if days <= 0 || units.is_empty() {
+            return Ok(0);
+        }
+        // The table below has a slot per day, validation keeps periods shorter than this
+        if days > MAX_DAYS {
+            return Err(MoneyError::Overflow);
+        }
+        let days = days as usize;
+
+        // whole[d] is the cheapest mix of whole units covering at least d days
+        let mut whole = vec![0i64; days + 1];
+        for d in 1..=days {
+            whole[d] = units
+                .iter()
+                .filter_map(|&(unit_days, rate)| whole[d.saturating_sub(unit_days as usize)].checked_add(rate))
+                .min()
+                .ok_or(MoneyError::Overflow)?;
+        }
+
+        if self.proration == Proration::WholeUnits {
+            return Ok(whole[days]);
+        }
+
+        // Cover some days with whole units and prorate the rest
+        let mut best = whole[days];
+        for (covered, &cents) in whole[..days].iter().enumerate() {
+            let rest = prorate(&units, (days - covered) as i64)?;
+            let price = cents.checked_add(rest).ok_or(MoneyError::Overflow)?;
+            best = best.min(price);
+        }
+        Ok(best)
+    }
+}
+
+/// Charge the days as a share of the smallest unit so a prorated
+/// weekly rate never undercuts the daily rate
+fn prorate(units: &[(i64, i64)], days: i64) -> Result<i64, MoneyError> {
+    let Some(&(unit_days, rate)) = units.first() else {
+        return Ok(0);
+    };
+    let cents = rate
+        .checked_mul(days)
+        .and_then(|cents| cents.checked_add(unit_days - 1))
+        .ok_or(MoneyError::Overflow)?;
+    Ok(cents / unit_days)
+}
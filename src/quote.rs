@@ -0,0 +1,271 @@
+//! # Price Quotes
+//!
+//! Breaks the price of a combination into the line items checkout shows:
+//! the rent for each listing, the bundle discount, the service fee and tax.
+//!
+//! Charges are applied in order. The discount comes off the rent, the service fee
+//! is charged on the discounted rent and tax is charged on both.
+
+use crate::availability::DateRange;
+use crate::discounts::{self, AppliedDiscount};
+use crate::fx::FxTable;
+use crate::locations::Location;
+use crate::model::{Listing, SearchPeriod};
+use crate::money::{Currency, Money, MoneyError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// Fee the platform charges on top of the rent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct ServiceFee {
+    #[serde(default)]
+    #[validate(range(min = 0, max = 10000))]
+    pub percent_bps: i32,
+    /// In the exchange rate base currency
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    pub flat_in_cents: i64,
+}
+
+impl ServiceFee {
+    pub fn amount_for(&self, rent: Money, fx: &FxTable) -> Result<Money, MoneyError> {
+        let flat = fx.from_base(self.flat_in_cents, rent.currency)?;
+        rent.basis_points(self.percent_bps)?.checked_add(flat)
+    }
+}
+
+/// Everything charged on top of (or taken off) the rent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charges {
+    pub subtotal: Money,
+    pub discount: Option<AppliedDiscount>,
+    pub service_fee: Money,
+    pub tax: Money,
+}
+
+impl Charges {
+    pub fn new(location: &Location, subtotal: Money, listing_count: usize, fx: &FxTable) -> Result<Self, MoneyError> {
+        let discount = discounts::best_discount(&location.discounts, listing_count, subtotal, fx)?;
+        let discount_amount = Money::new(discount.as_ref().map_or(0, |d| d.amount_in_cents), subtotal.currency);
+        let discounted = subtotal.checked_sub(discount_amount)?;
+        let service_fee = match &location.service_fee {
+            Some(fee) => fee.amount_for(discounted, fx)?,
+            None => Money::zero(subtotal.currency),
+        };
+        let tax = discounted.checked_add(service_fee)?.basis_points(location.tax_rate_bps)?;
+
+        Ok(Self {
+            subtotal,
+            discount,
+            service_fee,
+            tax,
+        })
+    }
+
+    pub fn discount(&self) -> Money {
+        Money::new(self.discount.as_ref().map_or(0, |d| d.amount_in_cents), self.subtotal.currency)
+    }
+
+    /// Rent after the discount, what the host sets prices in
+    pub fn discounted(&self) -> Result<Money, MoneyError> {
+        self.subtotal.checked_sub(self.discount())
+    }
+
+    /// What the renter actually pays
+    pub fn total(&self) -> Result<Money, MoneyError> {
+        self.discounted()?.checked_add(self.service_fee)?.checked_add(self.tax)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LineItemKind {
+    Listing,
+    Discount,
+    ServiceFee,
+    Tax,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LineItem {
+    pub kind: LineItemKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listing_id: Option<String>,
+    pub description: String,
+    /// Negative for discounts
+    pub amount_in_cents: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Quote {
+    pub location_id: String,
+    pub listing_ids: Vec<String>,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<DateRange>,
+    pub currency: Currency,
+    pub line_items: Vec<LineItem>,
+    pub subtotal_in_cents: i64,
+    pub discount_in_cents: i64,
+    pub service_fee_in_cents: i64,
+    pub tax_rate_bps: i32,
+    pub tax_in_cents: i64,
+    pub total_in_cents: i64,
+}
+
+impl Quote {
+    /// Itemize renting the listings at the location, for the period if there is one,
+    /// in the currency
+    pub fn new(
+        location_id: &str,
+        listings: &[Listing],
+        period: Option<&DateRange>,
+        location: &Location,
+        fx: &FxTable,
+        currency: Currency,
+    ) -> Result<Self, MoneyError> {
+        let mut line_items = Vec::new();
+        for listing in listings {
+            let price = match period {
+                Some(period) => listing.price_for_period(period)?,
+                None => listing.price,
+            };
+            line_items.push(LineItem {
+                kind: LineItemKind::Listing,
+                listing_id: Some(listing.id.clone()),
+                description: format!("{}x{} space", listing.width, listing.length),
+                amount_in_cents: fx.convert(price, currency)?.amount_in_cents,
+            });
+        }
+
+        let subtotal = Money::sum(line_items.iter().map(|item| Money::new(item.amount_in_cents, currency)), currency)?;
+        let charges = Charges::new(location, subtotal, listings.len(), fx)?;
+
+        if let Some(applied) = &charges.discount {
+            line_items.push(LineItem {
+                kind: LineItemKind::Discount,
+                listing_id: None,
+                description: applied
+                    .discount
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("{}+ listing discount", applied.discount.min_listings)),
+                amount_in_cents: -applied.amount_in_cents,
+            });
+        }
+        if charges.service_fee.amount_in_cents > 0 {
+            line_items.push(LineItem {
+                kind: LineItemKind::ServiceFee,
+                listing_id: None,
+                description: "Service fee".to_string(),
+                amount_in_cents: charges.service_fee.amount_in_cents,
+            });
+        }
+        if charges.tax.amount_in_cents > 0 {
+            line_items.push(LineItem {
+                kind: LineItemKind::Tax,
+                listing_id: None,
+                description: format!("Tax ({}%)", location.tax_rate_bps as f64 / 100.0),
+                amount_in_cents: charges.tax.amount_in_cents,
+            });
+        }
+
+        Ok(Self {
+            location_id: location_id.to_string(),
+            listing_ids: listings.iter().map(|l| l.id.clone()).collect(),
+            period: period.copied(),
+            currency,
+            line_items,
+            subtotal_in_cents: subtotal.amount_in_cents,
+            discount_in_cents: charges.discount().amount_in_cents,
+            service_fee_in_cents: charges.service_fee.amount_in_cents,
+            tax_rate_bps: location.tax_rate_bps,
+            tax_in_cents: charges.tax.amount_in_cents,
+            total_in_cents: charges.total()?.amount_in_cents,
+        })
+    }
+
+    pub fn total(&self) -> Money {
+        Money::new(self.total_in_cents, self.currency)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct QuoteRequest {
+    #[validate(length(min = 1))]
+    #[schema(min_length = 1)]
+    pub location_id: String,
+    #[validate(length(min = 1))]
+    #[schema(min_items = 1)]
+    pub listing_ids: Vec<String>,
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub period: SearchPeriod,
+    /// Currency to quote in, the exchange rate base by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuoteError {
+    ListingNotFound(String),
+    WrongLocation(String),
+    DuplicateListing(String),
+    Unavailable(String),
+    Price(MoneyError),
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ListingNotFound(id) => write!(f, "Listing {id} does not exist"),
+            Self::WrongLocation(id) => write!(f, "Listing {id} is not at the requested location"),
+            Self::DuplicateListing(id) => write!(f, "Listing {id} was requested more than once"),
+            Self::Unavailable(id) => write!(f, "Listing {id} is not available for the whole period"),
+            Self::Price(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for QuoteError {}
+
+impl From<MoneyError> for QuoteError {
+    fn from(error: MoneyError) -> Self {
+        Self::Price(error)
+    }
+}
+
+/// Look up the chosen listings, checking they are all at the location and available
+pub fn select_listings(
+    location_id: &str,
+    listing_ids: &[String],
+    period: Option<&DateRange>,
+    catalog: &[Listing],
+) -> Result<Vec<Listing>, QuoteError> {
+    let mut seen = HashSet::new();
+    let mut selected = Vec::new();
+
+    for listing_id in listing_ids {
+        if !seen.insert(listing_id) {
+            return Err(QuoteError::DuplicateListing(listing_id.clone()));
+        }
+
+        let listing = catalog
+            .iter()
+            .find(|l| &l.id == listing_id)
+            .ok_or_else(|| QuoteError::ListingNotFound(listing_id.clone()))?;
+
+        if listing.location_id != location_id {
+            return Err(QuoteError::WrongLocation(listing_id.clone()));
+        }
+        if period.is_some_and(|p| !listing.is_available_for(p)) {
+            return Err(QuoteError::Unavailable(listing_id.clone()));
+        }
+
+        selected.push(listing.clone());
+    }
+
+    Ok(selected)
+}
@@ -0,0 +1,276 @@
+//! # Rate Limiting
+//!
+//! Each client gets a budget of cost units per minute, kept in a token bucket
+//! that refills continuously. A client with one of the configured `api_keys`
+//! in its `X-Api-Key` header gets a bucket of its own, everyone else is told
+//! apart by IP address. Behind a proxy in `trusted_proxies` the address comes
+//! from the proxy's `Fly-Client-IP` or `X-Forwarded-For` header, anyone else
+//! could send those. Routes with their own limit in `route_limits` get their
+//! own bucket, every other route shares one.
+//!
+//! Most requests cost 1. A search costs one unit per vehicle it places, the
+//! `expand_vehicles` count, and each option that adds work multiplies that:
+//! a date range, fees and taxes, or another currency. A batch costs the sum of
+//! its searches. A request that costs more than its limit could never go
+//! through and is turned away outright.
+//!
+//! Buckets that have refilled are no different from new ones, `evict` drops
+//! them. The server calls it once a `WINDOW`.
+
+use crate::model::{SearchParams, Vehicle};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const API_KEY: &str = "X-Api-Key";
+pub const FLY_CLIENT_IP: &str = "Fly-Client-IP";
+pub const FORWARDED_FOR: &str = "X-Forwarded-For";
+/// Cost units per minute for routes without their own limit
+pub const DEFAULT_LIMIT: u32 = 600;
+
+/// A bucket refills completely in this long
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// An IP address or a network such as `10.0.0.0/8` or `fdaa::/16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = network.split_once('/').map_or((network, None), |(addr, prefix)| (addr, Some(prefix)));
+        let addr = IpAddr::from_str(addr)
+            .map_err(|e| format!("Invalid trusted proxy {network}: {e}"))?
+            .to_canonical();
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => bits,
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= bits)
+                .ok_or_else(|| format!("Invalid trusted proxy {network}: the prefix must be 0 to {bits}"))?,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = String;
+
+    fn try_from(network: String) -> Result<Self, Self::Error> {
+        network.parse()
+    }
+}
+
+impl From<Network> for String {
+    fn from(network: Network) -> Self {
+        network.to_string()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix == bits {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+/// The answer for one request, and what goes in its headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// What the request was charged, or would have been
+    pub cost: u32,
+    /// Cost units per minute
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset: u64,
+    /// Seconds until the request would be allowed, when it wasn't.
+    /// None for a request that costs more than the limit.
+    pub retry_after: Option<u64>,
+}
+
+impl Decision {
+    /// Waiting won't help, the request costs more than a full bucket
+    pub fn over_limit(&self) -> bool {
+        self.cost > self.limit
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    default: u32,
+    routes: BTreeMap<String, u32>,
+    api_keys: HashSet<String>,
+    trusted_proxies: Vec<Network>,
+    buckets: DashMap<(String, String), Bucket>,
+}
+
+impl RateLimiter {
+    /// `default` cost units per minute, or per route in `routes`. A limit of 0 is no limit.
+    pub fn new(default: u32, routes: BTreeMap<String, u32>) -> Self {
+        Self {
+            default,
+            routes,
+            api_keys: HashSet::new(),
+            trusted_proxies: Vec::new(),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Clients sending one of `keys` get a budget of their own
+    pub fn with_api_keys(mut self, keys: impl IntoIterator<Item = String>) -> Self {
+        self.api_keys = keys.into_iter().collect();
+        self
+    }
+
+    /// Believe the client address that requests from these proxies say they forward
+    pub fn with_trusted_proxies(mut self, proxies: Vec<Network>) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Who is asking: a configured API key if there is one, the IP address otherwise
+    pub fn client(&self, api_key: Option<&str>, ip: Option<IpAddr>) -> String {
+        match (api_key, ip) {
+            (Some(key), _) if self.api_keys.contains(key) => format!("key:{key}"),
+            (_, Some(ip)) => format!("ip:{ip}"),
+            _ => "unknown".to_string(),
+        }
+    }
+
+    /// The client's address. `peer` is who connected, the headers only count
+    /// when that is a trusted proxy. `forwarded_for` lists every hop, the
+    /// client first, and is read from the right past the trusted proxies.
+    pub fn client_ip(&self, peer: Option<IpAddr>, fly_client_ip: Option<&str>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let peer = peer?.to_canonical();
+        if !self.trusted(peer) {
+            return Some(peer);
+        }
+        if let Some(ip) = fly_client_ip.and_then(|ip| ip.trim().parse::<IpAddr>().ok()) {
+            return Some(ip.to_canonical());
+        }
+        let mut hops = forwarded_for.into_iter().flat_map(|hops| hops.rsplit(','));
+        let mut client = peer;
+        for hop in hops.by_ref() {
+            match hop.trim().parse::<IpAddr>() {
+                Ok(ip) => client = ip.to_canonical(),
+                // Anything past a hop that isn't an address can't be trusted
+                Err(_) => break,
+            }
+            if !self.trusted(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    fn trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|network| network.contains(ip))
+    }
+
+    /// Charge `client` for a request to `route`, None when the route isn't limited
+    pub fn check(&self, client: &str, route: &str, cost: u32, now: Instant) -> Option<Decision> {
+        let (bucket, limit) = match self.routes.get(route) {
+            Some(&limit) => (route, limit),
+            None => ("*", self.default),
+        };
+        if limit == 0 {
+            return None;
+        }
+
+        let capacity = f64::from(limit);
+        let per_second = capacity / WINDOW.as_secs_f64();
+        let cost = cost.max(1);
+        let mut entry = self
+            .buckets
+            .entry((client.to_string(), bucket.to_string()))
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.saturating_duration_since(entry.updated).as_secs_f64();
+        entry.tokens = (entry.tokens + elapsed * per_second).min(capacity);
+        entry.updated = now;
+
+        let allowed = cost <= limit && entry.tokens >= f64::from(cost);
+        let retry_after = if allowed {
+            entry.tokens -= f64::from(cost);
+            None
+        } else if cost > limit {
+            None
+        } else {
+            Some(((f64::from(cost) - entry.tokens) / per_second).ceil() as u64)
+        };
+        Some(Decision {
+            allowed,
+            cost,
+            limit,
+            remaining: entry.tokens.floor() as u32,
+            reset: ((capacity - entry.tokens) / per_second).ceil() as u64,
+            retry_after,
+        })
+    }
+
+    /// Drop the buckets that have refilled since their last request
+    pub fn evict(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < WINDOW);
+    }
+
+    /// Buckets being kept
+    pub fn buckets(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+/// What a search for `vehicles` with `options` costs. Vehicles past
+/// `max_vehicles` aren't counted, validation turns those searches away.
+pub fn search_cost(vehicles: &[Vehicle], options: &SearchParams, max_vehicles: i32) -> u32 {
+    let count = vehicles
+        .iter()
+        .fold(0i64, |count, vehicle| count.saturating_add(i64::from(vehicle.quantity.max(0))))
+        .clamp(1, i64::from(max_vehicles.max(1)));
+    let extra = [
+        options.period.range().is_some(),
+        options.include_fees_and_taxes,
+        options.currency.is_some(),
+    ]
+    .into_iter()
+    .filter(|&option| option)
+    .count();
+    (count as u32).saturating_mul(1 + extra as u32)
+}
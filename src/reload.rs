@@ -0,0 +1,66 @@
+//! # Catalog Reloading
+//!
+//! Watches the listings file and reloads the store when it changes, so
+//! editing `listings.json` no longer needs a restart. `POST /admin/reload`
+//! does the same on demand.
+
+use crate::store::{CatalogError, ListingStore, Reload};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Editors save in several steps, wait for them to finish before reading
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Reload the store whenever the file changes. Watching stops when the watcher is dropped.
+pub fn watch(store: Arc<dyn ListingStore>, path: impl AsRef<Path>) -> anyhow::Result<RecommendedWatcher> {
+    let path = path.as_ref().canonicalize()?;
+    let file_name = path
+        .file_name()
+        .map(OsString::from)
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file", path.display()))?;
+    let directory = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    let (sender, receiver) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // Watch the directory, saving often replaces the file instead of writing to it
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    thread::spawn(move || {
+        while let Ok(event) = receiver.recv() {
+            let Ok(event) = event else { continue };
+            if !changes(&event, &file_name) {
+                continue;
+            }
+            thread::sleep(SETTLE);
+            while receiver.try_recv().is_ok() {}
+
+            report(&path, store.reload());
+        }
+    });
+
+    Ok(watcher)
+}
+
+fn changes(event: &Event, file_name: &OsString) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        && event.paths.iter().any(|p| p.file_name() == Some(file_name.as_os_str()))
+}
+
+fn report(path: &Path, result: Result<Reload, CatalogError>) {
+    let file = path.display().to_string();
+    match result {
+        Ok(Reload { changed: false, .. }) => {}
+        Ok(reload) => {
+            tracing::info!(file, listings = reload.listings, version = reload.version, "Reloaded the listings");
+            if reload.skipped > 0 {
+                tracing::warn!(file, skipped = reload.skipped, "Skipped listings with problems");
+            }
+        }
+        Err(e) => tracing::error!(file, error = %e, "Could not reload the listings"),
+    }
+}
@@ -0,0 +1,195 @@
+//! # Versioned Search
+//!
+//! Runs a `/v1/search` query: the same search as `/search`, then the filters,
+//! the sort order and one page of the results. `/search` keeps its bare array
+//! contract and only comes through here when it is paged.
+//!
+//! Pages after the first are asked for with the cursor the previous page
+//! returned. It remembers the catalog version and the search, so a cursor
+//! stops working once the catalog changes instead of skipping or repeating
+//! results. Versions start over when the server restarts, so the cursor also
+//! remembers which run of the server handed it out.
+
+use crate::bin_packing::{self, LocationIndex, SearchOptions};
+use crate::model::{Listing, PossibleSpace, SearchFilters, SearchQuery, SearchResponse, SearchSort};
+use crate::store::Snapshot;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorError {
+    /// Not a cursor this server handed out
+    Malformed,
+    /// From a search with other vehicles, filters, sort or options
+    OtherSearch,
+    /// The catalog changed since the first page
+    Expired,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "The cursor is not valid"),
+            Self::OtherSearch => write!(f, "The cursor belongs to a different search"),
+            Self::Expired => write!(f, "The catalog changed since the first page, search again without a cursor"),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// Picked at random once per run of the server
+pub fn epoch() -> u32 {
+    static EPOCH: OnceLock<u32> = OnceLock::new();
+    *EPOCH.get_or_init(|| uuid::Uuid::new_v4().as_u128() as u32)
+}
+
+/// Where the next page starts. Clients only see it encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    /// The `epoch` of the run that handed it out
+    pub epoch: u32,
+    pub catalog_version: u64,
+    /// Fingerprint of everything in the query except the paging
+    pub search: u32,
+    pub offset: usize,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        format!(
+            "{:08x}{:016x}{:08x}{:016x}",
+            self.epoch, self.catalog_version, self.search, self.offset as u64
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, CursorError> {
+        let part = |range: std::ops::Range<usize>| {
+            cursor
+                .get(range)
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                .ok_or(CursorError::Malformed)
+        };
+        if cursor.len() != 48 {
+            return Err(CursorError::Malformed);
+        }
+        Ok(Self {
+            epoch: u32::try_from(part(0..8)?).map_err(|_| CursorError::Malformed)?,
+            catalog_version: part(8..24)?,
+            search: u32::try_from(part(24..32)?).map_err(|_| CursorError::Malformed)?,
+            offset: usize::try_from(part(32..48)?).map_err(|_| CursorError::Malformed)?,
+        })
+    }
+}
+
+pub fn search(query: SearchQuery, snapshot: &Snapshot, options: &SearchOptions) -> Result<SearchResponse, CursorError> {
+    let started = Instant::now();
+
+    let search = fingerprint(&query);
+    let offset = match &query.cursor {
+        Some(cursor) => {
+            let cursor = Cursor::decode(cursor)?;
+            if cursor.search != search {
+                return Err(CursorError::OtherSearch);
+            }
+            if cursor.epoch != epoch() || cursor.catalog_version != snapshot.version {
+                return Err(CursorError::Expired);
+            }
+            cursor.offset
+        }
+        None => query.offset,
+    };
+
+    let listings = match &query.filters.location_ids {
+        Some(location_ids) => {
+            let wanted: HashSet<&str> = location_ids.iter().map(String::as_str).collect();
+            Cow::Owned(
+                snapshot
+                    .listings
+                    .iter()
+                    .filter(|listing| wanted.contains(listing.location_id.as_str()))
+                    .cloned()
+                    .collect::<Vec<Listing>>(),
+            )
+        }
+        None => Cow::Borrowed(snapshot.listings.as_slice()),
+    };
+    let index = LocationIndex::new(&listings, options);
+
+    let (results, total) = match (query.sort, query.limit) {
+        // The cheapest results come first anyway, so the search can stop early.
+        // One more than the page tells whether there is another page.
+        (SearchSort::Price, Some(limit)) => {
+            let count = offset.saturating_add(limit).saturating_add(1);
+            let cheapest = bin_packing::search_cheapest(query.vehicles, &index, options, count, |result| {
+                matches(&query.filters, result)
+            });
+            let total = cheapest.complete.then_some(cheapest.results.len());
+            (cheapest.results, total)
+        }
+        _ => {
+            let mut results = bin_packing::search_index(query.vehicles, &index, options);
+            results.retain(|result| matches(&query.filters, result));
+            sort(&mut results, query.sort);
+            let total = results.len();
+            (results, Some(total))
+        }
+    };
+
+    let found = results.len();
+    let results: Vec<PossibleSpace> = results
+        .into_iter()
+        .skip(offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+    let next = offset.saturating_add(results.len());
+    let next_cursor = (next < found).then(|| {
+        Cursor {
+            epoch: epoch(),
+            catalog_version: snapshot.version,
+            search,
+            offset: next,
+        }
+        .encode()
+    });
+
+    Ok(SearchResponse {
+        results,
+        total,
+        next_cursor,
+        took_ms: started.elapsed().as_secs_f64() * 1000.0,
+        catalog_version: snapshot.version,
+    })
+}
+
+/// FNV-1a of the query without its paging, stable across restarts
+fn fingerprint(query: &SearchQuery) -> u32 {
+    let search = (&query.vehicles, &query.filters, query.sort, &query.options);
+    let bytes = serde_json::to_vec(&search).unwrap_or_default();
+    bytes
+        .iter()
+        .fold(0x811c_9dc5, |hash: u32, &byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+}
+
+fn matches(filters: &SearchFilters, result: &PossibleSpace) -> bool {
+    let affordable = filters
+        .max_total_in_cents
+        .is_none_or(|max| result.total_price_in_cents <= max);
+    let small_enough = filters
+        .max_listings
+        .is_none_or(|max| result.listing_ids.len() <= max);
+    affordable && small_enough
+}
+
+/// The search returns the cheapest first, the sorts are stable so ties stay in that order
+fn sort(results: &mut [PossibleSpace], sort: SearchSort) {
+    match sort {
+        SearchSort::Price => {}
+        SearchSort::PriceDesc => results.sort_by_key(|r| Reverse(r.total_price_in_cents)),
+        SearchSort::FewestListings => results.sort_by_key(|r| (r.listing_ids.len(), r.total_price_in_cents)),
+    }
+}
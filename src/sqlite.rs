@@ -0,0 +1,292 @@
+//! # SQLite Storage
+//!
+//! Keeps listings and bookings in an embedded SQLite database so they survive
+//! restarts. The schema is upgraded on open by running every migration newer
+//! than the database's `user_version`.
+//!
+//! Searches still run against an in-memory snapshot of the listings, every
+//! edit is written to the database first and then swapped into the snapshot.
+
+use crate::bookings::{Booking, BookingLog};
+use crate::model::{Listing, ListingPatch};
+use crate::money::Money;
+use crate::store::{CatalogError, ListingStore, MemoryStore, Reload, Snapshot};
+use crate::validation::{self, LoadMode, LoadReport};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Applied in order, a database at `user_version` N has run the first N
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE listings (
+        id TEXT PRIMARY KEY,
+        location_id TEXT NOT NULL,
+        length INTEGER NOT NULL,
+        width INTEGER NOT NULL,
+        price_in_cents INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        availability TEXT NOT NULL,
+        pricing TEXT
+    );
+    CREATE INDEX listings_location_id ON listings (location_id);",
+    "CREATE TABLE bookings (
+        id TEXT PRIMARY KEY,
+        location_id TEXT NOT NULL,
+        start_date TEXT NOT NULL,
+        end_date TEXT NOT NULL,
+        booking TEXT NOT NULL
+    );
+    CREATE INDEX bookings_location_id ON bookings (location_id);",
+];
+
+const LISTING_COLUMNS: &str = "id, location_id, length, width, price_in_cents, currency, availability, pricing";
+
+pub struct Database {
+    connection: Mutex<Connection>,
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::migrate(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut connection: Connection) -> anyhow::Result<Self> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Version of the newest migration that has run
+    pub fn schema_version(&self) -> anyhow::Result<usize> {
+        let connection = self.connection.lock().unwrap();
+        Ok(connection.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+    }
+
+    pub fn listings(&self) -> anyhow::Result<Vec<Listing>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&format!("SELECT {LISTING_COLUMNS} FROM listings ORDER BY rowid"))?;
+        let listings = statement.query_map([], listing_from_row)?.collect::<Result<_, _>>()?;
+        Ok(listings)
+    }
+
+    /// Uses the `location_id` index
+    pub fn listings_at(&self, location_id: &str) -> anyhow::Result<Vec<Listing>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {LISTING_COLUMNS} FROM listings WHERE location_id = ?1 ORDER BY rowid"
+        ))?;
+        let listings = statement
+            .query_map([location_id], listing_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(listings)
+    }
+
+    pub fn listing(&self, id: &str) -> anyhow::Result<Option<Listing>> {
+        let connection = self.connection.lock().unwrap();
+        let listing = connection
+            .query_row(
+                &format!("SELECT {LISTING_COLUMNS} FROM listings WHERE id = ?1"),
+                [id],
+                listing_from_row,
+            )
+            .optional()?;
+        Ok(listing)
+    }
+
+    pub fn save_listing(&self, listing: &Listing) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        insert_listing(&connection, listing)
+    }
+
+    pub fn delete_listing(&self, id: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("DELETE FROM listings WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Copy every valid listing in a `listings.json` style file into the database,
+    /// overwriting listings with the same id. Nothing is imported if strict mode
+    /// finds a problem.
+    pub fn import_listings(&self, path: impl AsRef<Path>, mode: LoadMode) -> anyhow::Result<LoadReport> {
+        let (listings, report) = validation::load_listings(path, mode)?;
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        for listing in &listings {
+            insert_listing(&transaction, listing)?;
+        }
+        transaction.commit()?;
+        Ok(report)
+    }
+
+    pub fn bookings(&self) -> anyhow::Result<Vec<Booking>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT booking FROM bookings ORDER BY start_date, id")?;
+        let rows: Vec<String> = statement.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+        rows.iter()
+            .map(|booking| Ok(serde_json::from_str(booking)?))
+            .collect()
+    }
+}
+
+impl BookingLog for Database {
+    fn record(&self, booking: &Booking) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO bookings (id, location_id, start_date, end_date, booking) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                booking.id,
+                booking.location_id,
+                booking.period.start_date.to_string(),
+                booking.period.end_date.to_string(),
+                serde_json::to_string(booking)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("DELETE FROM bookings WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+fn insert_listing(connection: &Connection, listing: &Listing) -> anyhow::Result<()> {
+    let pricing = listing.pricing.as_ref().map(serde_json::to_string).transpose()?;
+    connection.execute(
+        &format!(
+            "INSERT INTO listings ({LISTING_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (id) DO UPDATE SET
+                location_id = excluded.location_id,
+                length = excluded.length,
+                width = excluded.width,
+                price_in_cents = excluded.price_in_cents,
+                currency = excluded.currency,
+                availability = excluded.availability,
+                pricing = excluded.pricing"
+        ),
+        params![
+            listing.id,
+            listing.location_id,
+            listing.length,
+            listing.width,
+            listing.price.amount_in_cents,
+            listing.price.currency.as_str(),
+            serde_json::to_string(&listing.availability)?,
+            pricing,
+        ],
+    )?;
+    Ok(())
+}
+
+fn conversion_error(index: usize, error: impl std::error::Error + Send + Sync + 'static) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(error))
+}
+
+fn json_column<T: serde::de::DeserializeOwned>(index: usize, text: &str) -> rusqlite::Result<T> {
+    serde_json::from_str(text).map_err(|e| conversion_error(index, e))
+}
+
+fn listing_from_row(row: &Row) -> rusqlite::Result<Listing> {
+    let currency: String = row.get(5)?;
+    let availability: String = row.get(6)?;
+    let pricing: Option<String> = row.get(7)?;
+
+    Ok(Listing {
+        id: row.get(0)?,
+        location_id: row.get(1)?,
+        length: row.get(2)?,
+        width: row.get(3)?,
+        price: Money::new(row.get(4)?, currency.parse().map_err(|e| conversion_error(5, e))?),
+        availability: json_column(6, &availability)?,
+        pricing: pricing.map(|pricing| json_column(7, &pricing)).transpose()?,
+    })
+}
+
+/// Listings in SQLite, searched through an in-memory snapshot
+pub struct SqliteStore {
+    database: Arc<Database>,
+    memory: MemoryStore,
+    /// Held while editing so the database gets edits in the same order as memory
+    writing: Mutex<()>,
+}
+
+impl SqliteStore {
+    pub fn open(database: Arc<Database>) -> anyhow::Result<Self> {
+        let listings = database.listings()?;
+        Ok(Self {
+            database,
+            memory: MemoryStore::new(listings),
+            writing: Mutex::new(()),
+        })
+    }
+
+    /// Edit a copy of the snapshot and write the listing the edit returned
+    /// with `save`. Searches only see the edit once the database has it.
+    fn saved(
+        &self,
+        edit: impl FnOnce(&MemoryStore) -> Result<Listing, CatalogError>,
+        save: impl FnOnce(&Database, &Listing) -> anyhow::Result<()>,
+    ) -> Result<Listing, CatalogError> {
+        let _writing = self.writing.lock().unwrap();
+        let staged = self.memory.staged();
+        let listing = edit(&staged)?;
+        save(&self.database, &listing).map_err(|e| CatalogError::Storage(e.to_string()))?;
+        self.memory.publish(staged.listings());
+        Ok(listing)
+    }
+}
+
+impl ListingStore for SqliteStore {
+    fn snapshot(&self) -> Snapshot {
+        self.memory.snapshot()
+    }
+
+    fn by_location(&self, location_id: &str) -> Vec<Listing> {
+        self.database
+            .listings_at(location_id)
+            .unwrap_or_else(|_| self.memory.by_location(location_id))
+    }
+
+    fn insert(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.insert(listing), Database::save_listing)
+    }
+
+    fn replace(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.replace(listing), Database::save_listing)
+    }
+
+    fn update(&self, id: &str, patch: ListingPatch) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.update(id, patch), Database::save_listing)
+    }
+
+    fn delete(&self, id: &str) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.delete(id), |database, listing| database.delete_listing(&listing.id))
+    }
+
+    /// Pick up listings another process wrote to the database
+    fn reload(&self) -> Result<Reload, CatalogError> {
+        let _writing = self.writing.lock().unwrap();
+        let result = self
+            .database
+            .listings()
+            .map_err(|e| CatalogError::Storage(e.to_string()))
+            .and_then(|listings| self.memory.replace_all(listings));
+        self.memory.reloaded(result)
+    }
+
+    fn reload_error(&self) -> Option<String> {
+        self.memory.reload_error()
+    }
+}
@@ -0,0 +1,399 @@
+//! # Listing Storage
+//!
+//! The catalog lives behind the `ListingStore` trait so the server, the tests
+//! and other storage backends share the same handlers. Reads return snapshots:
+//! edits swap in a new one, so a search that already started keeps the
+//! listings it began with.
+//!
+//! Stores backed by a file or database can `reload` when it changes under them.
+//! The new listings are checked first, a bad edit keeps the current snapshot.
+//!
+//! - `MemoryStore` keeps everything in memory, for tests
+//! - `JsonFileStore` also writes every edit back to `listings.json`, the default
+//! - `SqliteStore` keeps listings and bookings in a database, chosen with
+//!   `storage = "sqlite"` in the configuration
+
+use crate::model::{Listing, ListingPatch};
+use crate::validation::{self, LoadError, LoadMode, LoadReport};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationErrors};
+
+pub const LISTINGS_FILE: &str = "listings.json";
+pub const DEFAULT_DATABASE: &str = "neighbor.db";
+
+/// Where listings and bookings are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// Listings in `listings.json`, bookings only in memory
+    Json,
+    Sqlite { path: PathBuf },
+}
+
+/// The listings at one point in time. The version goes up on every change.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub version: u64,
+    pub listings: Arc<Vec<Listing>>,
+    /// When these listings were loaded, or edited
+    pub changed_at: SystemTime,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            listings: Arc::default(),
+            changed_at: SystemTime::now(),
+        }
+    }
+}
+
+/// What a reload did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Reload {
+    pub version: u64,
+    pub listings: usize,
+    /// False when the source matched the current snapshot
+    pub changed: bool,
+    /// Listings with problems left out in lenient mode
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogError {
+    NotFound(String),
+    DuplicateId(String),
+    /// The id in the body isn't the one in the path
+    IdMismatch(String),
+    Invalid(ValidationErrors),
+    /// The backend couldn't save the change
+    Storage(String),
+    /// A reload found a problem with one of the listings
+    Rejected(String),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Listing {id} does not exist"),
+            Self::DuplicateId(id) => write!(f, "Listing {id} already exists"),
+            Self::IdMismatch(id) => write!(f, "Listing id {id} does not match the path"),
+            Self::Invalid(e) => write!(f, "{e}"),
+            Self::Storage(e) => write!(f, "Could not save the listings: {e}"),
+            Self::Rejected(e) => write!(f, "Kept the current listings: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<LoadError> for CatalogError {
+    fn from(error: LoadError) -> Self {
+        Self::Rejected(error.to_string())
+    }
+}
+
+pub trait ListingStore: Send + Sync {
+    fn snapshot(&self) -> Snapshot;
+
+    /// Every listing
+    fn listings(&self) -> Arc<Vec<Listing>> {
+        self.snapshot().listings
+    }
+
+    fn get(&self, id: &str) -> Option<Listing> {
+        self.listings().iter().find(|l| l.id == id).cloned()
+    }
+
+    fn by_location(&self, location_id: &str) -> Vec<Listing> {
+        self.listings()
+            .iter()
+            .filter(|l| l.location_id == location_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Add a listing with a new id
+    fn insert(&self, listing: Listing) -> Result<Listing, CatalogError>;
+
+    /// Overwrite the listing with the same id, which must exist
+    fn replace(&self, listing: Listing) -> Result<Listing, CatalogError>;
+
+    /// Change only the fields set in the patch
+    fn update(&self, id: &str, patch: ListingPatch) -> Result<Listing, CatalogError>;
+
+    fn delete(&self, id: &str) -> Result<Listing, CatalogError>;
+
+    /// Swap in the listings from the backing file or database
+    fn reload(&self) -> Result<Reload, CatalogError> {
+        Err(CatalogError::Storage("This store has nothing to reload from".to_string()))
+    }
+
+    /// Why the last reload kept the current listings, until a reload works
+    fn reload_error(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Read a listings file without validating it
+pub fn read_listings(path: impl AsRef<Path>) -> anyhow::Result<Vec<Listing>> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    snapshot: RwLock<Snapshot>,
+    reload_error: RwLock<Option<String>>,
+}
+
+impl MemoryStore {
+    pub fn new(listings: Vec<Listing>) -> Self {
+        Self {
+            snapshot: RwLock::new(Snapshot {
+                version: 1,
+                listings: Arc::new(listings),
+                changed_at: SystemTime::now(),
+            }),
+            reload_error: RwLock::default(),
+        }
+    }
+
+    /// Remember how a reload of the store wrapping this one went
+    pub(crate) fn reloaded(&self, result: Result<Reload, CatalogError>) -> Result<Reload, CatalogError> {
+        *self.reload_error.write().unwrap() = result.as_ref().err().map(CatalogError::to_string);
+        result
+    }
+
+    /// A copy of the current listings to edit before the change is saved
+    pub(crate) fn staged(&self) -> MemoryStore {
+        MemoryStore::new(self.listings().to_vec())
+    }
+
+    /// Swap in listings that were edited in a staged copy and saved
+    pub(crate) fn publish(&self, listings: Arc<Vec<Listing>>) {
+        let mut current = self.snapshot.write().unwrap();
+        *current = Snapshot {
+            version: current.version + 1,
+            listings,
+            changed_at: SystemTime::now(),
+        };
+    }
+
+    /// Swap in listings loaded from somewhere else if every one of them is valid
+    pub fn replace_all(&self, listings: Vec<Listing>) -> Result<Reload, CatalogError> {
+        let (listings, _) = validation::check_listings(listings, LoadMode::Strict)?;
+        let mut current = self.snapshot.write().unwrap();
+        let changed = *current.listings != listings;
+        if changed {
+            *current = Snapshot {
+                version: current.version + 1,
+                listings: Arc::new(listings),
+                changed_at: SystemTime::now(),
+            };
+        }
+        Ok(Reload {
+            version: current.version,
+            listings: current.listings.len(),
+            changed,
+            skipped: 0,
+        })
+    }
+
+    /// Copy the current listings, edit them and swap them in if the edit succeeds
+    fn edit<T>(&self, edit: impl FnOnce(&mut Vec<Listing>) -> Result<T, CatalogError>) -> Result<T, CatalogError> {
+        let mut current = self.snapshot.write().unwrap();
+        let mut listings = current.listings.as_ref().clone();
+        let result = edit(&mut listings)?;
+        validation::check_locations(&listings).map_err(CatalogError::Invalid)?;
+        *current = Snapshot {
+            version: current.version + 1,
+            listings: Arc::new(listings),
+            changed_at: SystemTime::now(),
+        };
+        Ok(result)
+    }
+}
+
+fn position(listings: &[Listing], id: &str) -> Result<usize, CatalogError> {
+    listings
+        .iter()
+        .position(|l| l.id == id)
+        .ok_or_else(|| CatalogError::NotFound(id.to_string()))
+}
+
+impl ListingStore for MemoryStore {
+    fn snapshot(&self) -> Snapshot {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    fn insert(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        listing.validate().map_err(CatalogError::Invalid)?;
+        self.edit(|listings| {
+            if listings.iter().any(|l| l.id == listing.id) {
+                return Err(CatalogError::DuplicateId(listing.id.clone()));
+            }
+            listings.push(listing.clone());
+            Ok(listing)
+        })
+    }
+
+    fn replace(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        listing.validate().map_err(CatalogError::Invalid)?;
+        self.edit(|listings| {
+            let index = position(listings, &listing.id)?;
+            listings[index] = listing.clone();
+            Ok(listing)
+        })
+    }
+
+    fn update(&self, id: &str, patch: ListingPatch) -> Result<Listing, CatalogError> {
+        self.edit(|listings| {
+            let index = position(listings, id)?;
+            let mut updated = listings[index].clone();
+            patch.apply(&mut updated);
+            updated.validate().map_err(CatalogError::Invalid)?;
+            listings[index] = updated.clone();
+            Ok(updated)
+        })
+    }
+
+    fn delete(&self, id: &str) -> Result<Listing, CatalogError> {
+        self.edit(|listings| {
+            let index = position(listings, id)?;
+            Ok(listings.remove(index))
+        })
+    }
+
+    fn reload_error(&self) -> Option<String> {
+        self.reload_error.read().unwrap().clone()
+    }
+}
+
+/// Listings kept in memory and written back to a JSON file after every edit
+#[derive(Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    memory: MemoryStore,
+    /// Held while editing and saving so the file gets edits in the same order as memory
+    saving: Mutex<()>,
+    /// Used again when reloading
+    mode: LoadMode,
+    /// Records lenient mode left out, written back after the listings so an
+    /// edit doesn't delete them from the file
+    skipped: Mutex<Vec<serde_json::Value>>,
+}
+
+/// One entry of the saved file
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Record<'a> {
+    Listing(&'a Listing),
+    Skipped(&'a serde_json::Value),
+}
+
+impl JsonFileStore {
+    /// Open a file that must not have any problems
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let (store, _) = Self::open_with(path, LoadMode::Strict)?;
+        Ok(store)
+    }
+
+    /// Open a file, returning what was wrong with it if lenient mode skipped anything
+    pub fn open_with(path: impl Into<PathBuf>, mode: LoadMode) -> Result<(Self, LoadReport), LoadError> {
+        let path = path.into();
+        let (listings, report) = validation::load_listings(&path, mode)?;
+        let store = Self {
+            path,
+            memory: MemoryStore::new(listings),
+            saving: Mutex::new(()),
+            mode,
+            skipped: Mutex::new(report.skipped.clone()),
+        };
+        Ok((store, report))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self, listings: &[Listing]) -> Result<(), CatalogError> {
+        let skipped = self.skipped.lock().unwrap();
+        let records: Vec<Record> = listings
+            .iter()
+            .map(Record::Listing)
+            .chain(skipped.iter().map(Record::Skipped))
+            .collect();
+        let mut data = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+        let mut serializer = serde_json::Serializer::with_formatter(&mut data, formatter);
+        records
+            .serialize(&mut serializer)
+            .map_err(|e| CatalogError::Storage(e.to_string()))?;
+
+        // Write next to the file and rename so a crash never leaves half a catalog
+        let temp = self.path.with_extension("json.tmp");
+        fs::write(&temp, data)
+            .and_then(|_| fs::rename(&temp, &self.path))
+            .map_err(|e| CatalogError::Storage(e.to_string()))
+    }
+
+    /// Edit a copy of the listings and only swap it in once the file has it,
+    /// so a failed write leaves the version where it was
+    fn saved<T>(&self, edit: impl FnOnce(&MemoryStore) -> Result<T, CatalogError>) -> Result<T, CatalogError> {
+        let _saving = self.saving.lock().unwrap();
+        let staged = self.memory.staged();
+        let result = edit(&staged)?;
+        let listings = staged.listings();
+        self.save(&listings)?;
+        self.memory.publish(listings);
+        Ok(result)
+    }
+}
+
+impl ListingStore for JsonFileStore {
+    fn snapshot(&self) -> Snapshot {
+        self.memory.snapshot()
+    }
+
+    fn insert(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.insert(listing))
+    }
+
+    fn replace(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.replace(listing))
+    }
+
+    fn update(&self, id: &str, patch: ListingPatch) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.update(id, patch))
+    }
+
+    fn delete(&self, id: &str) -> Result<Listing, CatalogError> {
+        self.saved(|memory| memory.delete(id))
+    }
+
+    fn reload(&self) -> Result<Reload, CatalogError> {
+        let _saving = self.saving.lock().unwrap();
+        let result = validation::load_listings(&self.path, self.mode)
+            .map_err(CatalogError::from)
+            .and_then(|(listings, report)| {
+                let reload = self.memory.replace_all(listings)?;
+                *self.skipped.lock().unwrap() = report.skipped;
+                Ok(Reload {
+                    skipped: report.total - report.valid,
+                    ..reload
+                })
+            });
+        self.memory.reloaded(result)
+    }
+
+    fn reload_error(&self) -> Option<String> {
+        self.memory.reload_error()
+    }
+}
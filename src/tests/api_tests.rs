@@ -1,5 +1,53 @@
-use actix_web::{test, App};
-use crate::{index, search};
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{test, web, App};
+use neighbor::auth::{self, AdminToken};
+use neighbor::bookings::BookingStore;
+use neighbor::compute::ComputePool;
+use neighbor::fx::FxTable;
+use neighbor::idempotency::IdempotencyStore;
+use neighbor::locations::Locations;
+use neighbor::model::Limits;
+use neighbor::store::{self, JsonFileStore, ListingStore, MemoryStore};
+use neighbor::validation;
+use std::sync::Arc;
+use crate::{
+    cancel_booking, create_booking, create_listing, create_quote, delete_listing, errors, get_booking, get_listing, index,
+    healthz, readyz, reload_listings, replace_listing, search, search_batch, search_v1, update_listing,
+};
+
+const TOKEN: &str = "an-admin-token-for-tests";
+
+fn admin_token() -> web::Data<AdminToken> {
+    web::Data::new(AdminToken::new(Some(TOKEN.to_string())))
+}
+
+/// Send the admin token with the request
+fn admin(request: test::TestRequest) -> test::TestRequest {
+    request.insert_header((auth::AUTHORIZATION, format!("Bearer {TOKEN}")))
+}
+
+/// The README catalog, in memory so tests can edit it without touching the file
+fn listings() -> web::Data<dyn ListingStore> {
+    let store: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(store::read_listings(store::LISTINGS_FILE).unwrap()));
+    web::Data::from(store)
+}
+
+/// Everything the handlers read from app data. Tests that need their own
+/// store or limits add them again, the later `app_data` wins.
+fn app() -> App<
+    impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<BoxBody>, Error = actix_web::Error, InitError = ()>,
+> {
+    App::new()
+        .app_data(web::Data::new(BookingStore::default()))
+        .app_data(listings())
+        .app_data(web::Data::new(Locations::default()))
+        .app_data(web::Data::new(FxTable::default()))
+        .app_data(web::Data::new(Limits::default()))
+        .app_data(web::Data::new(ComputePool::default()))
+        .app_data(web::Data::new(IdempotencyStore::default()))
+        .app_data(admin_token())
+}
 
 #[actix_web::test]
 async fn test_index_health_check() {
@@ -18,7 +66,7 @@ async fn test_index_health_check() {
 
 #[actix_web::test]
 async fn test_search_valid_request() {
-    let app = test::init_service(App::new().service(search)).await;
+    let app = test::init_service(app().service(search)).await;
 
     let payload = r#"[
         {
@@ -39,12 +87,12 @@ async fn test_search_valid_request() {
     let body = test::read_body(resp).await;
     let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
 
-    assert!(results.as_array().unwrap().len() > 0);
+    assert!(!results.as_array().unwrap().is_empty());
 }
 
 #[actix_web::test]
 async fn test_search_invalid_request_empty_vehicles() {
-    let app = test::init_service(App::new().service(search)).await;
+    let app = test::init_service(app().service(search)).await;
 
     let payload = r#"[]"#;
 
@@ -65,7 +113,7 @@ async fn test_search_invalid_request_empty_vehicles() {
 
 #[actix_web::test]
 async fn test_search_invalid_request_zero_length() {
-    let app = test::init_service(App::new().service(search)).await;
+    let app = test::init_service(app().service(search)).await;
 
     let payload = r#"[
         {
@@ -87,11 +135,14 @@ async fn test_search_invalid_request_zero_length() {
     let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
 
     assert_eq!(json["error"], "Validation failed");
+    assert_eq!(json["code"], "validation_failed");
+    assert_eq!(json["fields"][0]["field"], "vehicles[0].length");
+    assert_eq!(json["fields"][0]["code"], "length_must_be_positive");
 }
 
 #[actix_web::test]
 async fn test_search_invalid_request_too_many_vehicles() {
-    let app = test::init_service(App::new().service(search)).await;
+    let app = test::init_service(app().service(search)).await;
 
     let payload = r#"[
         {
@@ -113,3 +164,865 @@ async fn test_search_invalid_request_too_many_vehicles() {
 
     assert_eq!(resp.status().as_u16(), 400);
 }
+
+#[actix_web::test]
+async fn test_search_with_date_range() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search?start_date=2025-01-01&end_date=2025-01-16")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let body = test::read_body(resp).await;
+    let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    // Half a month of the cheapest README listing
+    assert_eq!(results[0]["total_price_in_cents"], 503);
+}
+
+#[actix_web::test]
+async fn test_search_invalid_date_range() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search?start_date=2025-02-01&end_date=2025-01-01")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let body = test::read_body(resp).await;
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"], "Validation failed");
+}
+
+#[actix_web::test]
+async fn test_search_period_is_capped() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search?start_date=0001-01-01&end_date=9999-12-31")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(json["fields"][0]["code"], "period_too_long");
+}
+
+#[actix_web::test]
+async fn test_booking_lifecycle() {
+    const CHEAPEST_LOCATION: &str = "42b8f068-2d13-4ed1-8eec-c98f1eef0850";
+    const CHEAPEST_LISTING: &str = "b9bbe25f-5679-4917-bd7b-1e19c464f3a8";
+
+    let app = test::init_service(
+        app()
+            .service(search)
+            .service(create_booking)
+            .service(get_booking)
+            .service(cancel_booking),
+    )
+    .await;
+
+    let booking = serde_json::json!({
+        "location_id": CHEAPEST_LOCATION,
+        "listing_ids": [CHEAPEST_LISTING],
+        "vehicles": [{ "length": 10, "quantity": 1 }],
+        "start_date": "2025-01-01",
+        "end_date": "2025-02-01"
+    });
+
+    let req = test::TestRequest::post().uri("/bookings").set_json(&booking).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 201);
+    let created: serde_json::Value = test::read_body_json(resp).await;
+    let id = created["id"].as_str().unwrap().to_string();
+
+    // The same listing can't be booked twice
+    let req = test::TestRequest::post().uri("/bookings").set_json(&booking).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 409);
+
+    // An overlapping search no longer offers the booked listing
+    let req = test::TestRequest::post()
+        .uri("/search?start_date=2025-01-15&end_date=2025-01-20")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let results: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(results
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|r| r["listing_ids"][0] != CHEAPEST_LISTING));
+
+    let req = test::TestRequest::get().uri(&format!("/bookings/{id}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let req = test::TestRequest::delete().uri(&format!("/bookings/{id}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let req = test::TestRequest::get().uri(&format!("/bookings/{id}")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+fn cheapest_booking() -> serde_json::Value {
+    serde_json::json!({
+        "location_id": "42b8f068-2d13-4ed1-8eec-c98f1eef0850",
+        "listing_ids": ["b9bbe25f-5679-4917-bd7b-1e19c464f3a8"],
+        "vehicles": [{ "length": 10, "quantity": 1 }],
+        "start_date": "2025-03-01",
+        "end_date": "2025-04-01"
+    })
+}
+
+#[actix_web::test]
+async fn test_booking_idempotency_key_replays_response() {
+    let bookings = web::Data::new(BookingStore::default());
+    let app = test::init_service(
+        app()
+            .app_data(bookings.clone())
+            .service(create_booking),
+    )
+    .await;
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/bookings")
+            .insert_header(("Idempotency-Key", "retry-1"))
+            .set_json(cheapest_booking())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        ids.push(body["id"].clone());
+    }
+    assert_eq!(ids[0], ids[1]);
+    assert_eq!(bookings.all().len(), 1);
+
+    // Reusing the key for another request is refused
+    let mut other = cheapest_booking();
+    other["end_date"] = "2025-05-01".into();
+    let req = test::TestRequest::post()
+        .uri("/bookings")
+        .insert_header(("Idempotency-Key", "retry-1"))
+        .set_json(other)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 422);
+}
+
+#[actix_web::test]
+async fn test_quote_chosen_combination() {
+    let app = test::init_service(app().service(create_quote)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/quote")
+        .set_json(serde_json::json!({
+            "location_id": "42b8f068-2d13-4ed1-8eec-c98f1eef0850",
+            "listing_ids": ["b9bbe25f-5679-4917-bd7b-1e19c464f3a8"],
+            "start_date": "2025-01-01",
+            "end_date": "2025-01-31"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let quote: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(quote["line_items"][0]["kind"], "listing");
+    assert_eq!(quote["subtotal_in_cents"], 1005);
+    assert_eq!(quote["total_in_cents"], 1005);
+
+    let req = test::TestRequest::post()
+        .uri("/quote")
+        .set_json(serde_json::json!({
+            "location_id": "somewhere-else",
+            "listing_ids": ["b9bbe25f-5679-4917-bd7b-1e19c464f3a8"]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 422);
+}
+
+#[actix_web::test]
+async fn test_search_with_fees_and_taxes() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search?include_fees_and_taxes=true&start_date=2025-01-01&end_date=2025-01-31")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let results: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    // No location has fees or taxes configured
+    assert_eq!(results[0]["total_price_in_cents"], 1005);
+}
+
+#[actix_web::test]
+async fn test_search_in_unsupported_currency() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search?currency=EUR")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Unsupported currency");
+}
+
+#[actix_web::test]
+async fn test_listing_endpoints_enforce_invariants() {
+    let app = test::init_service(
+        app()
+            .service(create_listing)
+            .service(get_listing)
+            .service(replace_listing),
+    )
+    .await;
+    let existing_id = "2f9266ce-7716-40b1-b27f-c1d77a807551";
+    let listing = |id: &str, length: i32| {
+        serde_json::json!({
+            "id": id,
+            "location_id": "d1c331f1-9ae6-4d8a-9d87-a0cf5cfe1536",
+            "length": length,
+            "width": 20,
+            "price_in_cents": 64683
+        })
+    };
+
+    let req = test::TestRequest::get().uri(&format!("/listings/{existing_id}")).to_request();
+    let found: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(found["length"], 40);
+
+    let req = test::TestRequest::get().uri("/listings/missing").to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+
+    let req = admin(test::TestRequest::post()).uri("/listings").set_json(listing("new", 45)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+
+    let req = admin(test::TestRequest::post()).uri("/listings").set_json(listing(existing_id, 40)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 409);
+
+    let req = admin(test::TestRequest::put())
+        .uri(&format!("/listings/{existing_id}"))
+        .set_json(listing("other", 40))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+    // PUT only replaces, new listings go through POST
+    let req = admin(test::TestRequest::put())
+        .uri("/listings/missing")
+        .set_json(listing("missing", 40))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn test_listing_edits_are_searchable() {
+    let app = test::init_service(
+        app()
+            .service(search)
+            .service(create_listing)
+            .service(update_listing)
+            .service(delete_listing),
+    )
+    .await;
+    let search_request = || {
+        test::TestRequest::post()
+            .uri("/search")
+            .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+            .to_request()
+    };
+
+    let req = admin(test::TestRequest::post())
+        .uri("/listings")
+        .set_json(serde_json::json!({
+            "id": "cheap",
+            "location_id": "new-location",
+            "length": 10,
+            "width": 10,
+            "price_in_cents": 1
+        }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 201);
+
+    let results: serde_json::Value = test::call_and_read_body_json(&app, search_request()).await;
+    assert_eq!(results[0]["listing_ids"][0], "cheap");
+
+    let req = admin(test::TestRequest::patch())
+        .uri("/listings/cheap")
+        .set_json(serde_json::json!({ "price_in_cents": 2 }))
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["price_in_cents"], 2);
+    assert_eq!(updated["length"], 10);
+
+    let req = admin(test::TestRequest::delete()).uri("/listings/cheap").to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let results: serde_json::Value = test::call_and_read_body_json(&app, search_request()).await;
+    assert_ne!(results[0]["listing_ids"][0], "cheap");
+}
+
+#[actix_web::test]
+async fn test_listing_edits_need_the_admin_token() {
+    let app = test::init_service(app().service(delete_listing).service(reload_listings)).await;
+    let listing = "/listings/2f9266ce-7716-40b1-b27f-c1d77a807551";
+
+    let req = test::TestRequest::delete().uri(listing).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+    assert_eq!(resp.headers().get("www-authenticate").unwrap(), "Bearer");
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "unauthorized");
+
+    let req = test::TestRequest::post()
+        .uri("/admin/reload")
+        .insert_header((auth::AUTHORIZATION, "Bearer not-the-admin-token"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+
+    let req = admin(test::TestRequest::delete()).uri(listing).to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn test_listing_edits_are_off_without_a_token() {
+    let app = test::init_service(
+        app()
+            .app_data(web::Data::new(AdminToken::default()))
+            .service(delete_listing),
+    )
+    .await;
+    let req = admin(test::TestRequest::delete()).uri("/listings/2f9266ce-7716-40b1-b27f-c1d77a807551").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "admin_disabled");
+}
+
+#[actix_web::test]
+async fn test_listing_edit_rules() {
+    let app = test::init_service(
+        app()
+            .service(create_booking)
+            .service(create_listing)
+            .service(update_listing)
+            .service(delete_listing),
+    )
+    .await;
+    let listing = |id: String| {
+        serde_json::json!({
+            "id": id,
+            "location_id": "crowded",
+            "length": 10,
+            "width": 10,
+            "price_in_cents": 100,
+            "pricing": { "monthly_in_cents": 100 }
+        })
+    };
+
+    // Sending null clears the pricing rules, leaving the field out keeps them
+    let req = admin(test::TestRequest::post()).uri("/listings").set_json(listing("0".into())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 201);
+    let req = admin(test::TestRequest::patch())
+        .uri("/listings/0")
+        .set_json(serde_json::json!({ "width": 20 }))
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["pricing"]["monthly_in_cents"], 100);
+    let req = admin(test::TestRequest::patch())
+        .uri("/listings/0")
+        .set_json(serde_json::json!({ "pricing": null }))
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(updated.get("pricing").is_none());
+
+    // Searches try every combination at a location, so there is a cap
+    for id in 1..validation::MAX_LISTINGS_PER_LOCATION {
+        let req = admin(test::TestRequest::post()).uri("/listings").set_json(listing(id.to_string())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 201);
+    }
+    let req = admin(test::TestRequest::post()).uri("/listings").set_json(listing("extra".into())).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["fields"][0]["code"], "too_many_listings_at_location");
+
+    // A listing with a booking that hasn't ended stays
+    let booking = serde_json::json!({
+        "location_id": "crowded",
+        "listing_ids": ["0"],
+        "vehicles": [{ "length": 10, "quantity": 1 }],
+        "start_date": "2099-01-01",
+        "end_date": "2099-02-01"
+    });
+    let req = test::TestRequest::post().uri("/bookings").set_json(booking).to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 201);
+    let req = admin(test::TestRequest::delete()).uri("/listings/0").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "listing_booked");
+    let req = admin(test::TestRequest::delete()).uri("/listings/1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn test_admin_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("listings.json");
+    let listing = |length: i32| {
+        serde_json::json!([{
+            "id": "a",
+            "location_id": "loc1",
+            "length": length,
+            "width": 10,
+            "price_in_cents": 100
+        }])
+    };
+    std::fs::write(&path, listing(10).to_string()).unwrap();
+    let store: Arc<dyn ListingStore> = Arc::new(JsonFileStore::open(&path).unwrap());
+    let app = test::init_service(App::new().app_data(web::Data::from(store.clone())).app_data(admin_token()).service(reload_listings)).await;
+
+    std::fs::write(&path, listing(20).to_string()).unwrap();
+    let req = test::TestRequest::post().uri("/admin/reload").to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+    assert_eq!(store.listings()[0].length, 10);
+
+    let req = admin(test::TestRequest::post()).uri("/admin/reload").to_request();
+    let reload: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reload["changed"], true);
+    assert_eq!(store.listings()[0].length, 20);
+
+    std::fs::write(&path, listing(25).to_string()).unwrap();
+    let req = admin(test::TestRequest::post()).uri("/admin/reload").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 422);
+    assert_eq!(store.listings()[0].length, 20);
+}
+
+#[actix_web::test]
+async fn test_health_checks() {
+    let app = test::init_service(App::new().app_data(listings()).service(healthz).service(readyz)).await;
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let live: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(live["status"], "ok");
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let ready: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(ready["ready"], true);
+    assert_eq!(ready["catalog"]["version"], 1);
+    assert!(ready["catalog"]["listings"].as_u64().unwrap() > ready["catalog"]["locations"].as_u64().unwrap());
+    assert!(ready["catalog"]["changed_at"].is_string());
+    assert!(ready["catalog"]["last_reload_error"].is_null());
+
+    // A store that never got its listings
+    let empty: Arc<dyn ListingStore> = Arc::new(MemoryStore::default());
+    let app = test::init_service(App::new().app_data(web::Data::from(empty)).service(readyz)).await;
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    let ready: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(ready["catalog"]["loaded"], false);
+
+    // An empty file or database loads, but every search would come back empty
+    let empty: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(Vec::new()));
+    let app = test::init_service(App::new().app_data(web::Data::from(empty)).service(readyz)).await;
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    let ready: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(ready["catalog"]["loaded"], true);
+    assert_eq!(ready["reason"], "The catalog has no listings");
+}
+
+#[actix_web::test]
+async fn test_failed_reload_is_reported_while_ready() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("listings.json");
+    let listing = serde_json::json!([{ "id": "a", "location_id": "loc1", "length": 10, "width": 10, "price_in_cents": 100 }]);
+    std::fs::write(&path, listing.to_string()).unwrap();
+    let store: Arc<dyn ListingStore> = Arc::new(JsonFileStore::open(&path).unwrap());
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(store))
+            .app_data(admin_token())
+            .service(reload_listings)
+            .service(readyz),
+    )
+    .await;
+
+    std::fs::write(&path, "[{ not json").unwrap();
+    let req = admin(test::TestRequest::post()).uri("/admin/reload").to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 422);
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let ready: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(ready["catalog"]["listings"], 1);
+    assert_eq!(ready["catalog"]["locations"], 1);
+    assert!(ready["catalog"]["last_reload_error"].as_str().unwrap().contains("Kept the current listings"));
+}
+
+#[actix_web::test]
+async fn test_search_batch() {
+    let app = test::init_service(
+        app()
+            .service(search)
+            .service(search_batch),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/search/batch")
+        .set_json(serde_json::json!([
+            { "id": "small", "vehicles": [{ "length": 10, "quantity": 1 }] },
+            { "id": "too-many", "vehicles": [{ "length": 10, "quantity": 6 }] },
+            { "id": "malformed", "vehicles": [{ "length": "long" }] },
+            { "id": "small", "vehicles": [{ "length": 20, "quantity": 1 }] },
+            { "id": "fleet", "vehicles": [{ "length": 20, "quantity": 2 }, { "length": 10, "quantity": 1 }] }
+        ]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let items: serde_json::Value = test::read_body_json(resp).await;
+    let items = items.as_array().unwrap();
+
+    let ids: Vec<&str> = items.iter().map(|item| item["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, vec!["small", "too-many", "malformed", "small", "fleet"]);
+    assert_eq!(items[1]["error"], "Validation failed");
+    assert_eq!(items[2]["error"], "Invalid request");
+    assert_eq!(items[3]["error"], "Duplicate id");
+
+    // Each result matches what /search returns for the same fleet
+    let req = test::TestRequest::post()
+        .uri("/search")
+        .set_json(serde_json::json!([{ "length": 20, "quantity": 2 }, { "length": 10, "quantity": 1 }]))
+        .to_request();
+    let single: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    assert_eq!(items[4]["results"], single);
+    assert!(!items[0]["results"].as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn test_search_batch_limit() {
+    let app = test::init_service(
+        app()
+            .app_data(web::Data::new(Limits { max_batch_size: 2, ..Default::default() }))
+            .service(search_batch),
+    )
+    .await;
+
+    let fleet = serde_json::json!({ "id": "a", "vehicles": [{ "length": 10, "quantity": 1 }] });
+    let req = test::TestRequest::post()
+        .uri("/search/batch")
+        .set_json(serde_json::json!([fleet, fleet, fleet]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Batch too large");
+}
+
+#[actix_web::test]
+async fn test_search_v1_envelope() {
+    let app = test::init_service(
+        app()
+            .service(search)
+            .service(search_v1),
+    )
+    .await;
+    let v1 = |body: serde_json::Value| test::TestRequest::post().uri("/v1/search").set_json(body).to_request();
+
+    let all: serde_json::Value =
+        test::read_body_json(test::call_service(&app, v1(serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }] }))).await).await;
+    let total = all["total"].as_u64().unwrap() as usize;
+    assert_eq!(all["results"].as_array().unwrap().len(), total);
+    assert_eq!(all["catalog_version"], 1);
+    assert!(all["took_ms"].as_f64().unwrap() >= 0.0);
+
+    // The same results /search gives, in the same order
+    let req = test::TestRequest::post()
+        .uri("/search")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let old: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    assert_eq!(all["results"], old);
+
+    let page: serde_json::Value = test::read_body_json(
+        test::call_service(&app, v1(serde_json::json!({
+            "vehicles": [{ "length": 10, "quantity": 1 }],
+            "limit": 2,
+            "offset": 1
+        })))
+        .await,
+    )
+    .await;
+    // The search stopped early, so it can't tell how many there are
+    assert!(page.get("total").is_none());
+    assert!(page["next_cursor"].is_string());
+    assert_eq!(page["results"][0], all["results"][1]);
+    assert_eq!(page["results"].as_array().unwrap().len(), 2);
+
+    let cheapest = all["results"][0]["total_price_in_cents"].as_i64().unwrap();
+    let filtered: serde_json::Value = test::read_body_json(
+        test::call_service(&app, v1(serde_json::json!({
+            "vehicles": [{ "length": 10, "quantity": 1 }],
+            "filters": { "max_total_in_cents": cheapest, "location_ids": [all["results"][0]["location_id"]] },
+            "sort": "price_desc"
+        })))
+        .await,
+    )
+    .await;
+    assert_eq!(filtered["total"], 1);
+    assert_eq!(filtered["results"][0], all["results"][0]);
+
+    let descending: serde_json::Value = test::read_body_json(
+        test::call_service(&app, v1(serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }], "sort": "price_desc" })))
+            .await,
+    )
+    .await;
+    assert_eq!(descending["results"][0], all["results"][total - 1]);
+}
+
+#[actix_web::test]
+async fn test_search_v1_rejects_bad_queries() {
+    let app = test::init_service(app().service(search_v1)).await;
+
+    for body in [
+        serde_json::json!({ "vehicles": [] }),
+        serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }], "limit": 0 }),
+        serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }], "sort": "random" }),
+        serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }], "fliters": {} }),
+        serde_json::json!({ "vehicles": [{ "length": 10, "quantity": 1 }], "options": { "currency": "EUR" } }),
+    ] {
+        let req = test::TestRequest::post().uri("/v1/search").set_json(&body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{body}");
+    }
+}
+
+#[actix_web::test]
+async fn test_search_array_contract_is_unchanged() {
+    let app = test::init_service(app().service(search)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/search")
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+
+    let catalog = store::read_listings(store::LISTINGS_FILE).unwrap();
+    let vehicles = vec![neighbor::model::Vehicle { length: 10, quantity: 1 }];
+    let expected = serde_json::to_vec(&neighbor::bin_packing::search_locations(vehicles, &catalog)).unwrap();
+    assert_eq!(body, expected);
+}
+
+#[actix_web::test]
+async fn test_search_cursor_pages() {
+    let store = listings();
+    let app = test::init_service(
+        app()
+            .app_data(store.clone())
+            .service(search)
+            .service(search_v1),
+    )
+    .await;
+    let v1 = |body: serde_json::Value| test::TestRequest::post().uri("/v1/search").set_json(body).to_request();
+    let vehicles = serde_json::json!([{ "length": 20, "quantity": 2 }]);
+
+    let all: serde_json::Value =
+        test::read_body_json(test::call_service(&app, v1(serde_json::json!({ "vehicles": vehicles, "sort": "fewest_listings" }))).await).await;
+    assert!(all.get("next_cursor").is_none());
+
+    // Walk every page and end up with the same results
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut body = serde_json::json!({ "vehicles": vehicles, "sort": "fewest_listings", "limit": 50 });
+        if let Some(cursor) = &cursor {
+            body["cursor"] = cursor.clone().into();
+        }
+        let page: serde_json::Value = test::read_body_json(test::call_service(&app, v1(body)).await).await;
+        assert_eq!(page["total"], all["total"]);
+        seen.extend(page["results"].as_array().unwrap().iter().cloned());
+        match page.get("next_cursor") {
+            Some(next) => cursor = Some(next.as_str().unwrap().to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(serde_json::Value::from(seen), all["results"]);
+
+    // /search pages the bare array and sends the cursor in a header
+    let req = test::TestRequest::post().uri("/search?limit=2").set_json(&vehicles).to_request();
+    let resp = test::call_service(&app, req).await;
+    let cursor = resp.headers().get("X-Next-Cursor").unwrap().to_str().unwrap().to_string();
+    let first: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(first.as_array().unwrap().len(), 2);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/search?limit=2&cursor={cursor}"))
+        .set_json(&vehicles)
+        .to_request();
+    let second: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+    assert_ne!(first[0], second[0]);
+
+    // A cursor is only good for the search it came from
+    let req = test::TestRequest::post()
+        .uri(&format!("/search?limit=2&cursor={cursor}"))
+        .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/search?limit=2&offset=2&cursor={cursor}"))
+        .set_json(&vehicles)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::post().uri("/search?limit=2&cursor=nope").set_json(&vehicles).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // ...and for the run of the server that handed it out
+    let other_run = if cursor.starts_with("00000000") { "00000001" } else { "00000000" };
+    let req = test::TestRequest::post()
+        .uri(&format!("/search?limit=2&cursor={other_run}{}", &cursor[8..]))
+        .set_json(&vehicles)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 410);
+
+    // ...and for the catalog it came from
+    store.delete(first[0]["listing_ids"][0].as_str().unwrap()).unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/search?limit=2&cursor={cursor}"))
+        .set_json(&vehicles)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 410);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Cursor expired");
+}
+
+#[actix_web::test]
+async fn test_error_responses_share_a_schema() {
+    let app = test::init_service(
+        app()
+            .configure(errors)
+            .service(search)
+            .service(search_batch),
+    )
+    .await;
+    let call = |req| async {
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status().as_u16();
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        (status, body)
+    };
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }, { "length": 10, "quantity": 0 }]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["fields"][0]["field"], "vehicles[1].quantity");
+    assert_eq!(body["fields"][0]["code"], "range");
+    assert_eq!(body["fields"][0]["message"], "must be at least 1");
+    assert_eq!(body["details"], "vehicles[1].quantity: must be at least 1");
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .set_json(serde_json::json!([{ "length": 10, "quantity": 6 }]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["fields"][0]["field"], "vehicles");
+    assert_eq!(body["fields"][0]["code"], "total_quantity_exceeds_5");
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("[{ \"length\": 10,")
+            .to_request(),
+    )
+    .await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("malformed_json")));
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .set_json(serde_json::json!([{ "length": "long", "quantity": 1 }]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("invalid_request")));
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .insert_header(("Content-Type", "text/plain"))
+            .set_payload("[]")
+            .to_request(),
+    )
+    .await;
+    assert_eq!((status, body["code"].as_str()), (415, Some("unsupported_media_type")));
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(format!("[{}]", " ".repeat(3 * 1024 * 1024)))
+            .to_request(),
+    )
+    .await;
+    assert_eq!((status, body["code"].as_str()), (413, Some("payload_too_large")));
+
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search?include_fees_and_taxes=maybe")
+            .set_json(serde_json::json!([{ "length": 10, "quantity": 1 }]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("invalid_query")));
+
+    let (status, body) = call(test::TestRequest::get().uri("/nowhere").to_request()).await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("not_found")));
+
+    // Batch items carry the same fields next to their id
+    let (status, body) = call(
+        test::TestRequest::post()
+            .uri("/search/batch")
+            .set_json(serde_json::json!([
+                { "id": "a", "vehicles": [{ "length": -10, "quantity": 1 }] },
+                { "id": "b", "vehicles": "none" }
+            ]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body[0]["id"], "a");
+    assert_eq!(body[0]["code"], "validation_failed");
+    assert_eq!(body[0]["fields"][0]["field"], "vehicles[0].length");
+    assert_eq!(body[1]["id"], "b");
+    assert_eq!(body[1]["code"], "invalid_request");
+}
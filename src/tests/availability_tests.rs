@@ -0,0 +1,115 @@
+//! Availability windows and time bounded search
+
+use chrono::NaiveDate;
+use neighbor::availability::{self, DateRange};
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::model::{Listing, Vehicle};
+use neighbor::money::Money;
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+}
+
+fn range(start: &str, end: &str) -> DateRange {
+    DateRange::new(date(start), date(end))
+}
+
+#[test]
+fn test_no_windows_is_always_available() {
+    assert!(availability::covers(&[], &range("2025-01-01", "2030-01-01")));
+}
+
+#[test]
+fn test_window_must_cover_whole_period() {
+    let windows = vec![range("2025-01-01", "2025-02-01")];
+    assert!(availability::covers(&windows, &range("2025-01-05", "2025-01-20")));
+    assert!(!availability::covers(&windows, &range("2025-01-20", "2025-02-05")));
+    assert!(!availability::covers(&windows, &range("2024-12-20", "2025-01-05")));
+}
+
+#[test]
+fn test_back_to_back_windows_are_merged() {
+    let windows = vec![
+        range("2025-02-01", "2025-03-01"),
+        range("2025-01-01", "2025-02-01"),
+    ];
+    assert!(availability::covers(&windows, &range("2025-01-15", "2025-02-15")));
+
+    let with_gap = vec![
+        range("2025-01-01", "2025-01-31"),
+        range("2025-02-01", "2025-03-01"),
+    ];
+    assert!(!availability::covers(&with_gap, &range("2025-01-15", "2025-02-15")));
+}
+
+#[test]
+fn test_price_for_period_is_prorated() {
+    let listing = Listing {
+        price: Money::usd(3000),
+        ..Default::default()
+    };
+    assert_eq!(listing.price_for_period(&range("2025-01-01", "2025-01-31")), Ok(Money::usd(3000)));
+    assert_eq!(listing.price_for_period(&range("2025-01-01", "2025-01-11")), Ok(Money::usd(1000)));
+    // Rounds up to the next cent
+    let listing = Listing {
+        price: Money::usd(100),
+        ..Default::default()
+    };
+    assert_eq!(listing.price_for_period(&range("2025-01-01", "2025-01-02")), Ok(Money::usd(4)));
+}
+
+#[test]
+fn test_unavailable_listing_is_not_eligible() {
+    let vehicles = vec![10];
+    let listings = vec![
+        Listing {
+            id: "cheap".to_string(),
+            location_id: "loc1".to_string(),
+            length: 10,
+            width: 10,
+            price: Money::usd(100),
+            availability: vec![range("2025-01-01", "2025-01-10")],
+            ..Default::default()
+        },
+        Listing {
+            id: "open".to_string(),
+            location_id: "loc1".to_string(),
+            length: 10,
+            width: 10,
+            price: Money::usd(300),
+            ..Default::default()
+        },
+    ];
+
+    let options = SearchOptions {
+        period: Some(range("2025-01-05", "2025-01-15")),
+        ..Default::default()
+    };
+    let result = bin_packing::find_cheapest_combination_with(&vehicles, &listings, &options).unwrap();
+    assert_eq!(result.listing_ids, vec!["open"]);
+    assert_eq!(result.total, Money::usd(100));
+
+    // Without a period the cheap listing wins at its listed price
+    let result = bin_packing::find_cheapest_combination(&vehicles, &listings).unwrap();
+    assert_eq!(result.listing_ids, vec!["cheap"]);
+}
+
+#[test]
+fn test_search_skips_locations_without_availability() {
+    let vehicles = vec![Vehicle { length: 10, quantity: 1 }];
+    let listings = vec![Listing {
+        id: "1".to_string(),
+        location_id: "loc1".to_string(),
+        length: 10,
+        width: 10,
+        price: Money::usd(100),
+        availability: vec![range("2025-06-01", "2025-07-01")],
+        ..Default::default()
+    }];
+
+    let options = SearchOptions {
+        period: Some(range("2025-01-01", "2025-02-01")),
+        ..Default::default()
+    };
+    assert!(bin_packing::search_locations_with(vehicles, &listings, &options).is_empty());
+}
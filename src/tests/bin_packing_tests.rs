@@ -1,8 +1,9 @@
 //! Test all the functions in bin_packing
 //! For the complex README examples see integration_tests
 
-use crate::model::{Vehicle, Listing};
-use crate::bin_packing::{self, CheapestCombo}; 
+use neighbor::model::{Vehicle, Listing};
+use neighbor::money::Money;
+use neighbor::bin_packing::{self, CheapestCombo}; 
 
 #[test]
 fn test_expand_vehicles() {
@@ -28,7 +29,8 @@ fn test_single_vehicle_fits_in_single_listing() {
         location_id: "loc1".to_string(),
         length: 20,
         width: 10,
-        price_in_cents: 100,
+        price: Money::usd(100),
+        ..Default::default()
     }];
 
     assert!(bin_packing::can_fit_all_vehicles(&vehicles, &listings));
@@ -42,7 +44,8 @@ fn test_single_vehicle_does_not_fit() {
         location_id: "loc1".to_string(),
         length: 20,
         width: 10,
-        price_in_cents: 100,
+        price: Money::usd(100),
+        ..Default::default()
     }];
 
     assert!(!bin_packing::can_fit_all_vehicles(&vehicles, &listings));
@@ -56,7 +59,8 @@ fn test_multiple_vehicles_same_listing() {
         location_id: "loc1".to_string(),
         length: 20,
         width: 20,
-        price_in_cents: 100,
+        price: Money::usd(100),
+        ..Default::default()
     }];
 
     assert!(bin_packing::can_fit_all_vehicles(&vehicles, &listings));
@@ -71,14 +75,16 @@ fn test_vehicles_across_multiple_listings() {
             location_id: "loc1".to_string(),
             length: 10,
             width: 10,
-            price_in_cents: 100,
+            price: Money::usd(100),
+            ..Default::default()
         },
         Listing {
             id: "2".to_string(),
             location_id: "loc1".to_string(),
             length: 20,
             width: 10,
-            price_in_cents: 200,
+            price: Money::usd(200),
+            ..Default::default()
         },
     ];
 
@@ -93,7 +99,8 @@ fn test_orientation_uses_longer_dimension() {
         location_id: "loc1".to_string(),
         length: 10,
         width: 20,
-        price_in_cents: 100,
+        price: Money::usd(100),
+        ..Default::default()
     }];
 
     // This should fit because width (20) >= length (10), so width becomes primary dimension
@@ -109,22 +116,24 @@ fn test_find_cheapest_combination() {
             location_id: "loc1".to_string(),
             length: 20,
             width: 10,
-            price_in_cents: 200,
+            price: Money::usd(200),
+            ..Default::default()
         },
         Listing {
             id: "2".to_string(),
             location_id: "loc1".to_string(),
             length: 15,
             width: 10,
-            price_in_cents: 100,
+            price: Money::usd(100),
+            ..Default::default()
         },
     ];
 
     let result = bin_packing::find_cheapest_combination(&vehicles, &listings);
     assert!(result.is_some());
-    let CheapestCombo {listing_ids, total_price_in_cents} = result.unwrap();
+    let CheapestCombo {listing_ids, total, ..} = result.unwrap();
     assert_eq!(listing_ids, vec!["2"]);
-    assert_eq!(total_price_in_cents, 100);
+    assert_eq!(total.amount_in_cents, 100);
 }
 
 #[test]
@@ -136,28 +145,31 @@ fn test_cheapest_combination_multiple_listings_needed() {
             location_id: "loc1".to_string(),
             length: 30,
             width: 10,
-            price_in_cents: 100,
+            price: Money::usd(100),
+            ..Default::default()
         },
         Listing {
             id: "2".to_string(),
             location_id: "loc1".to_string(),
             length: 30,
             width: 10,
-            price_in_cents: 150,
+            price: Money::usd(150),
+            ..Default::default()
         },
         Listing {
             id: "3".to_string(),
             location_id: "loc1".to_string(),
             length: 60,
             width: 20,
-            price_in_cents: 500,
+            price: Money::usd(500),
+            ..Default::default()
         },
     ];
 
     let result = bin_packing::find_cheapest_combination(&vehicles, &listings);
     assert!(result.is_some());
-    let CheapestCombo {listing_ids, total_price_in_cents} = result.unwrap();
-    assert_eq!(total_price_in_cents, 250);
+    let CheapestCombo {listing_ids, total, ..} = result.unwrap();
+    assert_eq!(total.amount_in_cents, 250);
     assert!(listing_ids.contains(&"1".to_string()));
     assert!(listing_ids.contains(&"2".to_string()));
 }
@@ -174,21 +186,24 @@ fn test_search_locations_groups_by_location() {
             location_id: "loc1".to_string(),
             length: 20,
             width: 10,
-            price_in_cents: 100,
+            price: Money::usd(100),
+            ..Default::default()
         },
         Listing {
             id: "2".to_string(),
             location_id: "loc2".to_string(),
             length: 20,
             width: 10,
-            price_in_cents: 150,
+            price: Money::usd(150),
+            ..Default::default()
         },
         Listing {
             id: "3".to_string(),
             location_id: "loc1".to_string(),
             length: 20,
             width: 10,
-            price_in_cents: 200,
+            price: Money::usd(200),
+            ..Default::default()
         },
     ];
 
@@ -209,3 +224,141 @@ fn test_try_fit_vehicles_in_dimension() {
     assert!(bin_packing::try_fit_vehicles_in_dimension(&[15], 15, 20, 20));
     assert!(!bin_packing::try_fit_vehicles_in_dimension(&[10, 10], 10, 15, 20));
 }
+
+#[test]
+fn test_location_index_is_shared_between_searches() {
+    let listings = neighbor::store::read_listings(neighbor::store::LISTINGS_FILE).unwrap();
+    let options = bin_packing::SearchOptions::default();
+    let index = bin_packing::LocationIndex::new(&listings, &options);
+
+    let locations: std::collections::HashSet<&str> = listings.iter().map(|l| l.location_id.as_str()).collect();
+    assert_eq!(index.len(), locations.len());
+
+    for length in [10, 20, 40] {
+        let fleet = || vec![Vehicle { length, quantity: 2 }];
+        let shared: Vec<_> = bin_packing::search_index(fleet(), &index, &options)
+            .into_iter()
+            .map(|r| (r.location_id, r.total_price_in_cents))
+            .collect();
+        let alone: Vec<_> = bin_packing::search_locations(fleet(), &listings)
+            .into_iter()
+            .map(|r| (r.location_id, r.total_price_in_cents))
+            .collect();
+        // Ties can come back in either order, compare as sets
+        assert_eq!(shared.len(), alone.len());
+        assert!(alone.iter().all(|result| shared.contains(result)));
+    }
+}
+
+#[test]
+fn test_search_cheapest_matches_full_search() {
+    let listings = neighbor::store::read_listings(neighbor::store::LISTINGS_FILE).unwrap();
+    let options = bin_packing::SearchOptions::default();
+    let index = bin_packing::LocationIndex::new(&listings, &options);
+    let fleet = || vec![Vehicle { length: 20, quantity: 2 }];
+    let ids = |results: Vec<neighbor::model::PossibleSpace>| -> Vec<String> {
+        results.into_iter().map(|r| r.location_id).collect()
+    };
+
+    let all = ids(bin_packing::search_index(fleet(), &index, &options));
+    for count in [1, 3, 10] {
+        let cheapest = bin_packing::search_cheapest(fleet(), &index, &options, count, |_| true);
+        assert!(!cheapest.complete);
+        assert_eq!(ids(cheapest.results), all[..count]);
+    }
+
+    let everything = bin_packing::search_cheapest(fleet(), &index, &options, usize::MAX, |_| true);
+    assert!(everything.complete);
+    assert_eq!(ids(everything.results), all);
+
+    // Rejected results don't count towards the limit
+    let single = bin_packing::search_cheapest(fleet(), &index, &options, 2, |r| r.listing_ids.len() == 1);
+    let expected: Vec<String> = bin_packing::search_index(fleet(), &index, &options)
+        .into_iter()
+        .filter(|r| r.listing_ids.len() == 1)
+        .take(2)
+        .map(|r| r.location_id)
+        .collect();
+    assert_eq!(ids(single.results), expected);
+}
+
+#[test]
+fn test_search_cheapest_keeps_discounted_locations() {
+    use neighbor::discounts::{Discount, DiscountKind};
+    use neighbor::locations::{Location, Locations};
+
+    let listing = |id: &str, location_id: &str, price_in_cents| Listing {
+        id: id.to_string(),
+        location_id: location_id.to_string(),
+        length: 10,
+        width: 10,
+        price: Money::usd(price_in_cents),
+        ..Default::default()
+    };
+    let listings = vec![
+        listing("cheap", "plain", 1000),
+        listing("a", "bundle", 1500),
+        listing("b", "bundle", 1500),
+    ];
+    let mut locations = Locations::default();
+    locations.insert(
+        "bundle",
+        Location {
+            discounts: vec![Discount {
+                name: None,
+                min_listings: 2,
+                kind: DiscountKind::Percentage { percent: 80 },
+            }],
+            ..Default::default()
+        },
+    );
+    let options = bin_packing::SearchOptions {
+        locations: std::sync::Arc::new(locations),
+        ..Default::default()
+    };
+    let index = bin_packing::LocationIndex::new(&listings, &options);
+
+    // Both listings at "bundle" cost more than "plain" alone, the discount still wins
+    let fleet = vec![Vehicle { length: 10, quantity: 2 }];
+    let cheapest = bin_packing::search_cheapest(fleet, &index, &options, 1, |_| true);
+    assert_eq!(cheapest.results[0].location_id, "bundle");
+    assert_eq!(cheapest.results[0].total_price_in_cents, 600);
+}
+
+#[test]
+fn test_max_listings_skips_larger_combinations() {
+    let listing = |id: &str, length: i32, price_in_cents: i64| Listing {
+        id: id.to_string(),
+        location_id: "loc1".to_string(),
+        length,
+        width: 10,
+        price: Money::usd(price_in_cents),
+        ..Default::default()
+    };
+    let listings = vec![listing("a", 10, 100), listing("b", 10, 100), listing("big", 20, 500)];
+    let fleet = || vec![Vehicle { length: 10, quantity: 2 }];
+
+    let open = bin_packing::SearchOptions::default();
+    let results = bin_packing::search_locations_with(fleet(), &listings, &open);
+    assert_eq!(results[0].listing_ids, vec!["a", "b"]);
+    assert_eq!(open.stats.subsets(), 7);
+
+    // The cheapest single listing, not no result at all
+    let one = bin_packing::SearchOptions { max_listings: Some(1), ..Default::default() };
+    let results = bin_packing::search_locations_with(fleet(), &listings, &one);
+    assert_eq!(results[0].listing_ids, vec!["big"]);
+    assert_eq!(one.stats.subsets(), 3);
+}
+
+#[test]
+fn test_max_listings_visits_fewer_masks() {
+    assert_eq!(bin_packing::combinations(20, None).count(), (1 << 20) - 1);
+    // 20 singles and 190 pairs, not a million masks to skip
+    assert_eq!(bin_packing::combinations(20, Some(2)).count(), 210);
+    assert_eq!(bin_packing::combinations(3, Some(5)).count(), 7);
+
+    let mut pairs: Vec<u32> = bin_packing::combinations(6, Some(2)).collect();
+    pairs.sort();
+    let expected: Vec<u32> = (1u32..1 << 6).filter(|mask| mask.count_ones() <= 2).collect();
+    assert_eq!(pairs, expected);
+}
@@ -0,0 +1,263 @@
+//! Reserving listings and excluding them from later searches
+
+use chrono::NaiveDate;
+use neighbor::availability::DateRange;
+use neighbor::bookings::{BookingError, BookingRequest, BookingStore};
+use neighbor::fx::FxTable;
+use neighbor::locations::{Location, Locations};
+use neighbor::model::{Listing, ListingPatch, Vehicle};
+use neighbor::money::Money;
+use neighbor::quote::QuoteError;
+use neighbor::store::{CatalogError, ListingStore, MemoryStore, Snapshot};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+fn date(day: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+}
+
+fn range(start: &str, end: &str) -> DateRange {
+    DateRange::new(date(start), date(end))
+}
+
+fn catalog() -> MemoryStore {
+    MemoryStore::new(vec![
+        Listing {
+            id: "1".to_string(),
+            location_id: "loc1".to_string(),
+            length: 20,
+            width: 10,
+            price: Money::usd(3000),
+            ..Default::default()
+        },
+        Listing {
+            id: "2".to_string(),
+            location_id: "loc2".to_string(),
+            length: 10,
+            width: 10,
+            price: Money::usd(3000),
+            availability: vec![range("2025-01-01", "2025-02-01")],
+            ..Default::default()
+        },
+    ])
+}
+
+fn request(location_id: &str, listing_id: &str, length: i32, period: DateRange) -> BookingRequest {
+    BookingRequest {
+        location_id: location_id.to_string(),
+        listing_ids: vec![listing_id.to_string()],
+        vehicles: vec![Vehicle { length, quantity: 1 }],
+        period,
+        currency: None,
+    }
+}
+
+#[test]
+fn test_create_booking_prices_the_period() {
+    let store = BookingStore::default();
+    let booking = store
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog(), &Locations::default(), &FxTable::default())
+        .unwrap();
+
+    assert_eq!(booking.total_price_in_cents, 1000);
+    assert_eq!(booking.total_with_fees_in_cents, 1000);
+    assert_eq!(store.get(&booking.id).unwrap().listing_ids, vec!["1"]);
+}
+
+#[test]
+fn test_fees_are_only_in_the_total_with_fees() {
+    let mut locations = Locations::default();
+    locations.insert(
+        "loc1",
+        Location {
+            tax_rate_bps: 1000,
+            ..Default::default()
+        },
+    );
+    let booking = BookingStore::default()
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog(), &locations, &FxTable::default())
+        .unwrap();
+
+    assert_eq!(booking.total_price_in_cents, 1000);
+    assert_eq!(booking.total_with_fees_in_cents, 1100);
+}
+
+#[test]
+fn test_overlapping_booking_is_rejected() {
+    let store = BookingStore::default();
+    let first = store
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog(), &Locations::default(), &FxTable::default())
+        .unwrap();
+
+    let err = store
+        .create(request("loc1", "1", 20, range("2025-01-10", "2025-01-20")), &catalog(), &Locations::default(), &FxTable::default())
+        .unwrap_err();
+    assert_eq!(
+        err,
+        BookingError::AlreadyBooked {
+            listing_id: "1".to_string(),
+            booking_id: first.id
+        }
+    );
+
+    // Back to back is fine since ranges are half open
+    assert!(store
+        .create(request("loc1", "1", 20, range("2025-01-11", "2025-01-20")), &catalog(), &Locations::default(), &FxTable::default())
+        .is_ok());
+}
+
+#[test]
+fn test_booking_rechecks_fit_location_and_availability() {
+    let store = BookingStore::default();
+    let period = range("2025-01-01", "2025-01-11");
+
+    assert_eq!(
+        store.create(request("loc1", "1", 30, period), &catalog(), &Locations::default(), &FxTable::default()).unwrap_err(),
+        BookingError::VehiclesDoNotFit
+    );
+    assert_eq!(
+        store.create(request("loc2", "1", 10, period), &catalog(), &Locations::default(), &FxTable::default()).unwrap_err(),
+        BookingError::Quote(QuoteError::WrongLocation("1".to_string()))
+    );
+    assert_eq!(
+        store.create(request("loc1", "missing", 10, period), &catalog(), &Locations::default(), &FxTable::default()).unwrap_err(),
+        BookingError::Quote(QuoteError::ListingNotFound("missing".to_string()))
+    );
+    assert_eq!(
+        store
+            .create(request("loc2", "2", 10, range("2025-01-20", "2025-02-10")), &catalog(), &Locations::default(), &FxTable::default())
+            .unwrap_err(),
+        BookingError::Quote(QuoteError::Unavailable("2".to_string()))
+    );
+}
+
+#[test]
+fn test_booked_listing_ids_respects_period() {
+    let store = BookingStore::default();
+    let booking = store
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog(), &Locations::default(), &FxTable::default())
+        .unwrap();
+
+    let today = date("2025-01-05");
+    assert!(store.booked_listing_ids(Some(&range("2025-01-05", "2025-01-06")), today).contains("1"));
+    assert!(store.booked_listing_ids(Some(&range("2025-02-01", "2025-03-01")), today).is_empty());
+    assert!(store.booked_listing_ids(None, today).contains("1"));
+    // Once the booking is over an open ended search can have the listing again
+    assert!(store.booked_listing_ids(None, date("2025-01-11")).is_empty());
+
+    store.cancel(&booking.id).unwrap();
+    assert!(store.booked_listing_ids(None, today).is_empty());
+}
+
+#[test]
+fn test_threads_racing_for_one_listing() {
+    let store = BookingStore::default();
+    let catalog = catalog();
+
+    let successes: usize = thread::scope(|scope| {
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                scope.spawn(|| {
+                    store
+                        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog, &Locations::default(), &FxTable::default())
+                        .is_ok()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap() as usize).sum()
+    });
+
+    assert_eq!(successes, 1);
+    assert_eq!(store.all().len(), 1);
+    assert_eq!(store.listing_version("1"), 1);
+}
+
+/// Tells the test when a booking reads the listings, and holds deletes until the test lets them go
+struct SlowDeletes {
+    inner: MemoryStore,
+    read: Sender<()>,
+    deleting: Sender<()>,
+    release: Mutex<Receiver<()>>,
+}
+
+impl ListingStore for SlowDeletes {
+    fn snapshot(&self) -> Snapshot {
+        let _ = self.read.send(());
+        self.inner.snapshot()
+    }
+
+    fn insert(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.inner.insert(listing)
+    }
+
+    fn replace(&self, listing: Listing) -> Result<Listing, CatalogError> {
+        self.inner.replace(listing)
+    }
+
+    fn update(&self, id: &str, patch: ListingPatch) -> Result<Listing, CatalogError> {
+        self.inner.update(id, patch)
+    }
+
+    fn delete(&self, id: &str) -> Result<Listing, CatalogError> {
+        self.deleting.send(()).unwrap();
+        self.release.lock().unwrap().recv().unwrap();
+        self.inner.delete(id)
+    }
+}
+
+#[test]
+fn test_booking_during_a_delete_finds_the_listing_gone() {
+    let store = BookingStore::default();
+    let (read, reads) = mpsc::channel();
+    let (deleting, deletes) = mpsc::channel();
+    let (release, released) = mpsc::channel();
+    let listings = SlowDeletes { inner: catalog(), read, deleting, release: Mutex::new(released) };
+
+    thread::scope(|scope| {
+        let delete = scope.spawn(|| store.unless_booked("1", date("2025-01-01"), || listings.delete("1")));
+        deletes.recv().unwrap();
+
+        // The booking sees listing 1 before the delete lands, then waits for the ledger
+        let booking = scope.spawn(|| {
+            store.create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &listings, &Locations::default(), &FxTable::default())
+        });
+        reads.recv().unwrap();
+        release.send(()).unwrap();
+
+        assert!(delete.join().unwrap().unwrap().is_ok());
+        let err = booking.join().unwrap().unwrap_err();
+        assert!(matches!(err, BookingError::Quote(QuoteError::ListingNotFound(id)) if id == "1"));
+    });
+    assert!(store.all().is_empty());
+}
+
+#[test]
+fn test_booked_listing_is_not_deleted() {
+    let store = BookingStore::default();
+    let listings = catalog();
+    let booking = store
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &listings, &Locations::default(), &FxTable::default())
+        .unwrap();
+
+    let err = store.unless_booked("1", date("2025-01-05"), || listings.delete("1")).unwrap_err();
+    assert!(matches!(err, BookingError::AlreadyBooked { booking_id, .. } if booking_id == booking.id));
+    assert!(listings.get("1").is_some());
+
+    // Once the booking has ended the listing can go
+    assert!(store.unless_booked("1", date("2025-01-11"), || listings.delete("1")).unwrap().is_ok());
+    assert!(listings.get("1").is_none());
+}
+
+#[test]
+fn test_cancel_bumps_listing_version() {
+    let store = BookingStore::default();
+    let booking = store
+        .create(request("loc1", "1", 20, range("2025-01-01", "2025-01-11")), &catalog(), &Locations::default(), &FxTable::default())
+        .unwrap();
+    assert_eq!(store.listing_version("1"), 1);
+
+    store.cancel(&booking.id).unwrap();
+    assert_eq!(store.listing_version("1"), 2);
+    assert_eq!(store.listing_version("2"), 0);
+}
@@ -0,0 +1,116 @@
+//! Editing the listing catalog
+
+use super::listing;
+use neighbor::model::{Listing, ListingPatch};
+use neighbor::money::Money;
+use neighbor::store::{CatalogError, JsonFileStore, ListingStore, MemoryStore};
+use validator::Validate;
+
+#[test]
+fn test_listing_invariants() {
+    assert!(listing("a").validate().is_ok());
+    assert!(Listing { length: 25, ..listing("a") }.validate().is_err());
+    assert!(Listing { width: 0, ..listing("a") }.validate().is_err());
+    assert!(Listing { price: Money::usd(0), ..listing("a") }.validate().is_err());
+    assert!(Listing { id: String::new(), ..listing("a") }.validate().is_err());
+}
+
+#[test]
+fn test_create_rejects_duplicate_ids() {
+    let catalog = MemoryStore::new(vec![listing("a")]);
+
+    assert_eq!(
+        catalog.insert(listing("a")).unwrap_err(),
+        CatalogError::DuplicateId("a".to_string())
+    );
+    assert!(catalog.insert(listing("b")).is_ok());
+    assert_eq!(catalog.listings().len(), 2);
+}
+
+#[test]
+fn test_edits_swap_the_snapshot() {
+    let catalog = MemoryStore::new(vec![listing("a")]);
+    let before = catalog.listings();
+
+    catalog.delete("a").unwrap();
+
+    // A search that already took the snapshot still sees the listing
+    assert_eq!(before.len(), 1);
+    assert!(catalog.listings().is_empty());
+    assert_eq!(catalog.delete("a").unwrap_err(), CatalogError::NotFound("a".to_string()));
+}
+
+#[test]
+fn test_replace_needs_an_existing_listing() {
+    let catalog = MemoryStore::new(vec![listing("a")]);
+
+    let replaced = catalog.replace(Listing { location_id: "loc2".to_string(), ..listing("a") }).unwrap();
+    assert_eq!(replaced.location_id, "loc2");
+    assert_eq!(catalog.by_location("loc2")[0].id, "a");
+    assert!(catalog.by_location("loc1").is_empty());
+
+    assert_eq!(catalog.replace(listing("b")).unwrap_err(), CatalogError::NotFound("b".to_string()));
+    assert_eq!(catalog.listings().len(), 1);
+}
+
+#[test]
+fn test_invalid_patch_leaves_listing_unchanged() {
+    let catalog = MemoryStore::new(vec![listing("a")]);
+
+    let patch = ListingPatch {
+        width: Some(15),
+        price_in_cents: Some(2000),
+        ..Default::default()
+    };
+    assert!(matches!(catalog.update("a", patch), Err(CatalogError::Invalid(_))));
+    assert_eq!(catalog.get("a").unwrap().price.amount_in_cents, 1000);
+
+    let patch = ListingPatch {
+        price_in_cents: Some(2000),
+        ..Default::default()
+    };
+    let updated = catalog.update("a", patch).unwrap();
+    assert_eq!(updated.price.amount_in_cents, 2000);
+    assert_eq!(updated.width, 10);
+}
+
+#[test]
+fn test_json_file_store_saves_edits() {
+    let dir = std::env::temp_dir().join(format!("neighbor-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("listings.json");
+    std::fs::write(&path, serde_json::to_string(&vec![listing("a")]).unwrap()).unwrap();
+
+    let store = JsonFileStore::open(&path).unwrap();
+    store.insert(listing("b")).unwrap();
+    store.delete("a").unwrap();
+
+    let reopened = JsonFileStore::open(&path).unwrap();
+    let ids: Vec<String> = reopened.listings().iter().map(|l| l.id.clone()).collect();
+    assert_eq!(ids, vec!["b"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_failed_save_keeps_memory_and_file_in_step() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("listings.json");
+    std::fs::write(&path, serde_json::to_string(&vec![listing("a")]).unwrap()).unwrap();
+    let store = JsonFileStore::open(&path).unwrap();
+    let version = store.snapshot().version;
+
+    // The new file can't be renamed over a directory
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    assert!(matches!(store.insert(listing("b")), Err(CatalogError::Storage(_))));
+    assert!(matches!(store.delete("a"), Err(CatalogError::Storage(_))));
+
+    let ids: Vec<String> = store.listings().iter().map(|l| l.id.clone()).collect();
+    assert_eq!(ids, vec!["a"]);
+
+    // Searches never saw a version for the edits, so no cursor can point at them
+    assert_eq!(store.snapshot().version, version);
+    std::fs::remove_dir(&path).unwrap();
+    store.insert(listing("c")).unwrap();
+    assert_eq!(store.snapshot().version, version + 1);
+}
@@ -0,0 +1,103 @@
+//! Running searches from the command line
+
+use neighbor::bin_packing;
+use neighbor::cli::{self, Format, SearchArgs, VehicleSpec};
+use neighbor::config::Config;
+use neighbor::model::Vehicle;
+use neighbor::store::{self, LISTINGS_FILE};
+
+fn vehicle(spec: &str) -> Vehicle {
+    spec.parse::<VehicleSpec>().unwrap().0
+}
+
+fn run(args: &SearchArgs) -> String {
+    let mut out = Vec::new();
+    cli::search(args, &Config::default(), &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_vehicle_specs() {
+    let two = vehicle("20x2");
+    assert_eq!((two.length, two.quantity), (20, 2));
+    let one = vehicle("10");
+    assert_eq!((one.length, one.quantity), (10, 1));
+
+    assert!("20x".parse::<VehicleSpec>().is_err());
+    assert!("twenty".parse::<VehicleSpec>().is_err());
+}
+
+#[test]
+fn test_parse_queries() {
+    let queries = cli::parse_queries(
+        "# one per line\n\
+         20x2 10\n\
+         \n\
+         [{\"length\": 30, \"quantity\": 1}]\n",
+    )
+    .unwrap();
+    assert_eq!(queries.len(), 2);
+    assert_eq!(queries[0].line, 2);
+    assert_eq!(queries[0].vehicles.len(), 2);
+    assert_eq!(queries[1].line, 4);
+    assert_eq!(queries[1].vehicles[0].length, 30);
+
+    let error = cli::parse_queries("10\n20xx\n").unwrap_err();
+    assert!(error.to_string().starts_with("Line 2"));
+}
+
+#[test]
+fn test_json_output_matches_the_search() {
+    let args = SearchArgs {
+        vehicles: vec!["20x2".parse().unwrap(), "10".parse().unwrap()],
+        format: Format::Json,
+        ..Default::default()
+    };
+    let output = run(&args);
+
+    let listings = store::read_listings(LISTINGS_FILE).unwrap();
+    let expected = bin_packing::search_locations(vec![vehicle("20x2"), vehicle("10")], &listings);
+    assert_eq!(output, format!("{}\n", serde_json::to_string(&expected).unwrap()));
+}
+
+#[test]
+fn test_batch_csv_and_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("queries.txt");
+    // Nothing in the catalog is long enough for the second query
+    std::fs::write(&path, "10\n10000\n").unwrap();
+
+    let csv = run(&SearchArgs {
+        queries: Some(path.clone()),
+        format: Format::Csv,
+        ..Default::default()
+    });
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("query,location_id,listing_ids,total_price_in_cents,currency,discount_in_cents")
+    );
+    assert!(lines.all(|line| line.starts_with("1,") && line.split(',').count() == 6));
+
+    let table = run(&SearchArgs {
+        queries: Some(path),
+        ..Default::default()
+    });
+    assert!(table.starts_with("query"));
+    assert!(table.lines().any(|line| line.starts_with("2") && line.contains("no match")));
+}
+
+#[test]
+fn test_searches_over_the_limit_are_refused() {
+    let args = SearchArgs {
+        vehicles: vec!["10x6".parse().unwrap()],
+        ..Default::default()
+    };
+    assert!(cli::search(&args, &Config::default(), &mut Vec::new()).is_err());
+
+    let config = Config {
+        max_vehicles: 6,
+        ..Default::default()
+    };
+    assert!(cli::search(&args, &config, &mut Vec::new()).is_ok());
+}
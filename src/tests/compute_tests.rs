@@ -0,0 +1,156 @@
+//! Searches off the HTTP workers, and turned away when the queue is full
+
+use crate::{record_metrics, search};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App};
+use futures_util::FutureExt;
+use std::future::Future;
+use neighbor::bookings::BookingStore;
+use neighbor::compute::{ComputeError, ComputePool};
+use neighbor::fx::FxTable;
+use neighbor::locations::Locations;
+use neighbor::metrics::Metrics;
+use neighbor::model::Limits;
+use neighbor::store::{self, ListingStore, MemoryStore};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Keep the pool's only thread busy until the returned sender is used or dropped.
+/// The job is skipped if its future is dropped before it starts, so keep that too.
+fn occupy(pool: &ComputePool) -> (mpsc::Sender<()>, impl Future + '_) {
+    loop {
+        let (release, released) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        let mut job = Box::pin(pool.run(move || {
+            started.send(()).unwrap();
+            released.recv().ok()
+        }));
+        // Polling once queues the job. With no queue the thread has to be
+        // waiting for work already, try again until it is.
+        match (&mut job).now_or_never() {
+            None => {
+                running.recv().unwrap();
+                return (release, job);
+            }
+            Some(Err(ComputeError::Busy)) => std::thread::sleep(Duration::from_millis(1)),
+            Some(other) => panic!("occupying job finished with {other:?}"),
+        }
+    }
+}
+
+/// Wait for the pool's threads to skip `n` jobs
+fn wait_abandoned(pool: &ComputePool, n: u64) {
+    for _ in 0..1000 {
+        if pool.abandoned() >= n {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(pool.abandoned(), n);
+}
+
+#[actix_web::test]
+async fn test_runs_jobs_on_the_pool() {
+    let pool = ComputePool::new(2, 4);
+    let caller = std::thread::current().id();
+    let computed = pool.run(move || std::thread::current().id() != caller).await.unwrap();
+    assert!(computed.value);
+    assert_eq!(pool.queued(), 0);
+}
+
+#[actix_web::test]
+async fn test_full_queue_is_busy() {
+    let pool = ComputePool::new(1, 1);
+    let (release, _job) = occupy(&pool);
+
+    let mut queued = Box::pin(pool.run(|| 1));
+    assert!((&mut queued).now_or_never().is_none());
+    assert_eq!(pool.queued(), 1);
+    assert_eq!(pool.run(|| 2).now_or_never(), Some(Err(ComputeError::Busy)));
+    assert_eq!(pool.queued(), 1);
+
+    release.send(()).unwrap();
+    assert_eq!(queued.await.unwrap().value, 1);
+    assert_eq!(pool.queued(), 0);
+    let computed = pool.run(|| 3).await.unwrap();
+    assert_eq!(computed.value, 3);
+    assert_eq!(pool.abandoned(), 0);
+}
+
+#[actix_web::test]
+async fn test_jobs_nobody_waits_for_are_skipped() {
+    let pool = ComputePool::new(1, 1);
+    let (release, _job) = occupy(&pool);
+
+    // The client went away while the job was queued
+    let (ran, did_run) = mpsc::channel();
+    assert!(pool.run(move || ran.send(()).unwrap()).now_or_never().is_none());
+    release.send(()).unwrap();
+    wait_abandoned(&pool, 1);
+    assert!(did_run.try_recv().is_err());
+    assert_eq!(pool.queued(), 0);
+    assert_eq!(pool.run(|| 5).await.unwrap().value, 5);
+}
+
+#[actix_web::test]
+async fn test_slow_jobs_time_out() {
+    let pool = ComputePool::new(1, 1).with_timeout(Duration::from_millis(50));
+    let (release, _job) = occupy(&pool);
+
+    // Stuck behind the running job until the timeout, then skipped
+    assert_eq!(pool.run(|| 6).await, Err(ComputeError::TimedOut));
+    release.send(()).unwrap();
+    wait_abandoned(&pool, 1);
+    assert_eq!(pool.run(|| 7).await.unwrap().value, 7);
+}
+
+#[actix_web::test]
+async fn test_panic_fails_only_its_job() {
+    let pool = ComputePool::new(1, 1);
+    assert_eq!(pool.run(|| panic!("solver bug")).await.map(|c| c.value), Err::<(), _>(ComputeError::Failed));
+    assert_eq!(pool.run(|| 4).await.unwrap().value, 4);
+}
+
+#[actix_web::test]
+async fn test_search_is_turned_away_when_busy() {
+    let pool = web::Data::new(ComputePool::new(1, 0));
+    let metrics = web::Data::new(Metrics::new());
+    let store: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(store::read_listings(store::LISTINGS_FILE).unwrap()));
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(BookingStore::default()))
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(Locations::default()))
+            .app_data(web::Data::new(FxTable::default()))
+            .app_data(web::Data::new(Limits::default()))
+            .app_data(pool.clone())
+            .app_data(metrics.clone())
+            .wrap(from_fn(record_metrics))
+            .service(search),
+    )
+    .await;
+    let fleet = serde_json::json!([{ "length": 10, "quantity": 1 }]);
+
+    let (release, _job) = occupy(&pool);
+    let req = actix_web::test::TestRequest::post().uri("/search").set_json(&fleet).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 503);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "1");
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "overloaded");
+    assert!(metrics.render().contains("neighbor_compute_rejected_total{reason=\"busy\"} 1\n"));
+
+    drop(release);
+    let mut status = 503;
+    // The thread has to get back to waiting for work before a queue of none takes a job
+    for _ in 0..100 {
+        let req = actix_web::test::TestRequest::post().uri("/search").set_json(&fleet).to_request();
+        status = actix_web::test::call_service(&app, req).await.status().as_u16();
+        if status != 503 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(status, 200);
+}
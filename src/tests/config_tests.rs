@@ -0,0 +1,189 @@
+//! Layering defaults, the config file, the environment and flags
+
+use crate::Cli;
+use clap::Parser;
+use neighbor::config::{Config, Overrides, Storage, CONFIG_ENV};
+use neighbor::store::Backend;
+use neighbor::validation::LoadMode;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn config_file(contents: &str) -> (tempfile::TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("neighbor.toml");
+    std::fs::write(&path, contents).unwrap();
+    (dir, path)
+}
+
+#[test]
+fn test_defaults_match_the_old_constants() {
+    let config = Config::load(None, Overrides::default(), env(&[])).unwrap();
+    assert_eq!(config.host, "0.0.0.0");
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.listings_file, PathBuf::from("listings.json"));
+    assert_eq!(config.backend(), Backend::Json);
+    assert_eq!(config.load_mode, LoadMode::Strict);
+    assert_eq!(config.max_vehicles, 5);
+}
+
+#[test]
+fn test_each_layer_overrides_the_last() {
+    let (_dir, path) = config_file(
+        r#"
+        port = 9000
+        workers = 2
+        storage = "sqlite"
+        max_vehicles = 8
+        "#,
+    );
+    let vars = env(&[("NEIGHBOR_PORT", "9100"), ("NEIGHBOR_WORKERS", "3"), ("NEIGHBOR_LOAD_MODE", "lenient")]);
+    let flags = Overrides {
+        port: Some(9200),
+        ..Default::default()
+    };
+
+    let config = Config::load(Some(&path), flags, vars).unwrap();
+    assert_eq!(config.port, 9200);
+    assert_eq!(config.workers, 3);
+    assert_eq!(config.load_mode, LoadMode::Lenient);
+    assert_eq!(config.storage, Storage::Sqlite);
+    assert_eq!(config.limits().max_vehicles, 8);
+    assert_eq!(config.backend(), Backend::Sqlite { path: "neighbor.db".into() });
+}
+
+#[test]
+fn test_config_file_from_the_environment() {
+    let (_dir, path) = config_file("host = \"127.0.0.1\"");
+    let config = Config::load(None, Overrides::default(), env(&[(CONFIG_ENV, path.to_str().unwrap())])).unwrap();
+    assert_eq!(config.host, "127.0.0.1");
+
+    let missing = Config::load(Some(&path.with_file_name("missing.toml")), Overrides::default(), env(&[]));
+    assert!(missing.is_err());
+}
+
+#[test]
+fn test_bad_values_are_rejected() {
+    let (_dir, path) = config_file("prot = 9000");
+    let typo = Config::load(Some(&path), Overrides::default(), env(&[])).unwrap_err();
+    assert!(typo.to_string().contains("prot"));
+
+    let bad_env = Config::load(None, Overrides::default(), env(&[("NEIGHBOR_STORAGE", "postgres")])).unwrap_err();
+    assert!(bad_env.to_string().contains("NEIGHBOR_STORAGE"));
+
+    let flags = Overrides {
+        port: Some(0),
+        workers: Some(0),
+        compute_threads: Some(0),
+        compute_queue: Some(0),
+        compute_timeout: Some(0),
+        max_vehicles: Some(0),
+        log_level: Some("neighbor=loud".to_string()),
+        ..Default::default()
+    };
+    let message = Config::load(None, flags, env(&[])).unwrap_err().to_string();
+    for setting in ["port", "workers", "compute_threads", "compute_queue", "compute_timeout", "max_vehicles", "log_level"] {
+        assert!(message.contains(setting), "{message}");
+    }
+}
+
+#[test]
+fn test_command_line_flags() {
+    let cli = Cli::try_parse_from(["neighbor", "--port", "3000", "--storage", "sqlite", "--load-mode", "lenient"]).unwrap();
+    assert_eq!(cli.overrides.port, Some(3000));
+    assert_eq!(cli.overrides.storage, Some(Storage::Sqlite));
+    assert_eq!(cli.overrides.load_mode, Some(LoadMode::Lenient));
+
+    assert!(Cli::try_parse_from(["neighbor", "--storage", "postgres"]).is_err());
+}
+
+#[test]
+fn test_printed_config_reads_back() {
+    let config = Config::load(None, Overrides { port: Some(3000), ..Default::default() }, env(&[])).unwrap();
+    let (_dir, path) = config_file(&config.to_string());
+    assert_eq!(Config::load(Some(&path), Overrides::default(), env(&[])).unwrap(), config);
+}
+
+#[test]
+fn test_admin_token_is_never_printed() {
+    let token = "a-long-enough-admin-token";
+    let config = Config::load(None, Overrides::default(), env(&[("NEIGHBOR_ADMIN_TOKEN", token)])).unwrap();
+    assert_eq!(config.admin_token.as_deref(), Some(token));
+    assert!(!config.to_string().contains(token));
+
+    let short = Config::load(None, Overrides::default(), env(&[("NEIGHBOR_ADMIN_TOKEN", "secret")])).unwrap_err();
+    assert!(short.to_string().contains("admin_token"));
+}
+
+#[test]
+fn test_route_limits_from_the_file() {
+    let (_dir, path) = config_file(
+        r#"
+        rate_limit = 100
+
+        [route_limits]
+        "/search" = 30
+        "/healthz" = 0
+        "#,
+    );
+    let vars = env(&[("NEIGHBOR_RATE_LIMIT", "120")]);
+    let config = Config::load(Some(&path), Overrides::default(), vars).unwrap();
+    assert_eq!(config.rate_limit, 120);
+    assert_eq!(config.route_limits["/search"], 30);
+    assert_eq!(config.route_limits["/healthz"], 0);
+
+    let (_dir, printed) = config_file(&config.to_string());
+    assert_eq!(Config::load(Some(&printed), Overrides::default(), env(&[])).unwrap(), config);
+
+    let (_dir, path) = config_file("[route_limits]\nsearch = 30");
+    let message = Config::load(Some(&path), Overrides::default(), env(&[])).unwrap_err().to_string();
+    assert!(message.contains("route_limits"), "{message}");
+}
+
+#[test]
+fn test_api_keys_and_trusted_proxies() {
+    let (_dir, path) = config_file(
+        r#"
+        api_keys = ["partner-key-one"]
+        trusted_proxies = ["10.0.0.0/8"]
+        "#,
+    );
+    let vars = env(&[("NEIGHBOR_API_KEYS", "partner-key-two, partner-key-three")]);
+    let flags = Cli::try_parse_from(["neighbor", "--trusted-proxies", "fdaa::/16,172.16.0.1"]).unwrap().overrides;
+    let config = Config::load(Some(&path), flags, vars).unwrap();
+    assert_eq!(config.api_keys, ["partner-key-two", "partner-key-three"]);
+    let proxies: Vec<String> = config.trusted_proxies.iter().map(ToString::to_string).collect();
+    assert_eq!(proxies, ["fdaa::/16", "172.16.0.1"]);
+
+    // Keys are secrets, the proxies are printed and read back
+    let printed = config.to_string();
+    assert!(!printed.contains("partner-key"), "{printed}");
+    assert!(printed.contains("fdaa::/16"), "{printed}");
+    let (_dir, printed) = config_file(&printed);
+    let read_back = Config::load(Some(&printed), Overrides::default(), env(&[])).unwrap();
+    assert_eq!(read_back.trusted_proxies, config.trusted_proxies);
+
+    let bad = env(&[("NEIGHBOR_TRUSTED_PROXIES", "10.0.0.0/40")]);
+    let message = Config::load(None, Overrides::default(), bad).unwrap_err().to_string();
+    assert!(message.contains("NEIGHBOR_TRUSTED_PROXIES"), "{message}");
+    let (_dir, path) = config_file(r#"api_keys = [" "]"#);
+    let message = Config::load(Some(&path), Overrides::default(), env(&[])).unwrap_err().to_string();
+    assert!(message.contains("api_keys"), "{message}");
+}
+
+#[test]
+fn test_malformed_settings_files_are_errors() {
+    let (_dir, path) = config_file("{\"loc1\": {\"tax_rate_bps\": \"ten\"}");
+    let config = Config { locations_file: path, ..Config::default() };
+    let error = crate::load_settings(&config).unwrap_err();
+    assert!(format!("{error:#}").starts_with("Invalid locations file"));
+
+    let (_dir, path) = config_file("{\"base\": \"USD\", \"rates\": {\"EUR\": -1.0}}");
+    let config = Config { fx_file: path, ..Config::default() };
+    let error = crate::load_settings(&config).unwrap_err();
+    assert!(format!("{error:#}").starts_with("Invalid exchange rates file"));
+}
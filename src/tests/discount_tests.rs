@@ -0,0 +1,114 @@
+//! Host bundle discounts and how they change the cheapest combination
+
+use super::listing;
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::discounts::{self, Discount, DiscountKind};
+use neighbor::fx::FxTable;
+use neighbor::locations::{Location, Locations};
+use neighbor::model::{Listing, Vehicle};
+use neighbor::money::Money;
+use std::sync::Arc;
+use validator::Validate;
+
+fn percent_off(min_listings: usize, percent: i32) -> Discount {
+    Discount {
+        name: None,
+        min_listings,
+        kind: DiscountKind::Percentage { percent },
+    }
+}
+
+fn flat_off(min_listings: usize, amount_in_cents: i64) -> Discount {
+    Discount {
+        name: None,
+        min_listings,
+        kind: DiscountKind::Flat { amount_in_cents },
+    }
+}
+
+fn with_discounts(discounts: Vec<Discount>) -> SearchOptions {
+    let mut locations = Locations::default();
+    locations.insert(
+        "loc1",
+        Location {
+            discounts,
+            ..Default::default()
+        },
+    );
+    SearchOptions {
+        locations: Arc::new(locations),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_amount_off() {
+    let fx = FxTable::default();
+    assert_eq!(percent_off(1, 10).amount_off(Money::usd(1005), &fx), Ok(Money::usd(100)));
+    assert_eq!(flat_off(1, 300).amount_off(Money::usd(1000), &fx), Ok(Money::usd(300)));
+    // A flat discount never makes the price negative
+    assert_eq!(flat_off(1, 300).amount_off(Money::usd(200), &fx), Ok(Money::usd(200)));
+}
+
+#[test]
+fn test_best_discount_respects_threshold() {
+    let rules = vec![percent_off(2, 10), flat_off(3, 500)];
+
+    let best = |count| discounts::best_discount(&rules, count, Money::usd(1000), &FxTable::default()).unwrap();
+
+    assert!(best(1).is_none());
+    assert_eq!(best(2).unwrap().amount_in_cents, 100);
+    assert_eq!(best(3).unwrap().amount_in_cents, 500);
+}
+
+#[test]
+fn test_invalid_discounts() {
+    assert!(percent_off(2, 0).validate().is_err());
+    assert!(percent_off(2, 101).validate().is_err());
+    assert!(flat_off(2, -5).validate().is_err());
+    assert!(flat_off(0, 5).validate().is_err());
+    assert!(percent_off(2, 10).validate().is_ok());
+}
+
+#[test]
+fn test_discount_changes_winning_combination() {
+    let vehicles = vec![10, 10];
+    let listings = vec![
+        Listing { length: 20, price: Money::usd(1000), ..listing("big") },
+        Listing { length: 10, price: Money::usd(550), ..listing("small-a") },
+        Listing { length: 10, price: Money::usd(550), ..listing("small-b") },
+    ];
+
+    let result = bin_packing::find_cheapest_combination(&vehicles, &listings).unwrap();
+    assert_eq!(result.listing_ids, vec!["big"]);
+    assert!(result.discount.is_none());
+
+    let options = with_discounts(vec![percent_off(2, 10)]);
+    let result = bin_packing::find_cheapest_combination_with(&vehicles, &listings, &options).unwrap();
+    assert_eq!(result.listing_ids, vec!["small-a", "small-b"]);
+    assert_eq!(result.total, Money::usd(990));
+    assert_eq!(result.discount.unwrap().amount_in_cents, 110);
+}
+
+#[test]
+fn test_search_reports_applied_discount() {
+    let vehicles = vec![Vehicle { length: 10, quantity: 2 }];
+    let listings = vec![
+        Listing { length: 10, price: Money::usd(500), ..listing("a") },
+        Listing { length: 10, price: Money::usd(500), ..listing("b") },
+    ];
+
+    let options = with_discounts(vec![flat_off(2, 150)]);
+    let results = bin_packing::search_locations_with(vehicles, &listings, &options);
+
+    assert_eq!(results[0].total_price_in_cents, 850);
+    let json = serde_json::to_value(&results[0]).unwrap();
+    assert_eq!(json["discount"]["type"], "flat");
+    assert_eq!(json["discount"]["amount_in_cents"], 150);
+}
+
+#[test]
+fn test_missing_locations_file_has_no_rules() {
+    let locations = Locations::load("does-not-exist.json").unwrap();
+    assert!(locations.discounts_for("loc1").is_empty());
+}
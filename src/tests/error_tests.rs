@@ -0,0 +1,78 @@
+//! The error body every failed request gets
+
+use neighbor::errors::{self, ApiError};
+use neighbor::model::{Limits, SearchQuery};
+use validator::ValidateArgs;
+
+fn query(body: serde_json::Value) -> SearchQuery {
+    serde_json::from_value(body).unwrap()
+}
+
+#[test]
+fn test_validation_paths() {
+    let query = query(serde_json::json!({
+        "vehicles": [{ "length": 10, "quantity": 1 }, { "length": 15, "quantity": 0 }],
+        "filters": { "max_listings": 0 },
+        "limit": 0
+    }));
+    let invalid = query.validate_with_args(&Limits::default()).unwrap_err();
+
+    let paths: Vec<Option<String>> = errors::flatten(&invalid).into_iter().map(|(field, _)| field).collect();
+    assert_eq!(
+        paths,
+        vec![
+            Some("filters.max_listings".to_string()),
+            Some("limit".to_string()),
+            Some("vehicles[1].quantity".to_string()),
+        ]
+    );
+
+    let error = ApiError::validation(&invalid);
+    assert_eq!(error.code, "validation_failed");
+    assert_eq!(error.fields.len(), 3);
+    assert_eq!(error.details.lines().count(), 3);
+}
+
+#[test]
+fn test_schema_errors_belong_to_their_struct() {
+    let fields = |body| {
+        let error = ApiError::validation(&query(body).validate_with_args(&Limits::default()).unwrap_err());
+        error
+            .fields
+            .into_iter()
+            .map(|f| (f.field, f.code, f.message))
+            .collect::<Vec<_>>()
+    };
+
+    let paged = fields(serde_json::json!({
+        "vehicles": [{ "length": 10, "quantity": 1 }],
+        "offset": 2,
+        "cursor": "abc"
+    }));
+    assert_eq!(
+        paged,
+        vec![(
+            None,
+            "offset_and_cursor_not_allowed_together".to_string(),
+            "offset and cursor not allowed together".to_string()
+        )]
+    );
+
+    let dated = fields(serde_json::json!({
+        "vehicles": [{ "length": 10, "quantity": 1 }],
+        "options": { "start_date": "2025-01-01" }
+    }));
+    assert_eq!(dated[0].0.as_deref(), Some("options.period"));
+    assert_eq!(dated[0].1, "start_date_and_end_date_required_together");
+}
+
+#[test]
+fn test_json_errors() {
+    let malformed = serde_json::from_str::<SearchQuery>("{").unwrap_err();
+    assert_eq!(ApiError::json(&malformed).code, "malformed_json");
+
+    let unknown = serde_json::from_str::<SearchQuery>(r#"{ "vehicles": [], "colour": "red" }"#).unwrap_err();
+    let error = ApiError::json(&unknown);
+    assert_eq!(error.code, "invalid_request");
+    assert!(error.details.contains("colour"));
+}
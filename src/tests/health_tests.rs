@@ -0,0 +1,33 @@
+//! `neighbor healthcheck` asking a running server
+
+use neighbor::config::Config;
+use neighbor::health;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+#[test]
+fn test_healthcheck_command_uses_the_configured_port() {
+    let answer = |status: &'static str| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                assert!(read > 0, "the request ended early");
+                request.extend_from_slice(&buffer[..read]);
+            }
+            assert!(request.starts_with(b"GET /readyz HTTP/1.0"));
+            stream.write_all(format!("HTTP/1.1 {status}\r\n\r\n").as_bytes()).unwrap();
+        });
+        Config { port, ..Default::default() }
+    };
+
+    assert!(health::probe(&answer("200 OK"), "/readyz").unwrap());
+    assert!(!health::probe(&answer("503 Service Unavailable"), "/readyz").unwrap());
+
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert!(health::probe(&Config { port: closed, ..Default::default() }, "/healthz").is_err());
+}
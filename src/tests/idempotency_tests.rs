@@ -0,0 +1,91 @@
+//! Idempotency key bookkeeping
+
+use neighbor::idempotency::{Begin, IdempotencyStore};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_first_use_then_replay() {
+    let store = IdempotencyStore::default();
+    assert_eq!(store.begin("key", "body"), Begin::New);
+    assert_eq!(store.begin("key", "body"), Begin::InProgress);
+
+    store.complete("key", 201, json!({"id": "abc"}));
+    assert_eq!(
+        store.begin("key", "body"),
+        Begin::Replay { status: 201, body: json!({"id": "abc"}) }
+    );
+}
+
+#[test]
+fn test_key_reused_for_other_request() {
+    let store = IdempotencyStore::default();
+    assert_eq!(store.begin("key", "body"), Begin::New);
+    store.complete("key", 201, json!({}));
+    assert_eq!(store.begin("key", "other body"), Begin::Mismatch);
+}
+
+#[test]
+fn test_expired_keys_are_forgotten() {
+    let store = IdempotencyStore::new(Duration::ZERO);
+    assert_eq!(store.begin("key", "body"), Begin::New);
+    store.complete("key", 201, json!({}));
+    assert_eq!(store.begin("key", "body"), Begin::New);
+}
+
+#[test]
+fn test_threads_racing_for_one_key() {
+    let store = IdempotencyStore::default();
+    let bookings_made = AtomicUsize::new(0);
+    let barrier = Barrier::new(16);
+
+    let outcomes: Vec<Begin> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let begin = store.begin("key", "body");
+                    if begin == Begin::New {
+                        let claim = store.claim("key");
+                        bookings_made.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        claim.complete(201, json!({ "id": "abc" }));
+                    }
+                    begin
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    assert_eq!(bookings_made.load(Ordering::SeqCst), 1);
+    assert_eq!(outcomes.iter().filter(|b| **b == Begin::New).count(), 1);
+    // The others either saw it running or, if they were late, its response
+    assert!(outcomes
+        .iter()
+        .all(|b| matches!(b, Begin::New | Begin::InProgress | Begin::Replay { status: 201, .. })));
+    assert!(matches!(store.begin("key", "body"), Begin::Replay { status: 201, .. }));
+}
+
+#[test]
+fn test_panic_releases_the_key() {
+    let store = IdempotencyStore::default();
+    let result = thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                assert_eq!(store.begin("key", "body"), Begin::New);
+                let _claim = store.claim("key");
+                panic!("booking failed");
+            })
+            .join()
+    });
+    assert!(result.is_err());
+    assert_eq!(store.begin("key", "body"), Begin::New);
+
+    // A completed key stays put when its claim goes away
+    store.claim("key").complete(201, json!({}));
+    assert_eq!(store.begin("key", "body"), Begin::Replay { status: 201, body: json!({}) });
+}
@@ -0,0 +1,124 @@
+//! Linting and summarizing a catalog before it ships
+
+use super::listing;
+use neighbor::cli::{self, CatalogArgs, CatalogCommand};
+use neighbor::config::Config;
+use neighbor::inspect::{self, Severity, Summary};
+use neighbor::model::Listing;
+use neighbor::money::Money;
+use neighbor::validation::{self, LoadMode};
+use std::path::PathBuf;
+
+/// Eight ordinary listings over four locations
+fn ordinary() -> Vec<Listing> {
+    (0..8)
+        .map(|i| Listing {
+            location_id: format!("loc{}", i % 4),
+            price: Money::usd(2000 + 100 * i),
+            ..listing(&format!("l{i}"))
+        })
+        .collect()
+}
+
+fn write(dir: &tempfile::TempDir, listings: &[Listing]) -> PathBuf {
+    let path = dir.path().join("listings.json");
+    std::fs::write(&path, serde_json::to_string(listings).unwrap()).unwrap();
+    path
+}
+
+#[test]
+fn test_summary_quartiles() {
+    let summary = Summary::of(&[4.0, 1.0, 3.0, 2.0, 5.0]).unwrap();
+    assert_eq!((summary.min, summary.p25, summary.median, summary.p75, summary.max), (1.0, 2.0, 3.0, 4.0, 5.0));
+    assert_eq!(summary.mean, 3.0);
+    assert!(Summary::of(&[]).is_none());
+}
+
+#[test]
+fn test_check_reports_errors_and_outliers() {
+    let mut listings = ordinary();
+    listings.insert(1, Listing { length: 15, location_id: "loc0".to_string(), price: Money::usd(2000), ..listing("bad") });
+    listings.push(Listing { price: Money::usd(2000), ..listing("l0") });
+    listings.push(Listing { location_id: "loc2".to_string(), price: Money::usd(2_000_000), ..listing("gold") });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(&dir, &listings);
+    let (loaded, report) = validation::load_listings(&path, LoadMode::Lenient).unwrap();
+    let check = inspect::check(&report, &loaded);
+
+    assert_eq!(check.errors, 2);
+    let errors: Vec<usize> = check
+        .findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .filter_map(|f| f.index)
+        .collect();
+    assert_eq!(errors, vec![1, 9]);
+
+    // The skipped listing doesn't throw off the index of the outlier
+    let outlier = check.findings.iter().find(|f| f.id.as_deref() == Some("gold")).unwrap();
+    assert_eq!(outlier.severity, Severity::Warning);
+    assert_eq!(outlier.index, Some(10));
+}
+
+#[test]
+fn test_crowded_locations_are_flagged() {
+    let mut listings = ordinary();
+    listings.extend((0..12).map(|i| Listing {
+        location_id: "busy".to_string(),
+        price: Money::usd(2000),
+        ..listing(&format!("crowded{i}"))
+    }));
+
+    let dir = tempfile::tempdir().unwrap();
+    let (loaded, report) = validation::load_listings(write(&dir, &listings), LoadMode::Lenient).unwrap();
+    let check = inspect::check(&report, &loaded);
+    let flagged: Vec<&str> = check.findings.iter().filter_map(|f| f.location_id.as_deref()).collect();
+    assert_eq!(flagged, vec!["busy"]);
+}
+
+#[test]
+fn test_stats() {
+    let mut listings = ordinary();
+    listings.push(Listing {
+        length: 40,
+        width: 20,
+        location_id: "loc0".to_string(),
+        price: Money::usd(8000),
+        ..listing("big")
+    });
+    let stats = inspect::stats(&listings);
+
+    assert_eq!(stats.listings, 9);
+    assert_eq!(stats.locations, 4);
+    assert_eq!(stats.sizes[0].listings, 8);
+    assert_eq!((stats.sizes[1].length, stats.sizes[1].width), (40, 20));
+    assert_eq!(stats.listings_per_location.unwrap().max, 3.0);
+    assert_eq!(stats.price_per_square_foot.values().next().unwrap().min, 10.0);
+}
+
+#[test]
+fn test_check_gates_on_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let check = |listings: &[Listing], deny_warnings: bool| {
+        let command = CatalogCommand::Check {
+            catalog: CatalogArgs {
+                listings: Some(write(&dir, listings)),
+                ..Default::default()
+            },
+            deny_warnings,
+        };
+        cli::catalog(&command, &Config::default(), &mut Vec::new()).unwrap()
+    };
+
+    assert!(check(&ordinary(), false));
+
+    let mut duplicate = ordinary();
+    duplicate.push(Listing { location_id: "loc0".to_string(), price: Money::usd(2000), ..listing("l0") });
+    assert!(!check(&duplicate, false));
+
+    let mut outlier = ordinary();
+    outlier.push(Listing { location_id: "loc0".to_string(), price: Money::usd(2_000_000), ..listing("gold") });
+    assert!(check(&outlier, false));
+    assert!(!check(&outlier, true));
+}
@@ -1,8 +1,9 @@
 //! This is using all the info I was given on the README
 //! The solution should pass for each README example 
 
-use crate::bin_packing;
-use crate::model::{AllListings, Vehicle};
+use neighbor::bin_packing;
+use neighbor::model::Vehicle;
+use neighbor::store::{self, LISTINGS_FILE};
 
 use std::collections::HashSet;
 
@@ -14,8 +15,8 @@ fn test_readme_example() {
         quantity: 1,
     }];
 
-    let listings = AllListings::get();
-    let results = bin_packing::search_locations(vehicles, listings.inner());
+    let listings = store::read_listings(LISTINGS_FILE).unwrap();
+    let results = bin_packing::search_locations(vehicles, &listings);
 
     assert!(!results.is_empty(), "Should return at least one result");
     assert!(
@@ -100,8 +101,8 @@ fn test_readme_complex_example() {
         },
     ];
 
-    let listings = AllListings::get(); 
-    let results = bin_packing::search_locations(vehicles, listings.inner());
+    let listings = store::read_listings(LISTINGS_FILE).unwrap();
+    let results = bin_packing::search_locations(vehicles, &listings);
 
     // Should return some results
     assert!(!results.is_empty(), "Should find locations that fit all vehicles");
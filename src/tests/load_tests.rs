@@ -0,0 +1,146 @@
+//! Checking listings files before they are loaded
+
+use super::listing;
+use neighbor::store::{JsonFileStore, ListingStore, LISTINGS_FILE};
+use neighbor::validation::{self, LoadError, LoadMode, Problem};
+use serde_json::json;
+use std::path::PathBuf;
+
+fn write(dir: &tempfile::TempDir, records: serde_json::Value) -> PathBuf {
+    let path = dir.path().join("listings.json");
+    std::fs::write(&path, records.to_string()).unwrap();
+    path
+}
+
+/// One of each kind of problem around two good listings
+fn mixed_file(dir: &tempfile::TempDir) -> PathBuf {
+    let mut no_width = json!(listing("no-width"));
+    no_width.as_object_mut().unwrap().remove("width");
+    let mut bad_dimensions = json!(listing("bad-dimensions"));
+    bad_dimensions["length"] = json!(15);
+    bad_dimensions["price_in_cents"] = json!(0);
+    let mut bad_pricing = json!(listing("bad-pricing"));
+    bad_pricing["pricing"] = json!({ "weekly_in_cents": 0 });
+
+    write(
+        dir,
+        json!([listing("a"), no_width, bad_dimensions, listing("b"), bad_pricing, listing("a")]),
+    )
+}
+
+fn fields(problems: &[Problem], index: usize) -> Vec<String> {
+    let mut fields: Vec<String> = problems
+        .iter()
+        .filter(|p| p.index == index)
+        .filter_map(|p| p.field.clone())
+        .collect();
+    fields.sort();
+    fields
+}
+
+#[test]
+fn test_the_listings_file_is_clean() {
+    let (listings, report) = validation::load_listings(LISTINGS_FILE, LoadMode::Strict).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.valid, listings.len());
+}
+
+#[test]
+fn test_strict_reports_every_problem() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = mixed_file(&dir);
+
+    let Err(LoadError::Rejected(report)) = validation::load_listings(&path, LoadMode::Strict) else {
+        panic!("Strict mode loaded a file with problems");
+    };
+    assert_eq!(report.total, 6);
+    assert_eq!(report.valid, 2);
+
+    assert_eq!(fields(&report.problems, 1), vec!["width"]);
+    assert_eq!(fields(&report.problems, 2), vec!["length", "price_in_cents"]);
+    assert_eq!(fields(&report.problems, 4), vec!["pricing.weekly_in_cents"]);
+    assert_eq!(fields(&report.problems, 5), vec!["id"]);
+    assert!(report.problems.iter().all(|p| p.index != 0 && p.index != 3));
+
+    let duplicate = report.problems.iter().find(|p| p.index == 5).unwrap();
+    assert_eq!(duplicate.message, "duplicate of listing 0");
+
+    // Every problem ends up in the message printed at startup
+    let message = LoadError::Rejected(report.clone()).to_string();
+    for problem in &report.problems {
+        assert!(message.contains(&problem.to_string()));
+    }
+}
+
+#[test]
+fn test_lenient_skips_bad_listings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = mixed_file(&dir);
+
+    let (listings, report) = validation::load_listings(&path, LoadMode::Lenient).unwrap();
+    let ids: Vec<&str> = listings.iter().map(|l| l.id.as_str()).collect();
+    assert_eq!(ids, vec!["a", "b"]);
+    assert_eq!(report.valid, 2);
+    assert!(!report.is_clean());
+
+    let (store, _) = JsonFileStore::open_with(&path, LoadMode::Lenient).unwrap();
+    assert_eq!(store.listings().len(), 2);
+    assert!(JsonFileStore::open(&path).is_err());
+}
+
+#[test]
+fn test_lenient_edits_keep_the_skipped_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = mixed_file(&dir);
+    let (store, _) = JsonFileStore::open_with(&path, LoadMode::Lenient).unwrap();
+
+    store.insert(listing("c")).unwrap();
+    store.delete("b").unwrap();
+
+    let (listings, report) = validation::load_listings(&path, LoadMode::Lenient).unwrap();
+    let ids: Vec<&str> = listings.iter().map(|l| l.id.as_str()).collect();
+    assert_eq!(ids, vec!["a", "c"]);
+    let mut skipped: Vec<Option<&str>> = report.problems.iter().map(|p| p.id.as_deref()).collect();
+    skipped.dedup();
+    assert_eq!(skipped, vec![Some("no-width"), Some("bad-dimensions"), Some("bad-pricing"), Some("a")]);
+    assert_eq!(report.skipped.len(), 4);
+}
+
+#[test]
+fn test_locations_have_a_cap() {
+    let dir = tempfile::tempdir().unwrap();
+    let ids: Vec<String> = (0..=validation::MAX_LISTINGS_PER_LOCATION).map(|i| i.to_string()).collect();
+    let path = write(&dir, ids.iter().map(|id| json!(listing(id))).collect());
+
+    let (listings, report) = validation::load_listings(&path, LoadMode::Lenient).unwrap();
+    assert_eq!(listings.len(), validation::MAX_LISTINGS_PER_LOCATION);
+    assert_eq!(fields(&report.problems, validation::MAX_LISTINGS_PER_LOCATION), vec!["location_id"]);
+}
+
+#[test]
+fn test_files_that_cant_be_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let missing = validation::load_listings(dir.path().join("missing.json"), LoadMode::Lenient);
+    assert!(matches!(missing, Err(LoadError::Unreadable(_))));
+
+    let path = write(&dir, json!({ "listings": [] }));
+    let not_an_array = validation::load_listings(&path, LoadMode::Lenient);
+    assert!(matches!(not_an_array, Err(LoadError::Malformed(_))));
+}
+
+#[test]
+fn test_lenient_reload_counts_skipped_listings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(&dir, json!([listing("a")]));
+    let (store, report) = JsonFileStore::open_with(&path, LoadMode::Lenient).unwrap();
+    assert!(report.is_clean());
+
+    let mut bad = json!(listing("bad"));
+    bad["width"] = json!(-10);
+    write(&dir, json!([listing("a"), listing("b"), bad]));
+
+    let reload = store.reload().unwrap();
+    assert_eq!(reload.listings, 2);
+    assert_eq!(reload.skipped, 1);
+}
@@ -0,0 +1,157 @@
+//! Request ids and the spans a search logs
+
+use crate::{errors, request_span, search, set_log_level};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App};
+use neighbor::auth::{self, AdminToken};
+use neighbor::bookings::BookingStore;
+use neighbor::compute::ComputePool;
+use neighbor::fx::FxTable;
+use neighbor::locations::Locations;
+use neighbor::logging::{self, LevelHandle};
+use neighbor::model::Limits;
+use neighbor::store::{self, ListingStore, MemoryStore};
+use serde_json::{json, Value};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Log lines written so far
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<Value> {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+/// Lines logged when a span called `name` closed
+fn closed<'a>(lines: &'a [Value], name: &str) -> Vec<&'a Value> {
+    lines
+        .iter()
+        .filter(|line| line["fields"]["message"] == "close" && line["span"]["name"] == name)
+        .collect()
+}
+
+#[test]
+fn test_request_ids() {
+    assert_eq!(logging::request_id(Some("abc-123")), "abc-123");
+    for unusable in [None, Some(""), Some("has space"), Some("naïve")] {
+        let id = logging::request_id(unusable);
+        assert_eq!(id.len(), 36, "{unusable:?} gave {id}");
+    }
+    let long = "a".repeat(200);
+    assert_ne!(logging::request_id(Some(&long)), long);
+    assert_ne!(logging::request_id(None), logging::request_id(None));
+}
+
+#[test]
+fn test_log_level_is_checked() {
+    let (_subscriber, level) = logging::subscriber("info", io::sink).unwrap();
+    assert!(level.set("neighbor=loud").is_err());
+    assert_eq!(level.get().level, "info");
+    assert_eq!(level.set("warn,neighbor::bin_packing=debug").unwrap().level, "warn,neighbor::bin_packing=debug");
+    assert!(logging::subscriber("neighbor=loud", io::sink).is_err());
+}
+
+const TOKEN: &str = "an-admin-token-for-tests";
+
+#[actix_web::test]
+async fn test_search_spans_carry_the_request_id() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let (subscriber, level) = logging::subscriber("info", move || writer.clone()).unwrap();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let store: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(store::read_listings(store::LISTINGS_FILE).unwrap()));
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(BookingStore::default()))
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(Locations::default()))
+            .app_data(web::Data::new(FxTable::default()))
+            .app_data(web::Data::new(Limits::default()))
+            .app_data(web::Data::new(ComputePool::default()))
+            .app_data(web::Data::<LevelHandle>::new(level))
+            .app_data(web::Data::new(AdminToken::new(Some(TOKEN.to_string()))))
+            .wrap(from_fn(request_span))
+            .configure(errors)
+            .service(search)
+            .service(set_log_level),
+    )
+    .await;
+    let fleet = json!([{ "length": 10, "quantity": 1 }]);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/search")
+        .insert_header((logging::REQUEST_ID, "abc-123"))
+        .set_json(&fleet)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get(logging::REQUEST_ID).unwrap(), "abc-123");
+
+    let lines = captured.lines();
+    let request = closed(&lines, "request");
+    assert_eq!(request.len(), 1);
+    assert_eq!(request[0]["span"]["request_id"], "abc-123");
+    assert_eq!(request[0]["span"]["route"], "/search");
+    assert_eq!(request[0]["span"]["status"], 200);
+    for phase in ["validate", "group", "solve", "serialize"] {
+        let phase = closed(&lines, phase);
+        assert_eq!(phase.len(), 1);
+        assert_eq!(phase[0]["spans"][0]["request_id"], "abc-123");
+        assert!(phase[0]["fields"]["time.busy"].is_string());
+    }
+    // Each location only at debug
+    assert!(closed(&lines, "solve_location").is_empty());
+
+    // Like the other admin routes it needs the token
+    let req = actix_web::test::TestRequest::put()
+        .uri("/admin/log-level")
+        .set_json(json!({ "level": "debug" }))
+        .to_request();
+    assert_eq!(actix_web::test::call_service(&app, req).await.status(), 401);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/admin/log-level")
+        .insert_header((auth::AUTHORIZATION, format!("Bearer {TOKEN}")))
+        .set_json(json!({ "level": "info,neighbor::bin_packing=debug" }))
+        .to_request();
+    assert!(actix_web::test::call_service(&app, req).await.status().is_success());
+    captured.lines();
+
+    let req = actix_web::test::TestRequest::post().uri("/search").set_json(&fleet).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    let id = resp.headers().get(logging::REQUEST_ID).unwrap().to_str().unwrap().to_string();
+    let lines = captured.lines();
+    let locations = closed(&lines, "solve_location");
+    assert!(!locations.is_empty());
+    assert!(locations.iter().all(|line| line["spans"][0]["request_id"] == id.as_str()));
+    assert!(locations[0]["span"]["location_id"].is_string());
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/admin/log-level")
+        .insert_header((auth::AUTHORIZATION, format!("Bearer {TOKEN}")))
+        .set_json(json!({ "level": "neighbor=loud" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["fields"][0]["code"], "invalid_log_level");
+}
@@ -0,0 +1,99 @@
+//! What `/metrics` reports after some traffic
+
+use crate::{errors, metrics_text, record_metrics, search, search_batch, search_v1};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App};
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::bookings::BookingStore;
+use neighbor::compute::ComputePool;
+use neighbor::fx::FxTable;
+use neighbor::locations::Locations;
+use neighbor::metrics::Metrics;
+use neighbor::model::{Limits, Vehicle};
+use neighbor::store::{self, ListingStore, MemoryStore};
+use serde_json::json;
+use std::sync::Arc;
+
+/// The value of the first sample whose line starts with `prefix`
+fn sample(text: &str, prefix: &str) -> f64 {
+    text.lines()
+        .find(|line| line.starts_with(prefix))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("no sample {prefix} in\n{text}"))
+}
+
+#[test]
+fn test_search_counts_subsets() {
+    let listings = store::read_listings(store::LISTINGS_FILE).unwrap();
+    let options = SearchOptions::default();
+    bin_packing::search_locations_with(vec![Vehicle { length: 10, quantity: 1 }], &listings, &options);
+
+    // Every location tries each non-empty subset of its listings once
+    let mut per_location = std::collections::HashMap::new();
+    for listing in &listings {
+        *per_location.entry(&listing.location_id).or_insert(0u32) += 1;
+    }
+    let expected: u64 = per_location.values().map(|&n| (1u64 << n) - 1).sum();
+    assert_eq!(options.stats.subsets(), expected);
+}
+
+#[actix_web::test]
+async fn test_metrics_after_searches() {
+    let store: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(store::read_listings(store::LISTINGS_FILE).unwrap()));
+    let catalog_size = store.listings().len();
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(BookingStore::default()))
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(Locations::default()))
+            .app_data(web::Data::new(FxTable::default()))
+            .app_data(web::Data::new(Limits::default()))
+            .app_data(web::Data::new(ComputePool::default()))
+            .app_data(web::Data::new(Metrics::new()))
+            .wrap(from_fn(record_metrics))
+            .configure(errors)
+            .service(search)
+            .service(search_batch)
+            .service(search_v1)
+            .service(metrics_text),
+    )
+    .await;
+
+    let fleet = json!([{ "length": 10, "quantity": 1 }]);
+    for _ in 0..2 {
+        let req = actix_web::test::TestRequest::post().uri("/search").set_json(&fleet).to_request();
+        assert!(actix_web::test::call_service(&app, req).await.status().is_success());
+    }
+    let batch = json!([{ "id": "a", "vehicles": fleet }, { "id": "b", "vehicles": [] }]);
+    let req = actix_web::test::TestRequest::post().uri("/search/batch").set_json(batch).to_request();
+    assert!(actix_web::test::call_service(&app, req).await.status().is_success());
+    let req = actix_web::test::TestRequest::post().uri("/search").set_json(json!([])).to_request();
+    assert_eq!(actix_web::test::call_service(&app, req).await.status(), 400);
+    let req = actix_web::test::TestRequest::get().uri("/listings/nope").to_request();
+    assert_eq!(actix_web::test::call_service(&app, req).await.status(), 404);
+
+    let req = actix_web::test::TestRequest::get().uri("/metrics").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain; version=0.0.4");
+    let text = String::from_utf8(actix_web::test::read_body(resp).await.to_vec()).unwrap();
+
+    let requests = r#"neighbor_http_requests_total{method="POST",route="/search",status="#;
+    assert_eq!(sample(&text, &format!(r#"{requests}"200"}}"#)), 2.0);
+    assert_eq!(sample(&text, &format!(r#"{requests}"400"}}"#)), 1.0);
+    // Routes that don't exist share one series instead of one per path
+    assert_eq!(sample(&text, r#"neighbor_http_requests_total{method="GET",route="unmatched",status="404"}"#), 1.0);
+
+    // The failed search isn't timed, the batch is timed once with one search for its good item
+    assert_eq!(sample(&text, r#"neighbor_search_duration_seconds_count{endpoint="/search"}"#), 2.0);
+    assert_eq!(sample(&text, r#"neighbor_search_duration_seconds_count{endpoint="/search/batch"}"#), 1.0);
+    assert_eq!(sample(&text, "neighbor_search_results_count"), 3.0);
+    assert_eq!(sample(&text, "neighbor_search_subsets_evaluated_count"), 3.0);
+    assert!(sample(&text, "neighbor_search_subsets_evaluated_sum") > 0.0);
+    assert_eq!(sample(&text, "neighbor_compute_queue_wait_seconds_count"), 3.0);
+    assert_eq!(sample(&text, "neighbor_compute_queue_depth"), 0.0);
+
+    assert_eq!(sample(&text, "neighbor_catalog_listings"), catalog_size as f64);
+    assert_eq!(sample(&text, "neighbor_catalog_version"), 1.0);
+    assert!(sample(&text, "neighbor_catalog_changed_timestamp_seconds") > 0.0);
+}
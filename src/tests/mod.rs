@@ -1,4 +1,40 @@
 mod api_tests;
+mod availability_tests;
 mod bin_packing_tests;
+mod booking_tests;
+mod catalog_tests;
+mod cli_tests;
+mod compute_tests;
+mod config_tests;
+mod discount_tests;
+mod error_tests;
+mod health_tests;
+mod idempotency_tests;
+mod inspect_tests;
 mod integration_tests;
+mod load_tests;
+mod logging_tests;
+mod metrics_tests;
+mod money_tests;
+mod openapi_tests;
+mod pricing_tests;
+mod quote_tests;
+mod rate_limit_tests;
+mod reload_tests;
+mod sqlite_tests;
 mod validation_tests;
+
+use neighbor::model::Listing;
+use neighbor::money::Money;
+
+/// A valid 20x10 listing at loc1 for $10 a month, change the rest with `..listing(id)`
+pub fn listing(id: &str) -> Listing {
+    Listing {
+        id: id.to_string(),
+        location_id: "loc1".to_string(),
+        length: 20,
+        width: 10,
+        price: Money::usd(1000),
+        ..Default::default()
+    }
+}
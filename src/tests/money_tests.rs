@@ -0,0 +1,128 @@
+//! Checked money arithmetic and pricing listings in several currencies
+
+use super::listing;
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::fx::FxTable;
+use neighbor::model::{Listing, Vehicle};
+use neighbor::money::{Currency, Money, MoneyError};
+use std::sync::Arc;
+
+fn eur() -> Currency {
+    "EUR".parse().unwrap()
+}
+
+fn fx() -> FxTable {
+    let mut fx = FxTable::default();
+    fx.set_rate(eur(), 1.25).unwrap();
+    fx
+}
+
+#[test]
+fn test_checked_arithmetic() {
+    assert_eq!(Money::usd(100).checked_add(Money::usd(250)), Ok(Money::usd(350)));
+    assert_eq!(Money::usd(i64::MAX).checked_add(Money::usd(1)), Err(MoneyError::Overflow));
+    assert_eq!(Money::usd(i64::MAX).checked_mul(2), Err(MoneyError::Overflow));
+    assert_eq!(
+        Money::usd(100).checked_add(Money::new(100, eur())),
+        Err(MoneyError::CurrencyMismatch { expected: Currency::USD, found: eur() })
+    );
+}
+
+#[test]
+fn test_basis_points_round_half_up() {
+    assert_eq!(Money::usd(1000).basis_points(825), Ok(Money::usd(83)));
+    assert_eq!(Money::usd(1005).basis_points(500), Ok(Money::usd(50)));
+    assert_eq!(Money::usd(1010).basis_points(500), Ok(Money::usd(51)));
+}
+
+#[test]
+fn test_ratio_down_drops_fractions() {
+    assert_eq!(Money::usd(1005).checked_ratio_down(10, 100), Ok(Money::usd(100)));
+    assert_eq!(Money::usd(1009).checked_ratio_down(10, 100), Ok(Money::usd(100)));
+    assert_eq!(Money::usd(1005).checked_ratio(10, 100), Ok(Money::usd(101)));
+}
+
+#[test]
+fn test_currency_codes() {
+    assert_eq!("EUR".parse::<Currency>().unwrap().to_string(), "EUR");
+    assert!("eur".parse::<Currency>().is_err());
+    assert!("EURO".parse::<Currency>().is_err());
+    assert_eq!(Money::new(-1250, eur()).to_string(), "-12.50 EUR");
+}
+
+#[test]
+fn test_fx_conversion() {
+    let fx = fx();
+    assert_eq!(fx.convert(Money::new(1000, eur()), Currency::USD), Ok(Money::usd(1250)));
+    assert_eq!(fx.convert(Money::usd(1000), eur()), Ok(Money::new(800, eur())));
+    assert_eq!(
+        fx.convert(Money::usd(1000), "GBP".parse().unwrap()),
+        Err(MoneyError::UnknownRate("GBP".parse().unwrap()))
+    );
+    assert!(fx.supports(eur()));
+    assert_eq!(fx.currencies(), vec![eur(), Currency::USD]);
+}
+
+#[test]
+fn test_missing_fx_file_only_supports_base() {
+    let fx = FxTable::load("does-not-exist.json").unwrap();
+    assert_eq!(fx.currencies(), vec![Currency::USD]);
+}
+
+#[test]
+fn test_listings_in_different_currencies_are_compared_converted() {
+    let vehicles = vec![Vehicle { length: 10, quantity: 1 }];
+    // 900 EUR is 1125 USD, so the USD listing is cheaper
+    let listings = vec![
+        Listing { length: 10, price: Money::new(900, eur()), ..listing("eur") },
+        Listing { length: 10, price: Money::usd(1000), ..listing("usd") },
+    ];
+    let options = SearchOptions {
+        fx: Arc::new(fx()),
+        ..Default::default()
+    };
+
+    let results = bin_packing::search_locations_with(vehicles.clone(), &listings, &options);
+    assert_eq!(results[0].listing_ids, vec!["usd"]);
+    assert_eq!(results[0].total(), Money::usd(1000));
+
+    let options = SearchOptions { currency: eur(), ..options };
+    let results = bin_packing::search_locations_with(vehicles, &listings, &options);
+    assert_eq!(results[0].total(), Money::new(800, eur()));
+    let json = serde_json::to_value(&results[0]).unwrap();
+    assert_eq!(json["currency"], "EUR");
+}
+
+#[test]
+fn test_overflowing_combination_is_skipped() {
+    let vehicles = vec![10, 10];
+    let listings = vec![
+        Listing { length: 10, price: Money::usd(i64::MAX - 10), ..listing("a") },
+        Listing { length: 10, price: Money::usd(100), ..listing("b") },
+        Listing { length: 10, price: Money::usd(100), ..listing("c") },
+    ];
+
+    let result = bin_packing::find_cheapest_combination(&vehicles, &listings).unwrap();
+    assert_eq!(result.total, Money::usd(200));
+
+    // Without a rate the EUR listing can't be priced, so it can't be rented
+    let listings = vec![Listing { length: 10, price: Money::new(100, eur()), ..listing("a") }];
+    assert!(bin_packing::find_cheapest_combination(&vehicles[..1], &listings).is_none());
+}
+
+#[test]
+fn test_listings_without_a_rate() {
+    let listings = vec![
+        Listing { length: 10, price: Money::usd(100), ..listing("usd") },
+        Listing { length: 10, price: Money::new(100, eur()), ..listing("eur") },
+    ];
+    assert!(fx().check_listings(&listings).is_ok());
+    let message = FxTable::default().check_listings(&listings).unwrap_err().to_string();
+    assert!(message.contains("EUR"), "{message}");
+
+    // A catalog edit can still add one, searches count what they left out
+    let options = SearchOptions::default();
+    let results = bin_packing::search_locations_with(vec![Vehicle { length: 10, quantity: 1 }], &listings, &options);
+    assert_eq!(results[0].listing_ids, vec!["usd"]);
+    assert_eq!(options.stats.unpriced(), 1);
+}
@@ -0,0 +1,155 @@
+//! The committed `openapi.json` has to match what the code generates
+
+use crate::{openapi, openapi_json};
+use actix_web::App;
+use neighbor::bookings::BookingRequest;
+use neighbor::discounts::Discount;
+use neighbor::model::{Limits, Listing, SearchFilters, SearchQuery, SearchRequest, Vehicle};
+use neighbor::pricing::Pricing;
+use neighbor::quote::QuoteRequest;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use validator::{Validate, ValidateArgs};
+
+fn committed() -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string("openapi.json").unwrap()).unwrap()
+}
+
+#[test]
+fn test_committed_spec_is_current() {
+    let generated = serde_json::to_value(openapi()).unwrap();
+    assert!(
+        generated == committed(),
+        "openapi.json is out of date, regenerate it with `cargo run -- openapi > openapi.json`"
+    );
+}
+
+#[test]
+fn test_spec_has_validation_constraints() {
+    let spec = serde_json::to_value(openapi()).unwrap();
+    let schemas = &spec["components"]["schemas"];
+
+    assert_eq!(schemas["Vehicle"]["properties"]["quantity"]["minimum"], 1);
+    assert_eq!(schemas["SearchRequest"]["minItems"], 1);
+    assert_eq!(schemas["SearchRequest"]["items"]["$ref"], "#/components/schemas/Vehicle");
+    assert_eq!(schemas["Listing"]["properties"]["width"]["multipleOf"], 10);
+    assert_eq!(schemas["SearchQuery"]["properties"]["limit"]["minimum"], 1);
+
+    let search = &spec["paths"]["/search"]["post"];
+    assert_eq!(search["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/SearchRequest");
+    assert_eq!(search["responses"]["200"]["content"]["application/json"]["schema"]["items"]["$ref"], "#/components/schemas/PossibleSpace");
+}
+
+/// Parses and validates a request body
+type Accepts = fn(Value) -> bool;
+
+fn accepts<T: DeserializeOwned + Validate>(value: Value) -> bool {
+    serde_json::from_value::<T>(value).is_ok_and(|parsed| parsed.validate().is_ok())
+}
+
+fn accepts_with_limits<T>(value: Value) -> bool
+where
+    T: DeserializeOwned + for<'a> ValidateArgs<'a, Args = &'a Limits>,
+{
+    serde_json::from_value::<T>(value).is_ok_and(|parsed| parsed.validate_with_args(&Limits::default()).is_ok())
+}
+
+/// The bounds in a schema, by property. `None` is the schema itself.
+fn bounds(schema: &Value) -> Vec<(Option<&str>, &serde_json::Map<String, Value>)> {
+    let mut found: Vec<_> = schema.as_object().into_iter().map(|bounds| (None, bounds)).collect();
+    let parts = schema["allOf"].as_array().into_iter().flatten().chain([schema]);
+    for part in parts {
+        for (field, property) in part["properties"].as_object().into_iter().flatten() {
+            found.push((Some(field.as_str()), property.as_object().unwrap()));
+        }
+    }
+    found
+}
+
+/// Feed every bound the spec promises to the validator, right at the bound and just past it
+#[test]
+fn test_schema_bounds_match_validation() {
+    let vehicle = json!({ "length": 10, "quantity": 1 });
+    let cases: Vec<(&str, Value, Accepts)> = vec![
+        (
+            "Listing",
+            json!({ "id": "a", "location_id": "loc1", "length": 20, "width": 10, "price_in_cents": 1000 }),
+            accepts::<Listing>,
+        ),
+        ("Vehicle", vehicle.clone(), accepts::<Vehicle>),
+        ("Pricing", json!({ "daily_in_cents": 100, "weekly_in_cents": 500, "monthly_in_cents": 1000 }), accepts::<Pricing>),
+        ("Discount", json!({ "min_listings": 2, "type": "percentage", "percent": 10 }), accepts::<Discount>),
+        ("SearchRequest", json!([vehicle]), accepts_with_limits::<SearchRequest>),
+        ("SearchQuery", json!({ "vehicles": [vehicle], "limit": 10 }), accepts_with_limits::<SearchQuery>),
+        ("SearchFilters", json!({ "max_listings": 2, "max_total_in_cents": 100 }), accepts::<SearchFilters>),
+        (
+            "BookingRequest",
+            json!({
+                "location_id": "loc1",
+                "listing_ids": ["a"],
+                "vehicles": [vehicle],
+                "start_date": "2030-01-01",
+                "end_date": "2030-02-01"
+            }),
+            accepts_with_limits::<BookingRequest>,
+        ),
+        ("QuoteRequest", json!({ "location_id": "loc1", "listing_ids": ["a"] }), accepts::<QuoteRequest>),
+    ];
+    let spec = serde_json::to_value(openapi()).unwrap();
+    let schemas = spec["components"]["schemas"].as_object().unwrap();
+
+    for (name, base, accepts) in &cases {
+        assert!(accepts(base.clone()), "{name} example is not valid");
+        let with = |field: Option<&str>, value: Value| {
+            let mut changed = base.clone();
+            match field {
+                Some(field) => changed[field] = value,
+                None => changed = value,
+            }
+            accepts(changed)
+        };
+        let field_of = |field: Option<&str>| field.map_or(base.clone(), |field| base[field].clone());
+
+        for (field, bound) in bounds(&schemas[*name]) {
+            let at = format!("{name}.{}", field.unwrap_or(""));
+            if let Some(minimum) = bound.get("minimum").and_then(Value::as_i64) {
+                assert!(with(field, json!(minimum)), "{at} refuses its minimum {minimum}");
+                assert!(!with(field, json!(minimum - 1)), "{at} accepts less than its minimum {minimum}");
+                if let Some(step) = bound.get("multipleOf").and_then(Value::as_i64) {
+                    assert!(!with(field, json!(minimum + step / 2)), "{at} accepts a value off its multiple of {step}");
+                }
+            }
+            if let Some(length) = bound.get("minLength").and_then(Value::as_u64) {
+                let length = length as usize;
+                assert!(with(field, json!("x".repeat(length))), "{at} refuses its minimum length {length}");
+                assert!(!with(field, json!("x".repeat(length - 1))), "{at} accepts less than its minimum length");
+            }
+            if let Some(items) = bound.get("minItems").and_then(Value::as_u64) {
+                let items = items as usize;
+                let full = field_of(field).as_array().unwrap().clone();
+                assert!(with(field, json!(full[..items])), "{at} refuses its minimum of {items} items");
+                assert!(!with(field, json!(full[..items - 1])), "{at} accepts less than {items} items");
+            }
+        }
+    }
+
+    // New schemas with bounds have to be added above. Unsigned integers
+    // get `minimum: 0` from their type, there is nothing to check.
+    for (name, schema) in schemas {
+        let bounded = bounds(schema).iter().any(|(_, bound)| {
+            ["minimum", "multipleOf", "minLength", "minItems"]
+                .iter()
+                .any(|key| bound.get(*key).is_some_and(|value| value != 0))
+        });
+        let checked = cases.iter().any(|(case, _, _)| case == name);
+        assert!(!bounded || checked, "{name} has bounds that aren't checked");
+    }
+}
+
+#[actix_web::test]
+async fn test_spec_is_served() {
+    let app = actix_web::test::init_service(App::new().service(openapi_json)).await;
+    let req = actix_web::test::TestRequest::get().uri("/openapi.json").to_request();
+    let served: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(served, committed());
+}
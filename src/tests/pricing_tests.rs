@@ -0,0 +1,110 @@
+//! Quoting daily, weekly and monthly rates for a period
+
+use chrono::NaiveDate;
+use neighbor::availability::{DateRange, MAX_DAYS};
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::model::Listing;
+use neighbor::money::{Money, MoneyError};
+use neighbor::pricing::{Pricing, Proration};
+
+fn days(n: i64) -> DateRange {
+    let start = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    DateRange::new(start, start + chrono::Duration::days(n))
+}
+
+fn rates(daily: Option<i64>, weekly: Option<i64>, monthly: Option<i64>) -> Pricing {
+    Pricing {
+        daily_in_cents: daily,
+        weekly_in_cents: weekly,
+        monthly_in_cents: monthly,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_monthly_only_is_prorated_by_day() {
+    let pricing = Pricing::monthly(3000);
+    assert_eq!(pricing.quote(30), Ok(3000));
+    assert_eq!(pricing.quote(10), Ok(1000));
+    assert_eq!(pricing.quote(45), Ok(4500));
+}
+
+#[test]
+fn test_weekly_rate_beats_daily_rate() {
+    let pricing = rates(Some(200), Some(1000), None);
+    assert_eq!(pricing.quote(3), Ok(600));
+    // 6 days at the daily rate would be 1200
+    assert_eq!(pricing.quote(6), Ok(1000));
+    assert_eq!(pricing.quote(8), Ok(1200));
+}
+
+#[test]
+fn test_mixes_months_weeks_and_days() {
+    let pricing = rates(Some(100), Some(500), Some(1500));
+    // 1 month + 1 week + 2 days
+    assert_eq!(pricing.quote(39), Ok(1500 + 500 + 200));
+}
+
+#[test]
+fn test_whole_units_rounds_up() {
+    let pricing = Pricing {
+        proration: Proration::WholeUnits,
+        ..rates(None, Some(1000), None)
+    };
+    assert_eq!(pricing.quote(1), Ok(1000));
+    assert_eq!(pricing.quote(7), Ok(1000));
+    assert_eq!(pricing.quote(8), Ok(2000));
+}
+
+#[test]
+fn test_periods_past_the_cap_are_not_quoted() {
+    let pricing = rates(Some(100), None, None);
+    assert_eq!(pricing.quote(MAX_DAYS), Ok(100 * MAX_DAYS));
+    assert_eq!(pricing.quote(MAX_DAYS + 1), Err(MoneyError::Overflow));
+}
+
+#[test]
+fn test_listing_without_rates_uses_price_in_cents() {
+    let listing = Listing {
+        price: Money::usd(3000),
+        pricing: Some(Pricing::default()),
+        ..Default::default()
+    };
+    assert_eq!(listing.price_for_period(&days(15)), Ok(Money::usd(1500)));
+}
+
+#[test]
+fn test_cheapest_combination_uses_period_quote() {
+    let listings = vec![
+        Listing {
+            id: "daily".to_string(),
+            location_id: "loc1".to_string(),
+            length: 10,
+            width: 10,
+            price: Money::usd(3000),
+            pricing: Some(rates(Some(100), None, None)),
+            ..Default::default()
+        },
+        Listing {
+            id: "weekly".to_string(),
+            location_id: "loc1".to_string(),
+            length: 10,
+            width: 10,
+            price: Money::usd(4000),
+            pricing: Some(rates(None, Some(500), None)),
+            ..Default::default()
+        },
+    ];
+
+    // Undated searches compare the listed price
+    let result = bin_packing::find_cheapest_combination(&[10], &listings).unwrap();
+    assert_eq!(result.listing_ids, vec!["daily"]);
+
+    let options = SearchOptions {
+        period: Some(days(7)),
+        ..Default::default()
+    };
+    let result = bin_packing::find_cheapest_combination_with(&[10], &listings, &options).unwrap();
+    assert_eq!(result.listing_ids, vec!["weekly"]);
+    assert_eq!(result.total, Money::usd(500));
+}
@@ -0,0 +1,123 @@
+//! Itemized quotes with discounts, service fees and tax
+
+use super::listing;
+use neighbor::bin_packing::{self, SearchOptions};
+use neighbor::discounts::{Discount, DiscountKind};
+use neighbor::fx::FxTable;
+use neighbor::locations::{Location, Locations};
+use neighbor::model::{Listing, Vehicle};
+use neighbor::money::{Currency, Money};
+use neighbor::quote::{self, Charges, LineItemKind, Quote, QuoteError, ServiceFee};
+use std::sync::Arc;
+
+fn taxed_location() -> Location {
+    Location {
+        discounts: vec![Discount {
+            name: Some("Bundle".to_string()),
+            min_listings: 2,
+            kind: DiscountKind::Percentage { percent: 10 },
+        }],
+        service_fee: Some(ServiceFee {
+            percent_bps: 500,
+            flat_in_cents: 100,
+        }),
+        tax_rate_bps: 1000,
+    }
+}
+
+#[test]
+fn test_charges_apply_in_order() {
+    let charges = Charges::new(&taxed_location(), Money::usd(2000), 2, &FxTable::default()).unwrap();
+    assert_eq!(charges.discount(), Money::usd(200));
+    // 5% of the discounted 1800 plus the flat 100
+    assert_eq!(charges.service_fee, Money::usd(190));
+    // 10% of 1800 + 190
+    assert_eq!(charges.tax, Money::usd(199));
+    assert_eq!(charges.total(), Ok(Money::usd(1800 + 190 + 199)));
+}
+
+#[test]
+fn test_quote_line_items() {
+    let listings = vec![
+        Listing { price: Money::usd(1200), ..listing("a") },
+        Listing { price: Money::usd(800), ..listing("b") },
+    ];
+    let quote = Quote::new("loc1", &listings, None, &taxed_location(), &FxTable::default(), Currency::USD).unwrap();
+
+    let kinds: Vec<LineItemKind> = quote.line_items.iter().map(|item| item.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            LineItemKind::Listing,
+            LineItemKind::Listing,
+            LineItemKind::Discount,
+            LineItemKind::ServiceFee,
+            LineItemKind::Tax,
+        ]
+    );
+    assert_eq!(quote.line_items[2].amount_in_cents, -200);
+    assert_eq!(quote.line_items[2].description, "Bundle");
+    assert_eq!(quote.total_in_cents, quote.line_items.iter().map(|item| item.amount_in_cents).sum::<i64>());
+}
+
+#[test]
+fn test_quote_without_settings_is_the_rent() {
+    let listings = vec![Listing { price: Money::usd(1200), ..listing("a") }];
+    let quote = Quote::new("loc1", &listings, None, &Location::default(), &FxTable::default(), Currency::USD).unwrap();
+    assert_eq!(quote.line_items.len(), 1);
+    assert_eq!(quote.total_in_cents, 1200);
+}
+
+#[test]
+fn test_select_listings_errors() {
+    let catalog = vec![
+        Listing { price: Money::usd(100), ..listing("a") },
+        Listing { location_id: "loc2".to_string(), price: Money::usd(100), ..listing("b") },
+    ];
+    let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+        quote::select_listings("loc1", &ids(&["a", "a"]), None, &catalog).unwrap_err(),
+        QuoteError::DuplicateListing("a".to_string())
+    );
+    assert_eq!(
+        quote::select_listings("loc1", &ids(&["b"]), None, &catalog).unwrap_err(),
+        QuoteError::WrongLocation("b".to_string())
+    );
+    assert_eq!(
+        quote::select_listings("loc1", &ids(&["c"]), None, &catalog).unwrap_err(),
+        QuoteError::ListingNotFound("c".to_string())
+    );
+    assert!(quote::select_listings("loc1", &ids(&["a"]), None, &catalog).is_ok());
+}
+
+#[test]
+fn test_taxed_ranking_matches_what_renters_pay() {
+    let vehicles = vec![Vehicle { length: 20, quantity: 1 }];
+    let listings = vec![
+        Listing { location_id: "taxed".to_string(), price: Money::usd(1000), ..listing("a") },
+        Listing { location_id: "untaxed".to_string(), price: Money::usd(1050), ..listing("b") },
+    ];
+
+    let mut locations = Locations::default();
+    locations.insert(
+        "taxed",
+        Location {
+            tax_rate_bps: 1000,
+            ..Default::default()
+        },
+    );
+    let mut options = SearchOptions {
+        locations: Arc::new(locations),
+        ..Default::default()
+    };
+
+    let results = bin_packing::search_locations_with(vehicles.clone(), &listings, &options);
+    assert_eq!(results[0].location_id, "taxed");
+    assert_eq!(results[0].total_price_in_cents, 1000);
+
+    options.include_fees_and_taxes = true;
+    let results = bin_packing::search_locations_with(vehicles, &listings, &options);
+    assert_eq!(results[0].location_id, "untaxed");
+    assert_eq!(results[1].total_price_in_cents, 1100);
+}
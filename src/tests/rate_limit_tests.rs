@@ -0,0 +1,267 @@
+//! Token buckets per client, what searches cost, and the 429s
+
+use crate::{errors, healthz, rate_limit, search, search_batch, search_v1};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App};
+use neighbor::bookings::BookingStore;
+use neighbor::compute::ComputePool;
+use neighbor::config::{Config, Overrides};
+use neighbor::fx::FxTable;
+use neighbor::locations::Locations;
+use neighbor::model::{Limits, SearchParams, Vehicle};
+use neighbor::rate_limit::{self as limits, Network, RateLimiter};
+use neighbor::store::{self, ListingStore, MemoryStore};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn params(query: serde_json::Value) -> SearchParams {
+    serde_json::from_value(query).unwrap()
+}
+
+#[test]
+fn test_search_cost() {
+    let fleet = [Vehicle { length: 10, quantity: 2 }, Vehicle { length: 20, quantity: 1 }];
+    assert_eq!(limits::search_cost(&fleet, &SearchParams::default(), 5), 3);
+    assert_eq!(limits::search_cost(&fleet, &params(json!({ "currency": "EUR" })), 5), 6);
+    let everything = params(json!({
+        "start_date": "2025-01-01",
+        "end_date": "2025-01-31",
+        "include_fees_and_taxes": true,
+        "currency": "EUR",
+    }));
+    assert_eq!(limits::search_cost(&fleet, &everything, 5), 12);
+
+    // Searches validation turns away still cost something, but no more than the largest allowed
+    assert_eq!(limits::search_cost(&[], &SearchParams::default(), 5), 1);
+    assert_eq!(limits::search_cost(&[Vehicle { length: 10, quantity: 50 }], &SearchParams::default(), 5), 5);
+}
+
+#[test]
+fn test_bucket_refills() {
+    let limiter = RateLimiter::new(60, BTreeMap::new());
+    let start = Instant::now();
+
+    let spent = limiter.check("key:a", "/listings", 60, start).unwrap();
+    assert!(spent.allowed);
+    assert_eq!((spent.remaining, spent.reset, spent.retry_after), (0, 60, None));
+
+    let refused = limiter.check("key:a", "/listings", 1, start).unwrap();
+    assert!(!refused.allowed);
+    assert_eq!(refused.retry_after, Some(1));
+
+    let later = limiter.check("key:a", "/listings", 30, start + Duration::from_secs(30)).unwrap();
+    assert!(later.allowed);
+    assert_eq!(later.remaining, 0);
+
+    // A request that costs more than the whole bucket never goes through, and isn't charged
+    let over = limiter.check("key:a", "/listings", 61, start + Duration::from_secs(120)).unwrap();
+    assert!(!over.allowed && over.over_limit());
+    assert_eq!((over.cost, over.remaining, over.retry_after), (61, 60, None));
+    let full = limiter.check("key:a", "/listings", 60, start + Duration::from_secs(120)).unwrap();
+    assert!(full.allowed && !full.over_limit());
+}
+
+#[test]
+fn test_refilled_buckets_are_evicted() {
+    let limiter = RateLimiter::new(60, BTreeMap::new());
+    let start = Instant::now();
+    limiter.check("ip:10.0.0.1", "/listings", 1, start);
+    limiter.check("ip:10.0.0.2", "/listings", 1, start + Duration::from_secs(30));
+    assert_eq!(limiter.buckets(), 2);
+
+    limiter.evict(start + Duration::from_secs(59));
+    assert_eq!(limiter.buckets(), 2);
+    limiter.evict(start + Duration::from_secs(60));
+    assert_eq!(limiter.buckets(), 1);
+    limiter.evict(start + Duration::from_secs(90));
+    assert_eq!(limiter.buckets(), 0);
+}
+
+#[test]
+fn test_routes_and_clients_have_their_own_buckets() {
+    let routes = BTreeMap::from([("/search".to_string(), 2), ("/metrics".to_string(), 0)]);
+    let limiter = RateLimiter::new(10, routes);
+    let now = Instant::now();
+
+    assert!(limiter.check("key:a", "/search", 2, now).unwrap().allowed);
+    assert!(!limiter.check("key:a", "/search", 1, now).unwrap().allowed);
+    assert!(limiter.check("key:b", "/search", 1, now).unwrap().allowed);
+    let other = limiter.check("key:a", "/listings", 1, now).unwrap();
+    assert!(other.allowed);
+    assert_eq!((other.limit, other.remaining), (10, 9));
+    assert_eq!(limiter.check("key:a", "/metrics", 1, now), None);
+    assert_eq!(RateLimiter::new(0, BTreeMap::new()).check("key:a", "/listings", 1, now), None);
+}
+
+#[test]
+fn test_client() {
+    let limiter = RateLimiter::new(10, BTreeMap::new()).with_api_keys(["abc".to_string()]);
+    let ip = "10.0.0.1".parse().ok();
+    assert_eq!(limiter.client(Some("abc"), ip), "key:abc");
+    // Keys nobody configured are no way around the IP's budget
+    assert_eq!(limiter.client(Some("made-up"), ip), "ip:10.0.0.1");
+    assert_eq!(limiter.client(Some(""), ip), "ip:10.0.0.1");
+    assert_eq!(limiter.client(None, None), "unknown");
+}
+
+#[test]
+fn test_trusted_networks() {
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    let network: Network = "10.0.0.0/8".parse().unwrap();
+    assert!(network.contains(ip("10.200.0.1")));
+    assert!(network.contains(ip("::ffff:10.0.0.1")));
+    assert!(!network.contains(ip("11.0.0.1")));
+    assert!(!network.contains(ip("fdaa::1")));
+    let network: Network = "fdaa::/16".parse().unwrap();
+    assert!(network.contains(ip("fdaa:0:1::3")));
+    assert!(!network.contains(ip("fdab::1")));
+    let single: Network = "192.0.2.7".parse().unwrap();
+    assert!(single.contains(ip("192.0.2.7")) && !single.contains(ip("192.0.2.8")));
+    assert!("0.0.0.0/0".parse::<Network>().unwrap().contains(ip("203.0.113.1")));
+
+    assert_eq!(single.to_string(), "192.0.2.7");
+    assert_eq!("fdaa::/16".parse::<Network>().unwrap().to_string(), "fdaa::/16");
+    assert!("10.0.0.0/33".parse::<Network>().is_err());
+    assert!("proxy".parse::<Network>().is_err());
+}
+
+#[test]
+fn test_client_ip_only_from_trusted_proxies() {
+    let limiter = RateLimiter::new(10, BTreeMap::new()).with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+    let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+    // Anyone else can send the headers, they are ignored
+    assert_eq!(limiter.client_ip(ip("203.0.113.1"), Some("198.51.100.1"), Some("198.51.100.2")), ip("203.0.113.1"));
+    // The proxy's word is taken, Fly-Client-IP first
+    assert_eq!(limiter.client_ip(ip("10.0.0.5"), Some("198.51.100.1"), Some("198.51.100.2")), ip("198.51.100.1"));
+    // X-Forwarded-For is read from the right, past the trusted hops only
+    assert_eq!(limiter.client_ip(ip("10.0.0.5"), None, Some("192.0.2.66, 198.51.100.2, 10.0.0.9")), ip("198.51.100.2"));
+    assert_eq!(limiter.client_ip(ip("10.0.0.5"), None, Some("junk, 10.0.0.9")), ip("10.0.0.9"));
+    assert_eq!(limiter.client_ip(ip("10.0.0.5"), Some("junk"), None), ip("10.0.0.5"));
+    assert_eq!(limiter.client_ip(ip("::ffff:10.0.0.5"), Some("198.51.100.1"), None), ip("198.51.100.1"));
+    assert_eq!(limiter.client_ip(None, Some("198.51.100.1"), None), None);
+}
+
+#[test]
+fn test_fly_client_ip_is_trusted_on_fly() {
+    let fly: toml::Table = toml::from_str(&std::fs::read_to_string("fly.toml").unwrap()).unwrap();
+    let vars = fly["env"].as_table().unwrap().clone();
+    let mut config = Config::default();
+    config.apply(Overrides::from_env(|name| vars.get(name).and_then(|v| v.as_str()).map(str::to_string)).unwrap());
+    config.check().unwrap();
+
+    let limiter = RateLimiter::new(config.rate_limit, config.route_limits).with_trusted_proxies(config.trusted_proxies);
+    let proxy: Option<IpAddr> = "fdaa:0:1:a7b:1::2".parse().ok();
+    assert_eq!(limiter.client_ip(proxy, Some("203.0.113.7"), None), "203.0.113.7".parse().ok());
+}
+
+#[actix_web::test]
+async fn test_searches_spend_the_budget() {
+    let store: Arc<dyn ListingStore> = Arc::new(MemoryStore::new(store::read_listings(store::LISTINGS_FILE).unwrap()));
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(BookingStore::default()))
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(Locations::default()))
+            .app_data(web::Data::new(FxTable::default()))
+            .app_data(web::Data::new(Limits::default()))
+            .app_data(web::Data::new(ComputePool::default()))
+            .app_data(web::Data::new(
+                RateLimiter::new(10, BTreeMap::new())
+                    .with_api_keys(["other".to_string()])
+                    .with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]),
+            ))
+            .wrap(from_fn(rate_limit))
+            .configure(errors)
+            .service(search)
+            .service(search_batch)
+            .service(search_v1)
+            .service(healthz),
+    )
+    .await;
+    let remaining = |resp: &actix_web::dev::ServiceResponse| {
+        resp.headers().get("ratelimit-remaining").unwrap().to_str().unwrap().parse::<u32>().unwrap()
+    };
+
+    let fleet = json!([{ "length": 10, "quantity": 2 }]);
+    let req = actix_web::test::TestRequest::post().uri("/search").set_json(&fleet).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("ratelimit-limit").unwrap(), "10");
+    assert_eq!(resp.headers().get("ratelimit-policy").unwrap(), "10;w=60");
+    assert_eq!(remaining(&resp), 8);
+    // The body was put back for the handler
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert!(!body.as_array().unwrap().is_empty());
+
+    let query = json!({ "vehicles": fleet, "options": { "include_fees_and_taxes": true } });
+    let req = actix_web::test::TestRequest::post().uri("/v1/search").set_json(query).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(remaining(&resp), 4);
+
+    let one = json!([{ "length": 10, "quantity": 1 }]);
+    let batch = json!([{ "id": "a", "vehicles": one }, { "id": "b", "vehicles": one }]);
+    let req = actix_web::test::TestRequest::post().uri("/search/batch").set_json(batch).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(remaining(&resp), 2);
+
+    let three = json!([{ "length": 10, "quantity": 3 }]);
+    let req = actix_web::test::TestRequest::post().uri("/search").set_json(&three).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 429);
+    let retry_after: u64 = resp.headers().get("retry-after").unwrap().to_str().unwrap().parse().unwrap();
+    assert!((1..=6).contains(&retry_after), "{retry_after}");
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "rate_limited");
+
+    // A configured API key has its own budget, any other key shares the address's
+    let req = actix_web::test::TestRequest::post()
+        .uri("/search")
+        .insert_header((limits::API_KEY, "other"))
+        .set_json(&three)
+        .to_request();
+    assert_eq!(actix_web::test::call_service(&app, req).await.status(), 200);
+    let req = actix_web::test::TestRequest::post()
+        .uri("/search")
+        .insert_header((limits::API_KEY, "made-up"))
+        .set_json(&three)
+        .to_request();
+    assert_eq!(actix_web::test::call_service(&app, req).await.status(), 429);
+
+    // Clients behind a trusted proxy each have their own budget
+    for client in ["198.51.100.1", "198.51.100.2"] {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/search")
+            .peer_addr("10.0.0.5:443".parse().unwrap())
+            .insert_header((limits::FLY_CLIENT_IP, client))
+            .set_json(&three)
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(remaining(&resp), 7);
+    }
+
+    // More than a whole budget is never allowed, waiting won't help
+    let options = json!({ "include_fees_and_taxes": true, "currency": "USD" });
+    let query = json!({ "vehicles": [{ "length": 10, "quantity": 5 }], "options": options });
+    let req = actix_web::test::TestRequest::post()
+        .uri("/v1/search")
+        .insert_header((limits::API_KEY, "other"))
+        .set_json(query)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    assert!(resp.headers().get("retry-after").is_none());
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["code"], "cost_over_limit");
+
+    let req = actix_web::test::TestRequest::get().uri("/healthz").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("ratelimit-limit").is_none());
+}
@@ -0,0 +1,92 @@
+//! Swapping in a new catalog without a restart
+
+use super::listing;
+use neighbor::model::Listing;
+use neighbor::reload;
+use neighbor::store::{CatalogError, JsonFileStore, ListingStore, MemoryStore};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn write(path: &Path, listings: &[Listing]) {
+    std::fs::write(path, serde_json::to_string(listings).unwrap()).unwrap();
+}
+
+#[test]
+fn test_replace_all_swaps_valid_listings() {
+    let store = MemoryStore::new(vec![Listing { length: 10, ..listing("a") }]);
+    let before = store.snapshot();
+
+    let reload = store
+        .replace_all(vec![Listing { length: 10, ..listing("a") }, Listing { length: 20, ..listing("b") }])
+        .unwrap();
+    assert!(reload.changed);
+    assert_eq!(reload.listings, 2);
+    assert_eq!(reload.version, before.version + 1);
+
+    // A search that started before the reload keeps its listings
+    assert_eq!(before.listings.len(), 1);
+
+    let again = store
+        .replace_all(vec![Listing { length: 10, ..listing("a") }, Listing { length: 20, ..listing("b") }])
+        .unwrap();
+    assert!(!again.changed);
+    assert_eq!(again.version, reload.version);
+}
+
+#[test]
+fn test_invalid_reload_keeps_the_snapshot() {
+    let store = MemoryStore::new(vec![Listing { length: 10, ..listing("a") }]);
+    let version = store.snapshot().version;
+
+    let bad_length = store.replace_all(vec![Listing { length: 15, ..listing("a") }]);
+    assert!(matches!(bad_length, Err(CatalogError::Rejected(_))));
+
+    let duplicate = store.replace_all(vec![
+        Listing { length: 10, ..listing("b") },
+        Listing { length: 20, ..listing("b") },
+    ]);
+    assert!(matches!(duplicate, Err(CatalogError::Rejected(_))));
+
+    assert_eq!(store.snapshot().version, version);
+    assert_eq!(store.listings()[0].length, 10);
+}
+
+#[test]
+fn test_json_file_store_reloads_from_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("listings.json");
+    write(&path, &[Listing { length: 10, ..listing("a") }]);
+    let store = JsonFileStore::open(&path).unwrap();
+
+    write(&path, &[Listing { length: 10, ..listing("a") }, Listing { length: 10, ..listing("b") }]);
+    assert!(store.reload().unwrap().changed);
+    assert_eq!(store.listings().len(), 2);
+
+    std::fs::write(&path, "[{ not json").unwrap();
+    assert!(matches!(store.reload(), Err(CatalogError::Rejected(_))));
+    assert_eq!(store.listings().len(), 2);
+    assert!(store.reload_error().unwrap().starts_with("Kept the current listings"));
+
+    write(&path, &[Listing { length: 10, ..listing("a") }]);
+    store.reload().unwrap();
+    assert_eq!(store.reload_error(), None);
+}
+
+#[test]
+fn test_watcher_reloads_when_the_file_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("listings.json");
+    write(&path, &[Listing { length: 10, ..listing("a") }]);
+    let store: Arc<dyn ListingStore> = Arc::new(JsonFileStore::open(&path).unwrap());
+    let _watcher = reload::watch(store.clone(), &path).unwrap();
+
+    write(&path, &[Listing { length: 10, ..listing("a") }, Listing { length: 10, ..listing("b") }]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while store.listings().len() != 2 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(store.listings().len(), 2);
+}
@@ -0,0 +1,136 @@
+//! Keeping listings and bookings in SQLite across restarts
+
+use super::listing;
+use chrono::NaiveDate;
+use neighbor::availability::DateRange;
+use neighbor::bookings::{BookingRequest, BookingStore};
+use neighbor::fx::FxTable;
+use neighbor::locations::Locations;
+use neighbor::model::{Listing, ListingPatch, Vehicle};
+use neighbor::pricing::Pricing;
+use neighbor::sqlite::{Database, SqliteStore};
+use neighbor::store::{self, ListingStore, LISTINGS_FILE};
+use neighbor::validation::LoadMode;
+use std::sync::Arc;
+
+#[test]
+fn test_migrations_run_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("neighbor.db");
+
+    let database = Database::open(&path).unwrap();
+    let version = database.schema_version().unwrap();
+    assert!(version > 0);
+    drop(database);
+
+    // Reopening an up to date database doesn't try to create the tables again
+    assert_eq!(Database::open(&path).unwrap().schema_version().unwrap(), version);
+}
+
+#[test]
+fn test_import_listings_json() {
+    let database = Database::open_in_memory().unwrap();
+    let report = database.import_listings(LISTINGS_FILE, LoadMode::Strict).unwrap();
+
+    let listings = store::read_listings(LISTINGS_FILE).unwrap();
+    assert_eq!(report.valid, listings.len());
+    assert_eq!(database.listings().unwrap().len(), listings.len());
+
+    // Importing again overwrites instead of duplicating
+    database.import_listings(LISTINGS_FILE, LoadMode::Strict).unwrap();
+    assert_eq!(database.listings().unwrap().len(), listings.len());
+
+    let location_id = &listings[0].location_id;
+    let expected = listings.iter().filter(|l| &l.location_id == location_id).count();
+    assert_eq!(database.listings_at(location_id).unwrap().len(), expected);
+}
+
+#[test]
+fn test_listing_edits_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("neighbor.db");
+
+    {
+        let store = SqliteStore::open(Arc::new(Database::open(&path).unwrap())).unwrap();
+        let windowed = Listing {
+            availability: vec![DateRange::new(
+                NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2025, 2, 1).unwrap(),
+            )],
+            pricing: Some(Pricing {
+                daily_in_cents: Some(100),
+                ..Default::default()
+            }),
+            ..listing("a")
+        };
+        store.insert(windowed).unwrap();
+        store.insert(Listing { location_id: "loc2".to_string(), ..listing("b") }).unwrap();
+        store.insert(Listing { location_id: "loc2".to_string(), ..listing("c") }).unwrap();
+        store
+            .update("b", ListingPatch { price_in_cents: Some(500), ..Default::default() })
+            .unwrap();
+        store.delete("c").unwrap();
+    }
+
+    let store = SqliteStore::open(Arc::new(Database::open(&path).unwrap())).unwrap();
+    let ids: Vec<String> = store.listings().iter().map(|l| l.id.clone()).collect();
+    assert_eq!(ids, vec!["a", "b"]);
+
+    let a = store.get("a").unwrap();
+    assert_eq!(a.availability.len(), 1);
+    assert_eq!(a.pricing.unwrap().daily_in_cents, Some(100));
+    assert_eq!(store.by_location("loc2")[0].price.amount_in_cents, 500);
+}
+
+#[test]
+fn test_edits_the_database_refuses_are_never_searched() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("neighbor.db");
+    let store = SqliteStore::open(Arc::new(Database::open(&path).unwrap())).unwrap();
+    store.insert(listing("a")).unwrap();
+    let version = store.snapshot().version;
+
+    rusqlite::Connection::open(&path).unwrap().execute("DROP TABLE listings", []).unwrap();
+    assert!(store.insert(listing("b")).is_err());
+    assert!(store.delete("a").is_err());
+
+    let snapshot = store.snapshot();
+    assert_eq!(snapshot.version, version);
+    let ids: Vec<String> = snapshot.listings.iter().map(|l| l.id.clone()).collect();
+    assert_eq!(ids, vec!["a"]);
+}
+
+#[test]
+fn test_bookings_survive_a_restart() {
+    let database = Arc::new(Database::open_in_memory().unwrap());
+    let catalog = store::MemoryStore::new(vec![listing("a"), listing("b")]);
+    let request = |listing_id: &str| BookingRequest {
+        location_id: "loc1".to_string(),
+        listing_ids: vec![listing_id.to_string()],
+        vehicles: vec![Vehicle { length: 20, quantity: 1 }],
+        period: DateRange::new(
+            NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2025, 1, 11).unwrap(),
+        ),
+        currency: None,
+    };
+
+    let bookings = BookingStore::persistent(database.clone(), Vec::new());
+    let kept = bookings
+        .create(request("a"), &catalog, &Locations::default(), &FxTable::default())
+        .unwrap();
+    let cancelled = bookings
+        .create(request("b"), &catalog, &Locations::default(), &FxTable::default())
+        .unwrap();
+    bookings.cancel(&cancelled.id).unwrap();
+
+    let restarted = BookingStore::persistent(database.clone(), database.bookings().unwrap());
+    let ids: Vec<String> = restarted.all().iter().map(|b| b.id.clone()).collect();
+    assert_eq!(ids, vec![kept.id.clone()]);
+    assert_eq!(restarted.get(&kept.id).unwrap().total_price_in_cents, kept.total_price_in_cents);
+
+    // The restored booking still blocks the listing
+    assert!(restarted
+        .create(request("a"), &catalog, &Locations::default(), &FxTable::default())
+        .is_err());
+}
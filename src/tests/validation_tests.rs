@@ -1,7 +1,7 @@
 //! Ensure validation is correct.
 
-use crate::model::{SearchRequest, Vehicle};
-use validator::Validate;
+use neighbor::model::{Limits, SearchRequest, Vehicle};
+use validator::{Validate, ValidateArgs};
 
 #[test]
 fn test_valid_vehicle() {
@@ -57,13 +57,13 @@ fn test_valid_search_request() {
             Vehicle { length: 20, quantity: 3 },
         ],
     };
-    assert!(request.validate().is_ok());
+    assert!(request.validate_with_args(&Limits::default()).is_ok());
 }
 
 #[test]
 fn test_search_request_empty_vehicles() {
     let request = SearchRequest { vehicles: vec![] };
-    assert!(request.validate().is_err());
+    assert!(request.validate_with_args(&Limits::default()).is_err());
 }
 
 #[test]
@@ -76,7 +76,7 @@ fn test_search_request_total_quantity_at_limit() {
     };
     let total: i32 = request.vehicles.iter().map(|v| v.quantity).sum();
     assert_eq!(total, 5);
-    assert!(request.validate().is_ok());
+    assert!(request.validate_with_args(&Limits::default()).is_ok());
 }
 
 #[test]
@@ -87,7 +87,20 @@ fn test_search_request_total_quantity_exceeds_limit() {
             Vehicle { length: 20, quantity: 3 },
         ],
     };
-    assert!(request.validate().is_err());
+    assert!(request.validate_with_args(&Limits::default()).is_err());
+
+    // The code predates max_vehicles and stays the same whatever the limit
+    let limits = Limits { max_vehicles: 4, ..Default::default() };
+    let errors = request.validate_with_args(&limits).unwrap_err();
+    assert_eq!(errors.field_errors()["vehicles"][0].code, "total_quantity_exceeds_5");
+}
+
+#[test]
+fn test_search_request_total_quantity_does_not_overflow() {
+    let request: SearchRequest =
+        serde_json::from_str(r#"[{"length":10,"quantity":2147483647},{"length":10,"quantity":1}]"#).unwrap();
+    let errors = request.validate_with_args(&Limits::default()).unwrap_err();
+    assert_eq!(errors.field_errors()["vehicles"][0].code, "total_quantity_exceeds_5");
 }
 
 #[test]
@@ -98,5 +111,14 @@ fn test_search_request_invalid_vehicle_quantity() {
             quantity: 0, 
         }],
     };
-    assert!(request.validate().is_err());
+    assert!(request.validate_with_args(&Limits::default()).is_err());
+}
+
+#[test]
+fn test_search_request_configured_limit() {
+    let request = SearchRequest {
+        vehicles: vec![Vehicle { length: 10, quantity: 8 }],
+    };
+    assert!(request.validate_with_args(&Limits::default()).is_err());
+    assert!(request.validate_with_args(&Limits { max_vehicles: 8, ..Default::default() }).is_ok());
 }
@@ -0,0 +1,249 @@
+//! # Listing File Validation
+//!
+//! Checks a listings file before it is used and reports every problem with the
+//! index of the listing and the field, instead of stopping at the first one.
+//!
+//! In `Strict` mode any problem rejects the whole file. In `Lenient` mode the
+//! listings with problems are skipped and the rest are loaded. A file that
+//! isn't a JSON array can't be loaded in either mode.
+
+use crate::errors;
+use crate::model::Listing;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+/// Searches try every combination of the listings at a location, so there
+/// can't be many of them
+pub const MAX_LISTINGS_PER_LOCATION: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadMode {
+    /// Refuse the file if any listing has a problem
+    #[default]
+    Strict,
+    /// Skip the listings with problems
+    Lenient,
+}
+
+impl FromStr for LoadMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            other => Err(format!("Unknown load mode {other}, expected strict or lenient")),
+        }
+    }
+}
+
+/// Something wrong with one listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    /// Position in the file, starting at 0
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "listing {}", self.index)?;
+        if let Some(id) = &self.id {
+            write!(f, " ({id})")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, " {field}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoadReport {
+    pub mode: LoadMode,
+    /// Listings in the file
+    pub total: usize,
+    /// Listings without problems, the ones lenient mode loads
+    pub valid: usize,
+    pub problems: Vec<Problem>,
+    /// The records that were left out, as they were in the file
+    #[serde(skip)]
+    pub skipped: Vec<serde_json::Value>,
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} listings are valid", self.valid, self.total)?;
+        if !self.is_clean() {
+            write!(f, ", found {} problems:", self.problems.len())?;
+        }
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The file couldn't be loaded at all
+#[derive(Debug)]
+pub enum LoadError {
+    Unreadable(String),
+    /// Not a JSON array
+    Malformed(String),
+    /// Strict mode found problems
+    Rejected(LoadReport),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "Could not read the listings: {e}"),
+            Self::Malformed(e) => write!(f, "The listings are not a JSON array: {e}"),
+            Self::Rejected(report) => {
+                write!(f, "Refusing to load listings with problems ({:?} mode)\n{report}", report.mode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Read and check a listings file
+pub fn load_listings(path: impl AsRef<Path>, mode: LoadMode) -> Result<(Vec<Listing>, LoadReport), LoadError> {
+    let data = fs::read_to_string(path).map_err(|e| LoadError::Unreadable(e.to_string()))?;
+    let records: Vec<serde_json::Value> =
+        serde_json::from_str(&data).map_err(|e| LoadError::Malformed(e.to_string()))?;
+
+    // Parse each listing on its own so one bad record doesn't hide the others
+    let parsed = records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let id = record.get("id").and_then(|id| id.as_str()).map(str::to_string);
+            Listing::deserialize(record).map_err(|e| Problem {
+                index,
+                id,
+                field: missing_field(&e.to_string()),
+                message: e.to_string(),
+            })
+        })
+        .collect();
+    let (listings, mut report) = check_parsed(parsed, mode)?;
+    let mut skipped: Vec<usize> = report.problems.iter().map(|p| p.index).collect();
+    skipped.dedup();
+    report.skipped = skipped.into_iter().map(|index| records[index].clone()).collect();
+    Ok((listings, report))
+}
+
+/// Check listings that are already parsed, such as a catalog read from a database
+pub fn check_listings(listings: Vec<Listing>, mode: LoadMode) -> Result<(Vec<Listing>, LoadReport), LoadError> {
+    check_parsed(listings.into_iter().map(Ok).collect(), mode)
+}
+
+fn check_parsed(parsed: Vec<Result<Listing, Problem>>, mode: LoadMode) -> Result<(Vec<Listing>, LoadReport), LoadError> {
+    let total = parsed.len();
+    let mut problems = Vec::new();
+    let mut listings = Vec::new();
+    let mut first_index_of: HashMap<String, usize> = HashMap::new();
+    let mut at_location: HashMap<String, usize> = HashMap::new();
+
+    for (index, listing) in parsed.into_iter().enumerate() {
+        let listing = match listing {
+            Ok(listing) => listing,
+            Err(problem) => {
+                problems.push(problem);
+                continue;
+            }
+        };
+        let id = Some(listing.id.clone()).filter(|id| !id.is_empty());
+
+        let before = problems.len();
+        if let Err(errors) = listing.validate() {
+            for (field, error) in errors::flatten(&errors) {
+                problems.push(Problem {
+                    index,
+                    id: id.clone(),
+                    field,
+                    message: errors::message(error),
+                });
+            }
+        }
+        if let Some(first) = first_index_of.get(&listing.id) {
+            problems.push(Problem {
+                index,
+                id: id.clone(),
+                field: Some("id".to_string()),
+                message: format!("duplicate of listing {first}"),
+            });
+        }
+        let crowded = at_location.get(&listing.location_id).copied().unwrap_or(0) >= MAX_LISTINGS_PER_LOCATION;
+        if crowded {
+            problems.push(Problem {
+                index,
+                id: id.clone(),
+                field: Some("location_id".to_string()),
+                message: format!("more than {MAX_LISTINGS_PER_LOCATION} listings at {}", listing.location_id),
+            });
+        }
+        if problems.len() == before {
+            first_index_of.insert(listing.id.clone(), index);
+            *at_location.entry(listing.location_id.clone()).or_default() += 1;
+            listings.push(listing);
+        }
+    }
+
+    let report = LoadReport {
+        mode,
+        total,
+        valid: listings.len(),
+        problems,
+        skipped: Vec::new(),
+    };
+    if mode == LoadMode::Strict && !report.is_clean() {
+        return Err(LoadError::Rejected(report));
+    }
+    Ok((listings, report))
+}
+
+/// Refuse an edit that would put too many listings at one location
+pub fn check_locations(listings: &[Listing]) -> Result<(), ValidationErrors> {
+    let mut at_location: HashMap<&str, usize> = HashMap::new();
+    for listing in listings {
+        *at_location.entry(&listing.location_id).or_default() += 1;
+    }
+    match at_location.into_iter().find(|(_, count)| *count > MAX_LISTINGS_PER_LOCATION) {
+        Some((location_id, _)) => {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "location_id",
+                ValidationError::new("too_many_listings_at_location").with_message(
+                    format!("{location_id} already has {MAX_LISTINGS_PER_LOCATION} listings").into(),
+                ),
+            );
+            Err(errors)
+        }
+        None => Ok(()),
+    }
+}
+
+/// serde reports a missing field as "missing field `width`"
+fn missing_field(message: &str) -> Option<String> {
+    let rest = message.strip_prefix("missing field `")?;
+    Some(rest[..rest.find('`')?].to_string())
+}